@@ -4,11 +4,10 @@ use android_activity::{AndroidApp, MainEvent, PollEvent};
 use tinyaudio::prelude::*;
 
 fn play_sine_wave() -> OutputDevice {
-    let params = OutputDeviceParameters {
-        channels_count: 2,
-        sample_rate: 44100,
-        channel_sample_count: 4410,
-    };
+    let mut params = OutputDeviceParameters::default();
+    params.channels_count = 2;
+    params.sample_rate = 44100;
+    params.channel_sample_count = 4410;
 
     run_output_device(params, {
         let mut clock = 0f32;
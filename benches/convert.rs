@@ -0,0 +1,39 @@
+//! Measures the conversion of `f32` samples to 16-bit integers, as done by the backends that do not accept
+//! floating-point samples. The baseline rounds and clamps each sample the straightforward way.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+// The conversion module is internal, so it is compiled into the benchmark directly.
+use tinyaudio::DitherMode;
+
+#[allow(dead_code)]
+#[path = "../src/convert.rs"]
+mod convert;
+
+const SAMPLE_COUNT: usize = 8 * 1024;
+
+fn convert_to_i16(c: &mut Criterion) {
+    // Half of the samples are out of range, so the clamping is measured too.
+    let input = (0..SAMPLE_COUNT)
+        .map(|i| (i % 64) as f32 / 16.0 - 2.0)
+        .collect::<Vec<_>>();
+    let mut output = vec![0i16; SAMPLE_COUNT];
+
+    c.bench_function("convert to i16 by rounding and clamping", |b| {
+        b.iter(|| {
+            for (in_sample, out_sample) in black_box(&input).iter().zip(output.iter_mut()) {
+                *out_sample = (in_sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+            }
+            black_box(&output);
+        })
+    });
+
+    c.bench_function("convert to i16 with convert_samples_f32_to_i16", |b| {
+        b.iter(|| {
+            convert::convert_samples_f32_to_i16(black_box(&input), &mut output);
+            black_box(&output);
+        })
+    });
+}
+
+criterion_group!(benches, convert_to_i16);
+criterion_main!(benches);
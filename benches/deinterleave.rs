@@ -0,0 +1,38 @@
+//! Measures the deinterleaving of 8 channels, as done by the WebAudio backend for each buffer. The baseline
+//! collects the samples of each channel into a cleared `Vec`, as the backend used to do.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use tinyaudio::deinterleave_planar;
+
+const CHANNELS_COUNT: usize = 8;
+const CHANNEL_SAMPLE_COUNT: usize = 4410;
+
+fn deinterleave(c: &mut Criterion) {
+    let interleaved = (0..CHANNELS_COUNT * CHANNEL_SAMPLE_COUNT)
+        .map(|i| i as f32)
+        .collect::<Vec<_>>();
+
+    let mut temp_samples = Vec::new();
+    c.bench_function("deinterleave 8 channels by pushing", |b| {
+        b.iter(|| {
+            for channel_index in 0..CHANNELS_COUNT {
+                temp_samples.clear();
+                for frame in black_box(&interleaved).chunks_exact(CHANNELS_COUNT) {
+                    temp_samples.push(frame[channel_index]);
+                }
+                black_box(&temp_samples);
+            }
+        })
+    });
+
+    let mut planar = vec![0.0; interleaved.len()];
+    c.bench_function("deinterleave 8 channels into a planar buffer", |b| {
+        b.iter(|| {
+            deinterleave_planar(black_box(&interleaved), CHANNELS_COUNT, &mut planar);
+            black_box(&planar);
+        })
+    });
+}
+
+criterion_group!(benches, deinterleave);
+criterion_main!(benches);
@@ -4,16 +4,14 @@
 use tinyaudio::prelude::*;
 
 fn main() {
-    let _device = run_output_device(
-        OutputDeviceParameters {
-            channels_count: 2,
-            sample_rate: 44100,
-            channel_sample_count: 4410,
-        },
-        move |_| {
-            // Output silence
-        },
-    )
+    let mut params = OutputDeviceParameters::default();
+    params.channels_count = 2;
+    params.sample_rate = 44100;
+    params.channel_sample_count = 4410;
+
+    let _device = run_output_device(params, move |_| {
+        // Output silence
+    })
     .unwrap();
 
     std::thread::sleep(std::time::Duration::from_secs(1));
@@ -6,11 +6,10 @@ static mut DEVICE_HANDLE: Option<OutputDevice> = None;
 
 #[no_mangle]
 pub extern "C" fn create_audio_device() -> i32 {
-    let params = OutputDeviceParameters {
-        channels_count: 2,
-        sample_rate: 44100,
-        channel_sample_count: 4410,
-    };
+    let mut params = OutputDeviceParameters::default();
+    params.channels_count = 2;
+    params.sample_rate = 44100;
+    params.channel_sample_count = 4410;
 
     let device_result = run_output_device(params, {
         let mut clock = 0f32;
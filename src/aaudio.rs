@@ -2,63 +2,312 @@
 
 #![cfg(target_os = "android")]
 
-use crate::{AudioOutputDevice, BaseAudioOutputDevice, OutputDeviceParameters};
+use crate::{
+    block::BlockAdapter, AndroidPerformanceMode, AndroidSharingMode, AudioOutputDevice, Backend,
+    BaseAudioOutputDevice, CallbackResult, OutputDeviceParameters,
+};
+#[cfg(feature = "android-api-28")]
+use crate::{AndroidContentType, AndroidUsage};
 use ndk::audio::{
     AudioCallbackResult, AudioDirection, AudioError, AudioFormat, AudioPerformanceMode,
-    AudioStream, AudioStreamBuilder,
+    AudioSharingMode, AudioStream, AudioStreamBuilder, AudioStreamState,
+};
+#[cfg(feature = "android-api-28")]
+use ndk::audio::{AudioContentType, AudioUsage};
+use std::{
+    error::Error,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex, Weak,
+    },
+    time::Duration,
 };
-use std::error::Error;
+
+type DataCallback =
+    Arc<Mutex<dyn FnMut(&mut [f32], Option<f64>) -> CallbackResult + Send + 'static>>;
+
+/// Maximum amount of attempts to reopen the stream after it was disconnected.
+const MAX_RECONNECT_ATTEMPTS: u32 = 5;
+
+/// Amount of underruns during the first second of the stream after which the stream is reopened with a
+/// larger buffer, see `OutputDeviceParameters::adaptive_latency`.
+const UNDERRUNS_PER_FALLBACK: i32 = 3;
 
 pub struct AAudioOutputDevice {
-    _stream: AudioStream,
+    state: Arc<SharedState>,
 }
 
-impl BaseAudioOutputDevice for AAudioOutputDevice {}
+struct SharedState {
+    // The stream is replaced with a new one when the old one is disconnected.
+    stream: Mutex<Option<AudioStream>>,
+    reconnect_count: AtomicUsize,
+    // The data callback has asked to stop, the stream must not be reopened.
+    is_stopped: AtomicBool,
+    // The stream has underrun repeatedly right after it was opened, it is reopened (also on reconnection)
+    // without the low latency mode and with a larger buffer.
+    has_fallen_back: AtomicBool,
+}
 
-unsafe impl Send for AAudioOutputDevice {}
+// AAudio streams could be used from any thread, the access to the stream itself is synchronized.
+unsafe impl Send for SharedState {}
+unsafe impl Sync for SharedState {}
+
+impl BaseAudioOutputDevice for AAudioOutputDevice {
+    fn is_playing(&self) -> bool {
+        matches!(
+            self.state
+                .stream
+                .lock()
+                .unwrap()
+                .as_ref()
+                .map(|stream| stream.state()),
+            Some(Ok(AudioStreamState::Started))
+        )
+    }
+
+    fn backend(&self) -> Backend {
+        Backend::AAudio
+    }
+
+    fn callback_is_realtime(&self) -> bool {
+        true
+    }
+
+    fn frames_per_burst(&self) -> Option<usize> {
+        self.state
+            .stream
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|stream| stream.frames_per_burst() as usize)
+    }
+
+    fn reconnect_count(&self) -> usize {
+        self.state.reconnect_count.load(Ordering::SeqCst)
+    }
+}
 
 fn convert_err(err: AudioError) -> Box<dyn Error> {
     format!("{:?}", err).into()
 }
 
+fn convert_performance_mode(performance_mode: AndroidPerformanceMode) -> AudioPerformanceMode {
+    match performance_mode {
+        AndroidPerformanceMode::None => AudioPerformanceMode::None,
+        AndroidPerformanceMode::PowerSaving => AudioPerformanceMode::PowerSaving,
+        AndroidPerformanceMode::LowLatency => AudioPerformanceMode::LowLatency,
+    }
+}
+
+fn convert_sharing_mode(sharing_mode: AndroidSharingMode) -> AudioSharingMode {
+    match sharing_mode {
+        AndroidSharingMode::Shared => AudioSharingMode::Shared,
+        AndroidSharingMode::Exclusive => AudioSharingMode::Exclusive,
+    }
+}
+
+#[cfg(feature = "android-api-28")]
+fn convert_usage(usage: AndroidUsage) -> AudioUsage {
+    match usage {
+        AndroidUsage::Media => AudioUsage::Media,
+        AndroidUsage::VoiceCommunication => AudioUsage::VoiceCommunication,
+        AndroidUsage::Alarm => AudioUsage::Alarm,
+        AndroidUsage::Notification => AudioUsage::Notification,
+        AndroidUsage::Game => AudioUsage::Game,
+        AndroidUsage::AssistanceSonification => AudioUsage::AssistanceSonification,
+    }
+}
+
+#[cfg(feature = "android-api-28")]
+fn convert_content_type(content_type: AndroidContentType) -> AudioContentType {
+    match content_type {
+        AndroidContentType::Speech => AudioContentType::Speech,
+        AndroidContentType::Music => AudioContentType::Music,
+        AndroidContentType::Movie => AudioContentType::Movie,
+        AndroidContentType::Sonification => AudioContentType::Sonification,
+    }
+}
+
+fn open_stream(
+    params: OutputDeviceParameters,
+    data_callback: DataCallback,
+    state: Weak<SharedState>,
+) -> Result<AudioStream, Box<dyn Error>> {
+    let frame_count = params.channel_sample_count as i32;
+    let has_fallen_back = state
+        .upgrade()
+        .map_or(false, |state| state.has_fallen_back.load(Ordering::SeqCst));
+    let (performance_mode, sharing_mode, buffer_capacity) = if has_fallen_back {
+        (
+            AudioPerformanceMode::None,
+            AudioSharingMode::Shared,
+            4 * frame_count,
+        )
+    } else {
+        (
+            convert_performance_mode(params.android.performance_mode),
+            convert_sharing_mode(params.android.sharing_mode),
+            // Ensure double buffering is possible.
+            2 * frame_count,
+        )
+    };
+    // Underruns are watched during the first second only, the stream falls back to the larger buffer once.
+    let mut frames_to_watch = if params.adaptive_latency && !has_fallen_back {
+        params.sample_rate
+    } else {
+        0
+    };
+    let fallback_data_callback = data_callback.clone();
+    let fallback_state = state.clone();
+    // AAudio works best with its natural burst size, so the blocks of the data callback are repartitioned
+    // across the bursts.
+    let mut adapter = BlockAdapter::new(&params);
+    let error_data_callback = data_callback.clone();
+    let error_state = state.clone();
+    #[allow(unused_mut)]
+    let mut builder = AudioStreamBuilder::new()
+        .map_err(convert_err)?
+        .buffer_capacity_in_frames(buffer_capacity)
+        .channel_count(params.channels_count as i32)
+        .format(AudioFormat::PCM_Float)
+        .sample_rate(params.sample_rate as i32)
+        .direction(AudioDirection::Output)
+        .performance_mode(performance_mode)
+        .sharing_mode(sharing_mode);
+    #[cfg(feature = "android-api-28")]
+    {
+        if let Some(usage) = params.android.usage {
+            builder = builder.usage(convert_usage(usage));
+        }
+        if let Some(content_type) = params.android.content_type {
+            builder = builder.content_type(convert_content_type(content_type));
+        }
+    }
+    let stream = builder
+        .data_callback(Box::new(move |stream, data, num_frames| {
+            let output_data = unsafe {
+                std::slice::from_raw_parts_mut::<f32>(
+                    data as *mut f32,
+                    num_frames as usize * params.channels_count,
+                )
+            };
+
+            // Returning `Stop` drops the samples of the current call, the adapter asks to stop only when
+            // the last portion of samples was sent completely.
+            let result = adapter.fill(output_data, |block| {
+                (data_callback.lock().unwrap())(block, None)
+            });
+            if result == CallbackResult::Stop {
+                if let Some(state) = state.upgrade() {
+                    state.is_stopped.store(true, Ordering::SeqCst);
+                }
+                return AudioCallbackResult::Stop;
+            }
+
+            if frames_to_watch > 0 {
+                frames_to_watch = frames_to_watch.saturating_sub(num_frames as usize);
+                if stream.x_run_count() >= UNDERRUNS_PER_FALLBACK {
+                    frames_to_watch = 0;
+                    if let Some(state) = fallback_state.upgrade() {
+                        state.has_fallen_back.store(true, Ordering::SeqCst);
+                    }
+                    eprintln!(
+                        "AAudio: the stream has underrun repeatedly, it is reopened with a larger buffer"
+                    );
+                    // The stream cannot be reopened from its own callback, it must be done from some other
+                    // thread.
+                    let data_callback = fallback_data_callback.clone();
+                    let state = fallback_state.clone();
+                    let _ = std::thread::Builder::new()
+                        .name(params.thread_name("AAudioReconnect"))
+                        .spawn(move || reconnect(params, data_callback, state));
+                }
+            }
+
+            AudioCallbackResult::Continue
+        }))
+        .error_callback(Box::new(move |_, error| {
+            if matches!(error, AudioError::Disconnected) {
+                // The stream cannot be reopened from the error callback, it must be done from some other
+                // thread.
+                let data_callback = error_data_callback.clone();
+                let state = error_state.clone();
+                let _ = std::thread::Builder::new()
+                    .name(params.thread_name("AAudioReconnect"))
+                    .spawn(move || reconnect(params, data_callback, state));
+            } else {
+                eprintln!("AAudio: an error has occurred - {:?}", error)
+            }
+        }))
+        .open_stream()
+        .map_err(convert_err)?;
+
+    if has_fallen_back {
+        // The latency is traded for the stability, the whole capacity is used.
+        let _ = stream.set_buffer_size_in_frames(buffer_capacity);
+    }
+    stream.request_start().map_err(convert_err)?;
+
+    Ok(stream)
+}
+
+/// Reopens the disconnected stream (for example, when headphones were plugged or unplugged), as
+/// recommended by the AAudio docs, or the stream that has fallen back to a larger buffer. The delay between
+/// attempts is doubled after each failed attempt.
+fn reconnect(
+    params: OutputDeviceParameters,
+    data_callback: DataCallback,
+    state: Weak<SharedState>,
+) {
+    for attempt in 0..MAX_RECONNECT_ATTEMPTS {
+        std::thread::sleep(Duration::from_millis(100 << attempt));
+
+        // The device was dropped in the meantime, nothing to reconnect.
+        let state_ref = match state.upgrade() {
+            Some(state_ref) => state_ref,
+            None => return,
+        };
+        if state_ref.is_stopped.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let mut stream = state_ref.stream.lock().unwrap();
+        // Close the old stream first, so the new one could take its place.
+        stream.take();
+        match open_stream(params, data_callback.clone(), state.clone()) {
+            Ok(new_stream) => {
+                *stream = Some(new_stream);
+                state_ref.reconnect_count.fetch_add(1, Ordering::SeqCst);
+                return;
+            }
+            Err(err) => eprintln!(
+                "AAudio: unable to reopen the stream (attempt {}) - {}",
+                attempt + 1,
+                err
+            ),
+        }
+    }
+}
+
 impl AudioOutputDevice for AAudioOutputDevice {
-    fn new<C>(params: OutputDeviceParameters, mut data_callback: C) -> Result<Self, Box<dyn Error>>
+    fn new<C>(params: OutputDeviceParameters, data_callback: C) -> Result<Self, Box<dyn Error>>
     where
-        C: FnMut(&mut [f32]) + Send + 'static,
+        C: FnMut(&mut [f32], Option<f64>) -> CallbackResult + Send + 'static,
         Self: Sized,
     {
-        let frame_count = params.channel_sample_count as i32;
-        let stream = AudioStreamBuilder::new()
-            .map_err(convert_err)?
-            // Ensure double buffering is possible.
-            .buffer_capacity_in_frames(2 * frame_count)
-            .channel_count(params.channels_count as i32)
-            .format(AudioFormat::PCM_Float)
-            .sample_rate(params.sample_rate as i32)
-            .direction(AudioDirection::Output)
-            .performance_mode(AudioPerformanceMode::LowLatency)
-            // Force the AAudio to give the buffer of fixed size.
-            .frames_per_data_callback(frame_count)
-            .data_callback(Box::new(move |_, data, num_frames| {
-                let output_data = unsafe {
-                    std::slice::from_raw_parts_mut::<f32>(
-                        data as *mut f32,
-                        num_frames as usize * params.channels_count,
-                    )
-                };
-
-                data_callback(output_data);
-
-                AudioCallbackResult::Continue
-            }))
-            .error_callback(Box::new(|_, error| {
-                eprintln!("AAudio: an error has occurred - {:?}", error)
-            }))
-            .open_stream()
-            .map_err(convert_err)?;
-
-        stream.request_start().map_err(convert_err)?;
+        let state = Arc::new(SharedState {
+            stream: Mutex::new(None),
+            reconnect_count: AtomicUsize::new(0),
+            is_stopped: AtomicBool::new(false),
+            has_fallen_back: AtomicBool::new(false),
+        });
+        let stream = open_stream(
+            params,
+            Arc::new(Mutex::new(data_callback)),
+            Arc::downgrade(&state),
+        )?;
+        *state.stream.lock().unwrap() = Some(stream);
 
-        Ok(Self { _stream: stream })
+        Ok(Self { state })
     }
 }
@@ -0,0 +1,104 @@
+//! Data callback adapters that remove the most common boilerplate from data callbacks.
+
+use crate::{ChannelMatrix, IntoCallbackResult, MaybeSend};
+
+/// Wraps a data callback that produces mono samples into a data callback that produces interleaved samples
+/// for `channels_count` channels. The mono buffer passed to the `callback` has `channel_sample_count` samples
+/// and each of its samples is copied to all channels.
+///
+/// ## Examples
+///
+/// The following example plays a 440 Hz sine wave on all channels.
+///
+/// ```rust,no_run
+/// # use tinyaudio::prelude::*;
+/// let params = OutputDeviceParameters::default();
+///
+/// let _device = run_output_device(
+///     params,
+///     upmix_mono(params.channels_count, {
+///         let mut clock = 0f32;
+///         move |data| {
+///             for sample in data {
+///                 clock = (clock + 1.0) % params.sample_rate as f32;
+///                 *sample =
+///                     (clock * 440.0 * 2.0 * std::f32::consts::PI / params.sample_rate as f32).sin();
+///             }
+///         }
+///     }),
+/// )
+/// .unwrap();
+///
+/// std::thread::sleep(std::time::Duration::from_secs(5));
+/// ```
+pub fn upmix_mono<C, R>(
+    channels_count: usize,
+    mut callback: C,
+) -> impl FnMut(&mut [f32]) -> R + MaybeSend + 'static
+where
+    C: FnMut(&mut [f32]) -> R + MaybeSend + 'static,
+    R: IntoCallbackResult,
+{
+    let mut mono_samples = Vec::new();
+    move |data| {
+        // Fill with silence, like the interleaved buffer. Allocates only once, since the size of the
+        // buffer does not change between the calls.
+        mono_samples.clear();
+        mono_samples.resize(data.len() / channels_count, 0.0);
+        let result = callback(&mut mono_samples);
+        for (frame, mono_sample) in data.chunks_mut(channels_count).zip(mono_samples.iter()) {
+            for sample in frame {
+                *sample = *mono_sample;
+            }
+        }
+        result
+    }
+}
+
+/// Wraps a data callback that produces interleaved samples for `matrix.source_channels()` channels into a
+/// data callback that produces samples for `matrix.output_channels()` channels, mixing them with the given
+/// `matrix`. Use it when a custom matrix is needed, otherwise [`crate::OutputDeviceParameters::source_channels`]
+/// does the same with the default matrix (see [`ChannelMatrix::default_for`]).
+///
+/// ## Examples
+///
+/// The following example renders stereo, but plays it on a mono device with the left channel only.
+///
+/// ```rust,no_run
+/// # use tinyaudio::prelude::*;
+/// let mut params = OutputDeviceParameters::default();
+/// params.channels_count = 1;
+///
+/// let matrix = ChannelMatrix::new(2, 1, vec![1.0, 0.0]).unwrap();
+///
+/// let _device = run_output_device(
+///     params,
+///     remix_channels(matrix, move |data| {
+///         for frame in data.chunks_mut(2) {
+///             frame[0] = 0.1;
+///             frame[1] = -0.1;
+///         }
+///     }),
+/// )
+/// .unwrap();
+///
+/// std::thread::sleep(std::time::Duration::from_secs(5));
+/// ```
+pub fn remix_channels<C, R>(
+    matrix: ChannelMatrix,
+    mut callback: C,
+) -> impl FnMut(&mut [f32]) -> R + MaybeSend + 'static
+where
+    C: FnMut(&mut [f32]) -> R + MaybeSend + 'static,
+    R: IntoCallbackResult,
+{
+    let mut source_samples = Vec::new();
+    move |data| {
+        let frame_count = data.len() / matrix.output_channels();
+        source_samples.clear();
+        source_samples.resize(frame_count * matrix.source_channels(), 0.0);
+        let result = callback(&mut source_samples);
+        matrix.apply(&source_samples, data);
+        result
+    }
+}
@@ -2,33 +2,55 @@
 
 #![cfg(target_os = "linux")]
 
-use crate::{AudioOutputDevice, BaseAudioOutputDevice, OutputDeviceParameters};
+use crate::{
+    convert::{convert_samples_i16_to_f32, Dither},
+    device::{COMMON_SAMPLE_RATES, MAX_CHANNELS_COUNT},
+    thread::{set_current_thread_affinity, set_current_thread_priority},
+    AudioOutputDevice, Backend, BaseAudioOutputDevice, CallbackResult, NativeHandle,
+    OutputDeviceParameters, Sample, SampleFormat, TinyAudioError,
+};
 use alsa_sys::*;
 use std::{
     error::Error,
     ffi::{CStr, CString},
-    os::raw::c_int,
+    os::raw::{c_int, c_void},
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicUsize, Ordering},
         Arc,
     },
     thread::JoinHandle,
 };
 
-pub struct AlsaSoundDevice {
-    playback_device: *mut snd_pcm_t,
+// 16-bit signed samples in native byte order, the samples are written as `i16` as is.
+#[cfg(target_endian = "little")]
+const SND_PCM_FORMAT_S16_NE: snd_pcm_format_t = SND_PCM_FORMAT_S16_LE;
+#[cfg(target_endian = "big")]
+const SND_PCM_FORMAT_S16_NE: snd_pcm_format_t = SND_PCM_FORMAT_S16_BE;
+
+pub struct AlsaSoundDevice<P: PlaybackPcm = *mut snd_pcm_t> {
+    playback_device: P,
     thread_handle: Option<JoinHandle<()>>,
     is_running: Arc<AtomicBool>,
+    // Size of the ring buffer of the device in frames, it changes when the buffer grows after underruns.
+    buffer_size: Arc<AtomicUsize>,
+    // Size of the period of the device in frames, it stays the same when the buffer grows.
+    period_size: usize,
+    // The nearest sample rate the device supports.
+    sample_rate: usize,
+    // The feed thread was asked to raise its priority.
+    is_realtime: bool,
+    // Asks the feed thread to restart the stream, see `OutputDevice::restart`.
+    restart_request: Arc<AtomicBool>,
 }
 
-unsafe impl Send for AlsaSoundDevice {}
+unsafe impl<P: PlaybackPcm> Send for AlsaSoundDevice<P> {}
 
 pub fn err_code_to_string(err_code: c_int) -> String {
     unsafe {
-        let message = CStr::from_ptr(snd_strerror(err_code) as *const _)
-            .to_bytes()
-            .to_vec();
-        String::from_utf8(message).unwrap()
+        // The messages could be translated, a malformed one must not bring the whole device down.
+        CStr::from_ptr(snd_strerror(err_code) as *const _)
+            .to_string_lossy()
+            .into_owned()
     }
 }
 
@@ -40,89 +62,843 @@ pub fn check(err_code: c_int) -> Result<(), Box<dyn Error>> {
     }
 }
 
-impl BaseAudioOutputDevice for AlsaSoundDevice {}
+/// Returns the description of the device from the ALSA device hints (for example, "Default ALSA Output"),
+/// the lines of the description are joined with spaces.
+pub fn device_description(device_name: &str) -> Option<String> {
+    unsafe {
+        let mut hints: *mut *mut c_void = std::ptr::null_mut();
+        if snd_device_name_hint(-1, b"pcm\0".as_ptr() as *const _, &mut hints) < 0 {
+            return None;
+        }
+
+        // Takes the hint value allocated by ALSA.
+        let take_hint = |hint: *const c_void, id: &[u8]| {
+            let value = snd_device_name_get_hint(hint, id.as_ptr() as *const _);
+            if value.is_null() {
+                None
+            } else {
+                let string = CStr::from_ptr(value).to_string_lossy().into_owned();
+                libc::free(value as *mut c_void);
+                Some(string)
+            }
+        };
+
+        let mut description = None;
+        let mut hint = hints;
+        while !(*hint).is_null() {
+            if take_hint(*hint, b"NAME\0").as_deref() == Some(device_name) {
+                description = take_hint(*hint, b"DESC\0").map(|description| {
+                    description
+                        .lines()
+                        .map(str::trim)
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                });
+                break;
+            }
+            hint = hint.add(1);
+        }
+
+        snd_device_name_free_hint(hints);
+        description
+    }
+}
+
+/// Opens the device without configuring it and runs the `query` on its full configuration space. Returns
+/// `None` if the device cannot be opened (for example, it is busy).
+fn query_hw_params<R>(
+    device_name: &str,
+    query: impl FnOnce(*mut snd_pcm_t, *mut snd_pcm_hw_params_t) -> R,
+) -> Option<R> {
+    let name = CString::new(device_name).ok()?;
+    unsafe {
+        let mut playback_device = std::ptr::null_mut();
+        if snd_pcm_open(
+            &mut playback_device,
+            name.as_ptr() as *const _,
+            SND_PCM_STREAM_PLAYBACK,
+            SND_PCM_NONBLOCK,
+        ) < 0
+        {
+            return None;
+        }
+
+        let mut hw_params = std::ptr::null_mut();
+        let mut result = None;
+        if snd_pcm_hw_params_malloc(&mut hw_params) >= 0 {
+            if snd_pcm_hw_params_any(playback_device, hw_params) >= 0 {
+                result = Some(query(playback_device, hw_params));
+            }
+            snd_pcm_hw_params_free(hw_params);
+        }
+        snd_pcm_close(playback_device);
+        result
+    }
+}
+
+/// Returns `true` if the device cannot be opened because someone else holds it (for example, an application
+/// that opened the hardware device directly, bypassing `dmix`).
+pub fn is_device_in_use(device_name: &str) -> bool {
+    let name = match CString::new(device_name) {
+        Ok(name) => name,
+        Err(_) => return false,
+    };
+    unsafe {
+        let mut playback_device = std::ptr::null_mut();
+        let result = snd_pcm_open(
+            &mut playback_device,
+            name.as_ptr() as *const _,
+            SND_PCM_STREAM_PLAYBACK,
+            SND_PCM_NONBLOCK,
+        );
+        if result >= 0 {
+            snd_pcm_close(playback_device);
+        }
+        result == -libc::EBUSY
+    }
+}
+
+pub fn supported_sample_rates(device_name: &str) -> Vec<usize> {
+    query_hw_params(device_name, |playback_device, hw_params| unsafe {
+        let mut min = 0;
+        let mut max = 0;
+        if snd_pcm_hw_params_get_rate_min(hw_params, &mut min, std::ptr::null_mut()) < 0
+            || snd_pcm_hw_params_get_rate_max(hw_params, &mut max, std::ptr::null_mut()) < 0
+        {
+            return Vec::new();
+        }
+
+        COMMON_SAMPLE_RATES
+            .iter()
+            .copied()
+            .filter(|&rate| {
+                (min as usize..=max as usize).contains(&rate)
+                    && snd_pcm_hw_params_test_rate(playback_device, hw_params, rate as _, 0) == 0
+            })
+            .collect()
+    })
+    .unwrap_or_default()
+}
+
+pub fn supported_channel_counts(device_name: &str) -> Vec<usize> {
+    query_hw_params(device_name, |playback_device, hw_params| unsafe {
+        let mut min = 0;
+        let mut max = 0;
+        if snd_pcm_hw_params_get_channels_min(hw_params, &mut min) < 0
+            || snd_pcm_hw_params_get_channels_max(hw_params, &mut max) < 0
+        {
+            return Vec::new();
+        }
+
+        (min.max(1) as usize..=(max as usize).min(MAX_CHANNELS_COUNT))
+            .filter(|&channels_count| {
+                snd_pcm_hw_params_test_channels(playback_device, hw_params, channels_count as _)
+                    == 0
+            })
+            .collect()
+    })
+    .unwrap_or_default()
+}
+
+/// Amount of periods in the ring buffer of the device.
+const PERIOD_COUNT: usize = 2;
+
+/// Maximum amount of periods in the ring buffer of the device, when it grows after underruns (see
+/// `OutputDeviceParameters::adaptive_latency`).
+const MAX_PERIOD_COUNT: usize = 8;
+
+/// Amount of underruns after which the ring buffer of the device grows by one period.
+const UNDERRUNS_PER_GROWTH: usize = 3;
+
+/// The configuration the device has actually accepted, it could differ from the requested one.
+pub struct PcmConfiguration {
+    // Size of the period in frames.
+    period_size: usize,
+    // Size of the ring buffer in frames.
+    buffer_size: usize,
+    sample_rate: usize,
+}
+
+/// The hardware device opened by `LinuxParameters::exclusive`.
+const EXCLUSIVE_DEVICE_NAME: &str = "hw:0,0";
+
+/// Opens the default device (or the hardware one, see `LinuxParameters::exclusive`) for the given `stream`
+/// direction and configures it with the `params`. Returns the device and its actual configuration.
+unsafe fn open_pcm(
+    stream: snd_pcm_stream_t,
+    params: &OutputDeviceParameters,
+) -> Result<(*mut snd_pcm_t, PcmConfiguration), Box<dyn Error>> {
+    let name = if params.linux.exclusive {
+        EXCLUSIVE_DEVICE_NAME
+    } else {
+        "default"
+    };
+    let c_name = CString::new(name)?;
+    let mut device = std::ptr::null_mut();
+    let result = snd_pcm_open(&mut device, c_name.as_ptr() as *const _, stream, 0);
+    if result < 0 {
+        let reason = if params.linux.exclusive {
+            format!(
+                "ALSA: unable to open the hardware device `{}` exclusively, it could be held by another \
+                application - {}",
+                name,
+                err_code_to_string(result)
+            )
+        } else {
+            format!("ALSA: {}", err_code_to_string(result))
+        };
+        return Err(Box::new(match -result {
+            libc::EBUSY => TinyAudioError::DeviceBusy(reason),
+            libc::ENOENT | libc::ENODEV => TinyAudioError::DeviceUnavailable(reason),
+            _ => TinyAudioError::BackendError(reason),
+        }));
+    }
+    match configure_pcm(device, params, PERIOD_COUNT) {
+        Ok(configuration) => Ok((device, configuration)),
+        Err(err) => {
+            snd_pcm_close(device);
+            Err(err)
+        }
+    }
+}
+
+/// Sets the format and the ring buffer of `period_count` periods of the device and prepares it. The device
+/// must not be running. Returns the actual configuration, the device could make the period and the ring
+/// buffer smaller than requested, but the ring buffer always holds at least one buffer of the data callback.
+/// The sample rate is the nearest one the device supports, the samples are not resampled if it differs.
+/// Hardware parameters allocated by ALSA, freed on drop.
+struct HwParams(*mut snd_pcm_hw_params_t);
+
+impl HwParams {
+    unsafe fn new() -> Result<Self, Box<dyn Error>> {
+        let mut hw_params = std::ptr::null_mut();
+        check(snd_pcm_hw_params_malloc(&mut hw_params))?;
+        Ok(Self(hw_params))
+    }
+}
+
+impl Drop for HwParams {
+    fn drop(&mut self) {
+        unsafe { snd_pcm_hw_params_free(self.0) }
+    }
+}
+
+/// Software parameters allocated by ALSA, freed on drop.
+struct SwParams(*mut snd_pcm_sw_params_t);
+
+impl SwParams {
+    unsafe fn new() -> Result<Self, Box<dyn Error>> {
+        let mut sw_params = std::ptr::null_mut();
+        check(snd_pcm_sw_params_malloc(&mut sw_params))?;
+        Ok(Self(sw_params))
+    }
+}
+
+impl Drop for SwParams {
+    fn drop(&mut self) {
+        unsafe { snd_pcm_sw_params_free(self.0) }
+    }
+}
+
+unsafe fn configure_pcm(
+    device: *mut snd_pcm_t,
+    params: &OutputDeviceParameters,
+    period_count: usize,
+) -> Result<PcmConfiguration, Box<dyn Error>> {
+    let frame_count = params.channel_sample_count;
+    let hw_params_guard = HwParams::new()?;
+    let hw_params = hw_params_guard.0;
+    check(snd_pcm_hw_params_any(device, hw_params))?;
+    let access = SND_PCM_ACCESS_RW_INTERLEAVED;
+    check(snd_pcm_hw_params_set_access(device, hw_params, access))?;
+    let format = match params.sample_format {
+        SampleFormat::I16 => SND_PCM_FORMAT_S16_NE,
+        SampleFormat::U8 => SND_PCM_FORMAT_U8,
+    };
+    check(snd_pcm_hw_params_set_format(device, hw_params, format))?;
+    let mut exact_rate = params.sample_rate as ::std::os::raw::c_uint;
+    check(snd_pcm_hw_params_set_rate_near(
+        device,
+        hw_params,
+        &mut exact_rate,
+        std::ptr::null_mut(),
+    ))?;
+    check(snd_pcm_hw_params_set_channels(
+        device,
+        hw_params,
+        params.channels_count as ::std::os::raw::c_uint,
+    ))?;
+    // The period could be clamped to the limits of the device, the samples are written in the buffers of
+    // the data callback anyway.
+    let mut exact_period = frame_count as snd_pcm_uframes_t;
+    let mut direction = 0;
+    check(snd_pcm_hw_params_set_period_size_near(
+        device,
+        hw_params,
+        &mut exact_period,
+        &mut direction,
+    ))?;
+    let mut exact_size = (frame_count * period_count) as ::std::os::raw::c_ulong;
+    check(snd_pcm_hw_params_set_buffer_size_near(
+        device,
+        hw_params,
+        &mut exact_size,
+    ))?;
+    // The device would never start if its buffer cannot hold a single buffer of the data callback.
+    if (exact_size as usize) < frame_count {
+        return Err(Box::new(TinyAudioError::InvalidParameters(format!(
+            "`channel_sample_count` is {}, but the buffer of the device holds at most {} frames",
+            frame_count, exact_size
+        ))));
+    }
+    check(snd_pcm_hw_params(device, hw_params))?;
+    // The period could be adjusted once more to fit the ring buffer, the installed one is reported.
+    check(snd_pcm_hw_params_get_period_size(
+        hw_params,
+        &mut exact_period,
+        &mut direction,
+    ))?;
+    drop(hw_params_guard);
+    let sw_params_guard = SwParams::new()?;
+    let sw_params = sw_params_guard.0;
+    check(snd_pcm_sw_params_current(device, sw_params))?;
+    check(snd_pcm_sw_params_set_avail_min(
+        device,
+        sw_params,
+        frame_count as ::std::os::raw::c_ulong,
+    ))?;
+    check(snd_pcm_sw_params_set_start_threshold(
+        device,
+        sw_params,
+        frame_count as ::std::os::raw::c_ulong,
+    ))?;
+    check(snd_pcm_sw_params(device, sw_params))?;
+    check(snd_pcm_prepare(device))?;
+    Ok(PcmConfiguration {
+        period_size: exact_period as usize,
+        buffer_size: exact_size as usize,
+        sample_rate: exact_rate as usize,
+    })
+}
+
+impl BaseAudioOutputDevice for AlsaSoundDevice {
+    fn is_playing(&self) -> bool {
+        self.is_running.load(Ordering::SeqCst)
+    }
+
+    fn backend(&self) -> Backend {
+        Backend::Alsa
+    }
+
+    fn native_handle(&self) -> NativeHandle<'_> {
+        NativeHandle::Alsa(self.playback_device)
+    }
+
+    fn device_buffer_size(&self) -> Option<usize> {
+        Some(self.buffer_size.load(Ordering::SeqCst))
+    }
+
+    fn device_period_size(&self) -> Option<usize> {
+        Some(self.period_size)
+    }
+
+    fn callback_is_realtime(&self) -> bool {
+        self.is_realtime
+    }
+
+    fn device_sample_rate(&self) -> Option<usize> {
+        Some(self.sample_rate)
+    }
+
+    fn restart(&self) -> bool {
+        self.restart_request.store(true, Ordering::SeqCst);
+        true
+    }
+}
 
 impl AudioOutputDevice for AlsaSoundDevice {
     fn new<C>(params: OutputDeviceParameters, data_callback: C) -> Result<Self, Box<dyn Error>>
     where
-        C: FnMut(&mut [f32]) + Send + 'static,
+        C: FnMut(&mut [f32], Option<f64>) -> CallbackResult + Send + 'static,
         Self: Sized,
     {
+        let (playback_device, configuration) =
+            unsafe { open_pcm(SND_PCM_STREAM_PLAYBACK, &params)? };
+        Self::start(playback_device, configuration, params, data_callback)
+    }
+}
+
+impl<P: PlaybackPcm> AlsaSoundDevice<P> {
+    /// Starts feeding the configured `playback_device` with the samples from a separate thread. The device
+    /// is closed when the returned device is dropped.
+    fn start<C>(
+        playback_device: P,
+        configuration: PcmConfiguration,
+        params: OutputDeviceParameters,
+        data_callback: C,
+    ) -> Result<Self, Box<dyn Error>>
+    where
+        C: FnMut(&mut [f32], Option<f64>) -> CallbackResult + Send + 'static,
+        P: 'static,
+    {
+        let is_running = Arc::new(AtomicBool::new(true));
+        let buffer_size = Arc::new(AtomicUsize::new(configuration.buffer_size));
+        let restart_request = Arc::new(AtomicBool::new(false));
+
+        let thread_handle = DataSender {
+            playback_device,
+            callback: data_callback,
+            data_buffer: vec![0.0f32; params.total_samples()],
+            output_buffer: OutputBuffer::new(&params),
+            dither: Dither::new(params.dither, params.limiter),
+            is_running: is_running.clone(),
+            params,
+            underrun_count: 0,
+            period_count: PERIOD_COUNT,
+            buffer_size: buffer_size.clone(),
+            restart_request: restart_request.clone(),
+        }
+        .run_in_thread();
+        let thread_handle = match thread_handle {
+            Ok(thread_handle) => thread_handle,
+            Err(err) => {
+                unsafe { playback_device.close() };
+                return Err(err);
+            }
+        };
+
+        Ok(Self {
+            playback_device,
+            is_running,
+            thread_handle: Some(thread_handle),
+            buffer_size,
+            period_size: configuration.period_size,
+            sample_rate: configuration.sample_rate,
+            is_realtime: params.thread_priority.is_some(),
+            restart_request,
+        })
+    }
+}
+
+impl<P: PlaybackPcm> Drop for AlsaSoundDevice<P> {
+    fn drop(&mut self) {
+        self.is_running.store(false, Ordering::SeqCst);
+
+        // The feed thread could be blocked in a write (or drain) for up to a buffer, stopping the device
+        // wakes it up right away.
         unsafe {
-            let name = CString::new("default").unwrap();
-            let frame_count = params.channel_sample_count;
-            let mut playback_device = std::ptr::null_mut();
-            check(snd_pcm_open(
-                &mut playback_device,
-                name.as_ptr() as *const _,
-                SND_PCM_STREAM_PLAYBACK,
-                0,
-            ))?;
-            let mut hw_params = std::ptr::null_mut();
-            check(snd_pcm_hw_params_malloc(&mut hw_params))?;
-            check(snd_pcm_hw_params_any(playback_device, hw_params))?;
-            let access = SND_PCM_ACCESS_RW_INTERLEAVED;
-            check(snd_pcm_hw_params_set_access(
-                playback_device,
-                hw_params,
-                access,
-            ))?;
-            check(snd_pcm_hw_params_set_format(
-                playback_device,
-                hw_params,
-                SND_PCM_FORMAT_S16_LE,
-            ))?;
-            let mut exact_rate = params.sample_rate as ::std::os::raw::c_uint;
-            check(snd_pcm_hw_params_set_rate_near(
-                playback_device,
-                hw_params,
-                &mut exact_rate,
-                std::ptr::null_mut(),
-            ))?;
-            check(snd_pcm_hw_params_set_channels(
-                playback_device,
-                hw_params,
-                params.channels_count as ::std::os::raw::c_uint,
-            ))?;
-            let mut _exact_period = frame_count as snd_pcm_uframes_t;
-            let mut _direction = 0;
-            check(snd_pcm_hw_params_set_period_size_near(
-                playback_device,
-                hw_params,
-                &mut _exact_period,
-                &mut _direction,
-            ))?;
-            let mut exact_size = (frame_count * 2) as ::std::os::raw::c_ulong;
-            check(snd_pcm_hw_params_set_buffer_size_near(
-                playback_device,
-                hw_params,
-                &mut exact_size,
-            ))?;
-            check(snd_pcm_hw_params(playback_device, hw_params))?;
-            snd_pcm_hw_params_free(hw_params);
-            let mut sw_params = std::ptr::null_mut();
-            check(snd_pcm_sw_params_malloc(&mut sw_params))?;
-            check(snd_pcm_sw_params_current(playback_device, sw_params))?;
-            check(snd_pcm_sw_params_set_avail_min(
-                playback_device,
-                sw_params,
-                frame_count as ::std::os::raw::c_ulong,
-            ))?;
-            check(snd_pcm_sw_params_set_start_threshold(
-                playback_device,
-                sw_params,
-                frame_count as ::std::os::raw::c_ulong,
-            ))?;
-            check(snd_pcm_sw_params(playback_device, sw_params))?;
-            check(snd_pcm_prepare(playback_device))?;
+            self.playback_device.drop_samples();
+        }
+
+        // The thread could have panicked in the data callback, the device must be released anyway.
+        if let Some(thread_handle) = self.thread_handle.take() {
+            let _ = thread_handle.join();
+        }
+
+        unsafe {
+            self.playback_device.close();
+        }
+    }
+}
+
+/// The calls to the playback device the feed thread makes, so the feed loop could be tested without a sound
+/// card.
+pub trait PlaybackPcm: Copy {
+    /// `snd_pcm_writei`, returns the amount of frames written or the error code. The `samples` are the
+    /// bytes of the interleaved samples in the format of the device.
+    unsafe fn write_interleaved(self, samples: &[u8], frame_count: usize) -> snd_pcm_sframes_t;
+
+    /// `snd_pcm_recover`, silently.
+    unsafe fn recover(self, err: c_int) -> c_int;
+
+    /// `snd_pcm_drop`, stops the device right away and wakes up a blocked write or drain.
+    unsafe fn drop_samples(self);
+
+    /// `snd_pcm_drain`, blocks until the queued samples are played.
+    unsafe fn drain(self);
+
+    /// `snd_pcm_prepare`, returns the error code.
+    unsafe fn prepare(self) -> c_int;
+
+    /// Configures the stopped device with the ring buffer of `period_count` periods, see [`configure_pcm`].
+    unsafe fn configure(
+        self,
+        params: &OutputDeviceParameters,
+        period_count: usize,
+    ) -> Result<PcmConfiguration, Box<dyn Error>>;
+
+    /// `snd_pcm_close`.
+    unsafe fn close(self);
+}
+
+impl PlaybackPcm for *mut snd_pcm_t {
+    unsafe fn write_interleaved(self, samples: &[u8], frame_count: usize) -> snd_pcm_sframes_t {
+        snd_pcm_writei(
+            self,
+            samples.as_ptr() as *const _,
+            frame_count as ::std::os::raw::c_ulong,
+        )
+    }
+
+    unsafe fn recover(self, err: c_int) -> c_int {
+        snd_pcm_recover(self, err, 1)
+    }
+
+    unsafe fn drop_samples(self) {
+        snd_pcm_drop(self);
+    }
+
+    unsafe fn drain(self) {
+        snd_pcm_drain(self);
+    }
+
+    unsafe fn prepare(self) -> c_int {
+        snd_pcm_prepare(self)
+    }
+
+    unsafe fn configure(
+        self,
+        params: &OutputDeviceParameters,
+        period_count: usize,
+    ) -> Result<PcmConfiguration, Box<dyn Error>> {
+        configure_pcm(self, params, period_count)
+    }
+
+    unsafe fn close(self) {
+        snd_pcm_close(self);
+    }
+}
+
+/// Recovers the device after a failed write or read, `snd_pcm_recover` is tried up to
+/// `LinuxParameters::xrun_recovery_attempts` times. Fails if the device cannot be recovered.
+unsafe fn recover<P: PlaybackPcm>(
+    device: P,
+    err: c_int,
+    params: &OutputDeviceParameters,
+) -> Result<(), Box<dyn Error>> {
+    // Only underruns, suspends and interrupted calls could be recovered. Otherwise the device has failed or
+    // was stopped (for example, when it is closed), there's no point to retry.
+    if err != -libc::EPIPE && err != -libc::ESTRPIPE && err != -libc::EINTR {
+        return Err(err_code_to_string(err).into());
+    }
+    let attempts = params.linux.xrun_recovery_attempts;
+    let mut result = err;
+    for _ in 0..attempts {
+        result = device.recover(err);
+        if result >= 0 {
+            return Ok(());
+        }
+        // The device could be busy for a moment, for example, while it is resumed after a suspend.
+        std::thread::sleep(std::time::Duration::from_millis(1));
+    }
+    Err(format!(
+        "Unable to recover the device after {} attempts - {}",
+        attempts,
+        err_code_to_string(result)
+    )
+    .into())
+}
+
+/// Writes all the frames of the buffer, returns the error code if the write has failed.
+unsafe fn try_write_frames<P: PlaybackPcm>(
+    playback_device: P,
+    samples: &[u8],
+    params: &OutputDeviceParameters,
+) -> Result<(), c_int> {
+    let frame_size = samples.len() / params.channel_sample_count;
+    let mut frames_written = 0;
+    while frames_written < params.channel_sample_count {
+        let result = playback_device.write_interleaved(
+            &samples[frames_written * frame_size..],
+            params.channel_sample_count - frames_written,
+        );
+
+        if result < 0 {
+            return Err(result as c_int);
+        }
+
+        // The write could be interrupted by a signal, then only a part of the frames is written.
+        frames_written += result as usize;
+    }
+    Ok(())
+}
+
+/// Writes one buffer of interleaved samples to the playback device, blocks until there's enough free space.
+/// If the device has underrun (or failed otherwise), it is recovered and the rest of the samples is dropped:
+/// they are late already, and replaying them would cause an audible stutter. The device restarts with the
+/// next portion of fresh samples, or with a buffer of silence (the `samples` are silenced then) if
+/// `LinuxParameters::recover_silence` is set. Returns `true` if the device has underrun, fails if the device
+/// cannot be recovered.
+unsafe fn write_frames<P: PlaybackPcm>(
+    playback_device: P,
+    samples: &mut OutputBuffer,
+    params: &OutputDeviceParameters,
+) -> Result<bool, Box<dyn Error>> {
+    let err = match try_write_frames(playback_device, samples.as_bytes(), params) {
+        Ok(()) => return Ok(false),
+        Err(err) => err,
+    };
+    recover(playback_device, err, params)?;
+
+    if params.linux.recover_silence {
+        samples.silence();
+        if let Err(err) = try_write_frames(playback_device, samples.as_bytes(), params) {
+            recover(playback_device, err, params)?;
+        }
+    }
+
+    Ok(err == -libc::EPIPE)
+}
+
+/// The samples in the format of the device, see `OutputDeviceParameters::sample_format`.
+#[derive(Debug, PartialEq)]
+enum OutputBuffer {
+    I16(Vec<i16>),
+    U8(Vec<u8>),
+}
+
+impl OutputBuffer {
+    /// Creates a silent buffer for one buffer of the data callback.
+    fn new(params: &OutputDeviceParameters) -> Self {
+        match params.sample_format {
+            SampleFormat::I16 => Self::I16(vec![i16::SILENCE; params.total_samples()]),
+            SampleFormat::U8 => Self::U8(vec![u8::SILENCE; params.total_samples()]),
+        }
+    }
+
+    /// Converts the samples of the data callback to the format of the device.
+    fn convert(&mut self, dither: &mut Dither, data_buffer: &[f32]) {
+        match self {
+            Self::I16(samples) => dither.convert(data_buffer, samples),
+            Self::U8(samples) => dither.convert_u8(data_buffer, samples),
+        }
+    }
+
+    fn silence(&mut self) {
+        match self {
+            Self::I16(samples) => samples.fill(i16::SILENCE),
+            Self::U8(samples) => samples.fill(u8::SILENCE),
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        match self {
+            Self::I16(samples) => unsafe {
+                std::slice::from_raw_parts(
+                    samples.as_ptr() as *const u8,
+                    samples.len() * std::mem::size_of::<i16>(),
+                )
+            },
+            Self::U8(samples) => samples,
+        }
+    }
+}
+
+struct DataSender<C, P> {
+    playback_device: P,
+    callback: C,
+    data_buffer: Vec<f32>,
+    output_buffer: OutputBuffer,
+    dither: Dither,
+    is_running: Arc<AtomicBool>,
+    params: OutputDeviceParameters,
+    // Underruns since the ring buffer of the device was configured last time.
+    underrun_count: usize,
+    // Amount of periods in the ring buffer of the device, `MAX_PERIOD_COUNT` if it cannot grow anymore.
+    period_count: usize,
+    buffer_size: Arc<AtomicUsize>,
+    restart_request: Arc<AtomicBool>,
+}
+
+unsafe impl<C, P> Send for DataSender<C, P> {}
+
+impl<C, P> Drop for DataSender<C, P> {
+    fn drop(&mut self) {
+        // The feed thread has exited (normally or because of a panic in the data callback), let the
+        // device know that it no longer plays anything.
+        self.is_running.store(false, Ordering::SeqCst);
+    }
+}
+
+impl<C, P: PlaybackPcm> DataSender<C, P> {
+    /// Adds one more period to the ring buffer of the device, so the data callback has more time to render
+    /// the samples. The device is stopped for a moment, it has underrun anyway.
+    fn grow_buffer(&mut self) {
+        self.underrun_count = 0;
+        let period_count = self.period_count + 1;
+        let result = unsafe {
+            self.playback_device.drop_samples();
+            self.playback_device.configure(&self.params, period_count)
+        };
+        match result {
+            Ok(PcmConfiguration { buffer_size, .. }) => {
+                self.period_count = period_count;
+                self.buffer_size.store(buffer_size, Ordering::SeqCst);
+                eprintln!(
+                    "ALSA: the device has underrun repeatedly, the buffer was grown to {} frames",
+                    buffer_size
+                );
+            }
+            Err(err) => {
+                // The device rejects the larger buffer, the previous configuration is restored and no
+                // further attempts are made.
+                eprintln!("ALSA: unable to grow the buffer of the device - {}", err);
+                unsafe {
+                    let _ = self
+                        .playback_device
+                        .configure(&self.params, self.period_count);
+                }
+                self.period_count = MAX_PERIOD_COUNT;
+            }
+        }
+    }
+
+    /// Drops the samples queued in the device and prepares it again, see `OutputDevice::restart`. One
+    /// period of silence is written, so the stream starts right away and the next buffer has time to render.
+    fn restart(&mut self) {
+        self.underrun_count = 0;
+        let result = unsafe {
+            self.playback_device.drop_samples();
+            check(self.playback_device.prepare())
+        };
+        match result {
+            Ok(()) => {
+                self.output_buffer.silence();
+                let written = unsafe {
+                    write_frames(self.playback_device, &mut self.output_buffer, &self.params)
+                };
+                if let Err(err) = written {
+                    eprintln!("ALSA: unable to restart the device - {}", err);
+                }
+            }
+            Err(err) => eprintln!("ALSA: unable to restart the device - {}", err),
+        }
+    }
+}
+
+impl<C, P> DataSender<C, P>
+where
+    C: FnMut(&mut [f32], Option<f64>) -> CallbackResult + Send + 'static,
+    P: PlaybackPcm + 'static,
+{
+    pub fn run_in_thread(mut self) -> Result<JoinHandle<()>, Box<dyn Error>> {
+        Ok(std::thread::Builder::new()
+            .name(self.params.thread_name("AlsaDataSender"))
+            .spawn(move || self.run_send_loop())?)
+    }
+
+    pub fn run_send_loop(&mut self) {
+        if let Some(thread_priority) = self.params.thread_priority {
+            set_current_thread_priority(thread_priority);
+        }
+        if let Some(core) = self.params.cpu_affinity {
+            set_current_thread_affinity(core);
+        }
+
+        while self.is_running.load(Ordering::SeqCst) {
+            if self.restart_request.swap(false, Ordering::SeqCst) {
+                self.restart();
+            }
+
+            let result = (self.callback)(&mut self.data_buffer, None);
+
+            self.output_buffer
+                .convert(&mut self.dither, &self.data_buffer);
+
+            let is_underrun = match unsafe {
+                write_frames(self.playback_device, &mut self.output_buffer, &self.params)
+            } {
+                Ok(is_underrun) => is_underrun,
+                Err(err) => {
+                    // The device cannot be fed anymore, the device reports that it is no longer playing.
+                    // The failure is expected when the device was stopped to be closed.
+                    if self.is_running.load(Ordering::SeqCst) {
+                        eprintln!("ALSA: {}", err);
+                    }
+                    break;
+                }
+            };
+            if is_underrun && self.params.adaptive_latency {
+                self.underrun_count += 1;
+                if self.underrun_count >= UNDERRUNS_PER_GROWTH
+                    && self.period_count < MAX_PERIOD_COUNT
+                {
+                    self.grow_buffer();
+                }
+            }
+
+            if result == CallbackResult::Stop {
+                // Wait until the last portion of samples is played and exit.
+                unsafe {
+                    self.playback_device.drain();
+                }
+                break;
+            }
+        }
+    }
+}
+
+/// Full-duplex device, the capture device is linked to the playback device so both of them are started at
+/// once and run with the same clock.
+pub struct AlsaDuplexDevice {
+    playback_device: *mut snd_pcm_t,
+    capture_device: *mut snd_pcm_t,
+    thread_handle: Option<JoinHandle<()>>,
+    is_running: Arc<AtomicBool>,
+}
+
+unsafe impl Send for AlsaDuplexDevice {}
+
+impl BaseAudioOutputDevice for AlsaDuplexDevice {
+    fn is_playing(&self) -> bool {
+        self.is_running.load(Ordering::SeqCst)
+    }
+
+    fn backend(&self) -> Backend {
+        Backend::Alsa
+    }
+
+    fn native_handle(&self) -> NativeHandle<'_> {
+        NativeHandle::Alsa(self.playback_device)
+    }
+}
+
+impl AlsaDuplexDevice {
+    pub fn new<C>(params: OutputDeviceParameters, data_callback: C) -> Result<Self, Box<dyn Error>>
+    where
+        C: FnMut(&[f32], &mut [f32]) -> CallbackResult + Send + 'static,
+    {
+        // The captured samples are 16-bit, so the played ones are too.
+        let params = OutputDeviceParameters {
+            sample_format: SampleFormat::I16,
+            ..params
+        };
+        unsafe {
+            let (playback_device, _) = open_pcm(SND_PCM_STREAM_PLAYBACK, &params)?;
+            let capture_device = match open_pcm(SND_PCM_STREAM_CAPTURE, &params) {
+                Ok((capture_device, _)) => capture_device,
+                Err(err) => {
+                    snd_pcm_close(playback_device);
+                    return Err(err);
+                }
+            };
+            if let Err(err) = check(snd_pcm_link(capture_device, playback_device)) {
+                snd_pcm_close(capture_device);
+                snd_pcm_close(playback_device);
+                return Err(err);
+            }
 
             let is_running = Arc::new(AtomicBool::new(true));
 
-            let thread_handle = DataSender {
+            let thread_handle = DuplexSender {
                 playback_device,
+                capture_device,
                 callback: data_callback,
-                data_buffer: vec![0.0f32; params.channel_sample_count * params.channels_count],
-                output_buffer: vec![0i16; params.channel_sample_count * params.channels_count],
+                input_samples: vec![0i16; params.total_samples()],
+                input_buffer: vec![0.0f32; params.total_samples()],
+                data_buffer: vec![0.0f32; params.total_samples()],
+                output_buffer: OutputBuffer::new(&params),
+                dither: Dither::new(params.dither, params.limiter),
                 is_running: is_running.clone(),
                 params,
             }
@@ -130,77 +906,368 @@ impl AudioOutputDevice for AlsaSoundDevice {
 
             Ok(Self {
                 playback_device,
-                is_running,
+                capture_device,
                 thread_handle: Some(thread_handle),
+                is_running,
             })
         }
     }
 }
 
-impl Drop for AlsaSoundDevice {
+impl Drop for AlsaDuplexDevice {
     fn drop(&mut self) {
         self.is_running.store(false, Ordering::SeqCst);
 
-        self.thread_handle
-            .take()
-            .expect("Alsa thread must exist!")
-            .join()
-            .unwrap();
+        // The feed thread could be blocked in a read or a write, stopping the linked devices wakes it up
+        // right away.
+        unsafe {
+            snd_pcm_drop(self.playback_device);
+        }
+
+        // The thread could have panicked in the data callback, the device must be released anyway.
+        if let Some(thread_handle) = self.thread_handle.take() {
+            let _ = thread_handle.join();
+        }
 
         unsafe {
+            snd_pcm_unlink(self.capture_device);
+            snd_pcm_close(self.capture_device);
             snd_pcm_close(self.playback_device);
         }
     }
 }
 
-struct DataSender<C> {
+struct DuplexSender<C> {
     playback_device: *mut snd_pcm_t,
+    capture_device: *mut snd_pcm_t,
     callback: C,
+    input_samples: Vec<i16>,
+    input_buffer: Vec<f32>,
     data_buffer: Vec<f32>,
-    output_buffer: Vec<i16>,
+    output_buffer: OutputBuffer,
+    dither: Dither,
     is_running: Arc<AtomicBool>,
     params: OutputDeviceParameters,
 }
 
-unsafe impl<C> Send for DataSender<C> {}
+unsafe impl<C> Send for DuplexSender<C> {}
 
-impl<C> DataSender<C>
+impl<C> Drop for DuplexSender<C> {
+    fn drop(&mut self) {
+        // The feed thread has exited (normally or because of a panic in the data callback), let the
+        // device know that it no longer plays anything.
+        self.is_running.store(false, Ordering::SeqCst);
+    }
+}
+
+impl<C> DuplexSender<C> {
+    /// Reads one buffer of samples from the capture device. A failed read is recovered and retried, up to
+    /// `LinuxParameters::xrun_recovery_attempts` times. Fails if the device cannot be recovered.
+    unsafe fn read_frames(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut attempts_left = self.params.linux.xrun_recovery_attempts;
+        let mut frames_read = 0;
+        while frames_read < self.params.channel_sample_count {
+            let err = snd_pcm_readi(
+                self.capture_device,
+                self.input_samples[frames_read * self.params.channels_count..].as_mut_ptr()
+                    as *mut _,
+                (self.params.channel_sample_count - frames_read) as ::std::os::raw::c_ulong,
+            ) as c_int;
+
+            // The read could be interrupted by a signal, then only a part of the frames is read.
+            if err >= 0 {
+                frames_read += err as usize;
+                continue;
+            }
+            if attempts_left == 0 {
+                return Err(format!(
+                    "Unable to capture the samples after {} attempts - {}",
+                    self.params.linux.xrun_recovery_attempts,
+                    err_code_to_string(err)
+                )
+                .into());
+            }
+            attempts_left -= 1;
+            let result = snd_pcm_recover(self.capture_device, err, 1);
+            if result < 0 {
+                return Err(format!(
+                    "Unable to recover the capture device - {}",
+                    err_code_to_string(result)
+                )
+                .into());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<C> DuplexSender<C>
 where
-    C: FnMut(&mut [f32]) + Send + 'static,
+    C: FnMut(&[f32], &mut [f32]) -> CallbackResult + Send + 'static,
 {
     pub fn run_in_thread(mut self) -> Result<JoinHandle<()>, Box<dyn Error>> {
         Ok(std::thread::Builder::new()
-            .name("AlsaDataSender".to_string())
+            .name(self.params.thread_name("AlsaDuplexSender"))
             .spawn(move || self.run_send_loop())?)
     }
 
     pub fn run_send_loop(&mut self) {
+        if let Some(thread_priority) = self.params.thread_priority {
+            set_current_thread_priority(thread_priority);
+        }
+        if let Some(core) = self.params.cpu_affinity {
+            set_current_thread_affinity(core);
+        }
+
+        // Prime the playback with one buffer of silence, so there's always one buffer in flight while the
+        // next one is captured. Playback starts the linked capture device as well.
+        if let Err(err) =
+            unsafe { write_frames(self.playback_device, &mut self.output_buffer, &self.params) }
+        {
+            eprintln!("ALSA: {}", err);
+            return;
+        }
+
         while self.is_running.load(Ordering::SeqCst) {
-            (self.callback)(&mut self.data_buffer);
+            if let Err(err) = unsafe { self.read_frames() } {
+                // The callback must not get the stale samples of the previous read, the device stops. The
+                // failure is expected when the device was stopped to be closed.
+                if self.is_running.load(Ordering::SeqCst) {
+                    eprintln!("ALSA: {}", err);
+                }
+                break;
+            }
+
+            convert_samples_i16_to_f32(&self.input_samples, &mut self.input_buffer);
 
-            debug_assert_eq!(self.data_buffer.len(), self.output_buffer.len());
-            for (in_sample, out_sample) in
-                self.data_buffer.iter().zip(self.output_buffer.iter_mut())
+            let result = (self.callback)(&self.input_buffer, &mut self.data_buffer);
+
+            self.output_buffer
+                .convert(&mut self.dither, &self.data_buffer);
+
+            if let Err(err) =
+                unsafe { write_frames(self.playback_device, &mut self.output_buffer, &self.params) }
             {
-                *out_sample = (*in_sample * i16::MAX as f32) as i16;
+                if self.is_running.load(Ordering::SeqCst) {
+                    eprintln!("ALSA: {}", err);
+                }
+                break;
             }
 
-            'try_loop: for _ in 0..10 {
+            if result == CallbackResult::Stop {
+                // Wait until the last portion of samples is played and exit.
                 unsafe {
-                    let err = snd_pcm_writei(
-                        self.playback_device,
-                        self.output_buffer.as_ptr() as *const _,
-                        self.params.channel_sample_count as ::std::os::raw::c_ulong,
-                    ) as i32;
-
-                    if err < 0 {
-                        // Try to recover from any errors and re-send data.
-                        snd_pcm_recover(self.playback_device, err, 1);
-                    } else {
-                        break 'try_loop;
-                    }
+                    snd_pcm_drain(self.playback_device);
                 }
+                break;
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        collections::VecDeque,
+        sync::{Condvar, Mutex},
+    };
+
+    /// Plays mono samples instantly. The results of the next writes could be overridden: an error code fails
+    /// the write, a positive value limits the amount of frames written. If `block_writes` is set, the writes
+    /// block until the device is stopped, like the writes to a real device that has no free space.
+    #[derive(Default)]
+    struct FakePcm {
+        state: Mutex<FakePcmState>,
+        state_changed: Condvar,
+    }
+
+    #[derive(Default)]
+    struct FakePcmState {
+        played: Vec<u8>,
+        write_results: VecDeque<snd_pcm_sframes_t>,
+        block_writes: bool,
+        is_write_blocked: bool,
+        is_stopped: bool,
+        is_closed: bool,
+    }
+
+    impl FakePcm {
+        fn push_write_result(&self, result: c_int) {
+            let mut state = self.state.lock().unwrap();
+            state.write_results.push_back(result as snd_pcm_sframes_t);
+        }
+
+        fn played_bytes(&self) -> Vec<u8> {
+            self.state.lock().unwrap().played.clone()
+        }
+
+        fn played(&self) -> Vec<i16> {
+            self.played_bytes()
+                .chunks_exact(2)
+                .map(|bytes| i16::from_ne_bytes([bytes[0], bytes[1]]))
+                .collect()
+        }
+    }
+
+    impl PlaybackPcm for &FakePcm {
+        unsafe fn write_interleaved(self, samples: &[u8], frame_count: usize) -> snd_pcm_sframes_t {
+            let mut state = self.state.lock().unwrap();
+            if state.block_writes {
+                state.is_write_blocked = true;
+                self.state_changed.notify_all();
+                while !state.is_stopped {
+                    state = self.state_changed.wait(state).unwrap();
+                }
+            }
+            if state.is_stopped {
+                return -libc::EBADFD as snd_pcm_sframes_t;
+            }
+            let frame_size = samples.len() / frame_count;
+            let frame_count = match state.write_results.pop_front() {
+                Some(result) if result < 0 => return result,
+                Some(result) => frame_count.min(result as usize),
+                None => frame_count,
+            };
+            state
+                .played
+                .extend_from_slice(&samples[..frame_count * frame_size]);
+            frame_count as snd_pcm_sframes_t
+        }
+
+        unsafe fn recover(self, _err: c_int) -> c_int {
+            0
+        }
+
+        unsafe fn drop_samples(self) {
+            self.state.lock().unwrap().is_stopped = true;
+            self.state_changed.notify_all();
+        }
+
+        unsafe fn drain(self) {}
+
+        unsafe fn prepare(self) -> c_int {
+            self.state.lock().unwrap().is_stopped = false;
+            0
+        }
+
+        unsafe fn configure(
+            self,
+            params: &OutputDeviceParameters,
+            period_count: usize,
+        ) -> Result<PcmConfiguration, Box<dyn Error>> {
+            Ok(PcmConfiguration {
+                period_size: params.channel_sample_count,
+                buffer_size: period_count * params.channel_sample_count,
+                sample_rate: params.sample_rate,
+            })
+        }
+
+        unsafe fn close(self) {
+            self.state.lock().unwrap().is_closed = true;
+        }
+    }
+
+    fn mono_params(recover_silence: bool) -> OutputDeviceParameters {
+        let mut params = OutputDeviceParameters {
+            channels_count: 1,
+            channel_sample_count: 4,
+            ..Default::default()
+        };
+        params.linux.recover_silence = recover_silence;
+        params
+    }
+
+    #[test]
+    fn test_late_samples_are_not_replayed() {
+        let pcm = FakePcm::default();
+        let params = mono_params(false);
+        unsafe {
+            assert!(!write_frames(&pcm, &mut OutputBuffer::I16(vec![1; 4]), &params).unwrap());
+            // The callback took too long, the device has underrun meanwhile.
+            pcm.push_write_result(-libc::EPIPE);
+            assert!(write_frames(&pcm, &mut OutputBuffer::I16(vec![2; 4]), &params).unwrap());
+            assert!(!write_frames(&pcm, &mut OutputBuffer::I16(vec![3; 4]), &params).unwrap());
+        }
+        assert_eq!(pcm.played(), [1, 1, 1, 1, 3, 3, 3, 3]);
+    }
+
+    #[test]
+    fn test_recover_with_silence() {
+        let pcm = FakePcm::default();
+        let params = mono_params(true);
+        let mut late_samples = OutputBuffer::I16(vec![2; 4]);
+        unsafe {
+            assert!(!write_frames(&pcm, &mut OutputBuffer::I16(vec![1; 4]), &params).unwrap());
+            pcm.push_write_result(-libc::EPIPE);
+            assert!(write_frames(&pcm, &mut late_samples, &params).unwrap());
+            assert!(!write_frames(&pcm, &mut OutputBuffer::I16(vec![3; 4]), &params).unwrap());
+        }
+        assert_eq!(late_samples, OutputBuffer::I16(vec![0; 4]));
+        assert_eq!(pcm.played(), [1, 1, 1, 1, 0, 0, 0, 0, 3, 3, 3, 3]);
+    }
+
+    #[test]
+    fn test_recover_with_u8_silence() {
+        let pcm = FakePcm::default();
+        let mut params = mono_params(true);
+        params.sample_format = SampleFormat::U8;
+        let mut samples = OutputBuffer::new(&params);
+        samples.convert(&mut Dither::new(params.dither, params.limiter), &[-1.0; 4]);
+        unsafe {
+            pcm.push_write_result(-libc::EPIPE);
+            assert!(write_frames(&pcm, &mut samples, &params).unwrap());
+        }
+        assert_eq!(samples, OutputBuffer::U8(vec![128; 4]));
+        assert_eq!(pcm.played_bytes(), [128; 4]);
+    }
+
+    #[test]
+    fn test_short_write_is_completed() {
+        let pcm = FakePcm::default();
+        pcm.push_write_result(1);
+        pcm.push_write_result(2);
+        unsafe {
+            let mut samples = OutputBuffer::I16(vec![1, 2, 3, 4]);
+            assert!(!write_frames(&pcm, &mut samples, &mono_params(false)).unwrap());
+        }
+        assert_eq!(pcm.played(), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_unrecoverable_write_fails() {
+        let pcm = FakePcm::default();
+        pcm.push_write_result(-libc::EIO);
+        unsafe {
+            let mut samples = OutputBuffer::I16(vec![1; 4]);
+            assert!(write_frames(&pcm, &mut samples, &mono_params(false)).is_err());
+        }
+        assert!(pcm.played().is_empty());
+    }
+
+    #[test]
+    fn test_close_wakes_up_blocked_write() {
+        // The feed thread borrows the device until it exits.
+        let pcm: &'static FakePcm = Box::leak(Box::default());
+        pcm.state.lock().unwrap().block_writes = true;
+        let params = mono_params(false);
+        let configuration = unsafe { pcm.configure(&params, PERIOD_COUNT).unwrap() };
+        let device = AlsaSoundDevice::start(pcm, configuration, params, |data, _| {
+            data.fill(0.5);
+            CallbackResult::Continue
+        })
+        .unwrap();
+
+        let mut state = pcm.state.lock().unwrap();
+        while !state.is_write_blocked {
+            state = pcm.state_changed.wait(state).unwrap();
+        }
+        drop(state);
+
+        // `OutputDevice::close` drops the backend device, it must not wait for the blocked write forever.
+        drop(device);
+        let state = pcm.state.lock().unwrap();
+        assert!(state.is_stopped);
+        assert!(state.is_closed);
+    }
+}
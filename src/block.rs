@@ -0,0 +1,177 @@
+//! Repartitioning of the samples between the periods of the device and the blocks of the data callback.
+
+#![cfg(any(
+    test,
+    target_os = "android",
+    all(target_os = "linux", feature = "jack")
+))]
+
+use crate::{CallbackResult, OutputDeviceParameters};
+
+/// Adapts the natural period of the device to the fixed block size of the data callback. The callback always
+/// renders exactly `channel_sample_count` frames, and the blocks are split across (or joined within) the
+/// periods of any size. The blocks that fit into the period completely are rendered right into it, so there's
+/// no extra copy when the period is a multiple of the block.
+pub struct BlockAdapter {
+    block: Vec<f32>,
+    channels_count: usize,
+    // Position (in frames) of the next frame to send from the block.
+    position: usize,
+    // The callback has asked to stop, the block contains the last portion of samples.
+    is_last_block: bool,
+}
+
+impl BlockAdapter {
+    pub fn new(params: &OutputDeviceParameters) -> Self {
+        Self {
+            block: vec![0.0; params.total_samples()],
+            channels_count: params.channels_count,
+            // Force the first period to render a new block.
+            position: params.channel_sample_count,
+            is_last_block: false,
+        }
+    }
+
+    fn block_frame_count(&self) -> usize {
+        self.block.len() / self.channels_count
+    }
+
+    /// Fills the interleaved `period` of any length with the blocks rendered by `callback`. Once the last
+    /// block is sent, the rest of the period is silent. Returns [`CallbackResult::Stop`] only when the last
+    /// block was sent completely by the previous calls, so the period contains nothing but silence and the
+    /// device could be stopped without losing any samples.
+    pub fn fill<C>(&mut self, period: &mut [f32], mut callback: C) -> CallbackResult
+    where
+        C: FnMut(&mut [f32]) -> CallbackResult,
+    {
+        let channels_count = self.channels_count;
+        let block_frame_count = self.block_frame_count();
+
+        if self.is_last_block && self.position == block_frame_count {
+            period.fill(0.0);
+            return CallbackResult::Stop;
+        }
+
+        let frame_count = period.len() / channels_count;
+        let mut written = 0;
+        while written < frame_count {
+            if self.position == block_frame_count {
+                if self.is_last_block {
+                    period[written * channels_count..].fill(0.0);
+                    break;
+                }
+
+                // The whole block fits, the device buffer takes it as is. The block stays sent completely.
+                if frame_count - written >= block_frame_count {
+                    let block = &mut period
+                        [written * channels_count..(written + block_frame_count) * channels_count];
+                    if callback(block) == CallbackResult::Stop {
+                        self.is_last_block = true;
+                    }
+                    written += block_frame_count;
+                    continue;
+                }
+
+                if callback(&mut self.block) == CallbackResult::Stop {
+                    self.is_last_block = true;
+                }
+                self.position = 0;
+            }
+
+            let count = (frame_count - written).min(block_frame_count - self.position);
+            period[written * channels_count..(written + count) * channels_count].copy_from_slice(
+                &self.block
+                    [self.position * channels_count..(self.position + count) * channels_count],
+            );
+
+            written += count;
+            self.position += count;
+        }
+
+        CallbackResult::Continue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK_FRAME_COUNT: usize = 4;
+    const BLOCK_COUNT: usize = 5;
+
+    /// Fills periods of `period_frame_count` frames with the blocks of stereo frames numbered from 1, the
+    /// callback stops after [`BLOCK_COUNT`] blocks. Returns the frame numbers and the results of each call.
+    fn fill_periods(period_frame_count: usize) -> (Vec<f32>, Vec<CallbackResult>) {
+        let params = OutputDeviceParameters {
+            channels_count: 2,
+            channel_sample_count: BLOCK_FRAME_COUNT,
+            ..Default::default()
+        };
+        let mut adapter = BlockAdapter::new(&params);
+        let mut frame_number = 0.0;
+        let mut blocks_left = BLOCK_COUNT;
+        let mut callback = |block: &mut [f32]| {
+            assert_eq!(block.len(), params.total_samples());
+            for frame in block.chunks_exact_mut(2) {
+                frame_number += 1.0;
+                frame.fill(frame_number);
+            }
+            blocks_left -= 1;
+            if blocks_left == 0 {
+                CallbackResult::Stop
+            } else {
+                CallbackResult::Continue
+            }
+        };
+
+        let mut frames = Vec::new();
+        let mut results = Vec::new();
+        let mut period = vec![-1.0; period_frame_count * 2];
+        loop {
+            let result = adapter.fill(&mut period, &mut callback);
+            frames.extend(period.chunks_exact(2).map(|frame| {
+                assert_eq!(frame[0], frame[1]);
+                frame[0]
+            }));
+            results.push(result);
+            if result == CallbackResult::Stop {
+                return (frames, results);
+            }
+        }
+    }
+
+    fn check_periods(period_frame_count: usize) {
+        let (frames, results) = fill_periods(period_frame_count);
+        let total_frame_count = BLOCK_COUNT * BLOCK_FRAME_COUNT;
+        let expected = (1..=total_frame_count).map(|number| number as f32);
+        assert!(frames[..total_frame_count].iter().copied().eq(expected));
+        assert!(frames[total_frame_count..]
+            .iter()
+            .all(|&frame| frame == 0.0));
+        // The period after the one with the last sample is silent and the only one that stops.
+        let stopped_period = (total_frame_count + period_frame_count - 1) / period_frame_count;
+        assert_eq!(results.len(), stopped_period + 1);
+        assert!(results[..stopped_period]
+            .iter()
+            .all(|&result| result == CallbackResult::Continue));
+    }
+
+    #[test]
+    fn test_period_smaller_than_block() {
+        check_periods(1);
+        check_periods(3);
+    }
+
+    #[test]
+    fn test_period_equal_to_block() {
+        check_periods(BLOCK_FRAME_COUNT);
+    }
+
+    #[test]
+    fn test_period_larger_than_block() {
+        // Multiples of the block are rendered right into the period, the others are split too.
+        check_periods(2 * BLOCK_FRAME_COUNT);
+        check_periods(7);
+        check_periods(100);
+    }
+}
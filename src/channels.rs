@@ -0,0 +1,353 @@
+//! Channel layouts and channel mixing, that maps the samples rendered for one channel count to another.
+
+/// Gain of the center and surround channels when they're mixed into the front channels, `-3 dB` as
+/// recommended by ITU-R BS.775.
+const ITU_GAIN: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+/// A matrix that maps interleaved frames with `source_channels` channels to frames with `output_channels`
+/// channels. Each output sample is a weighted sum of the samples of the source frame.
+///
+/// Surround channels are expected in the standard (WAVE) order: front left, front right, front center, low
+/// frequency effects, back left, back right, side left, side right.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChannelMatrix {
+    source_channels: usize,
+    output_channels: usize,
+    // Row-major: `output_channels` rows of `source_channels` coefficients.
+    coefficients: Vec<f32>,
+}
+
+impl ChannelMatrix {
+    /// Creates a custom matrix. `coefficients` must contain `output_channels` rows of `source_channels`
+    /// coefficients each, the row `i` holds the gains of the source channels that are mixed into the output
+    /// channel `i`. Returns `None` if the amount of coefficients does not match.
+    pub fn new(
+        source_channels: usize,
+        output_channels: usize,
+        coefficients: Vec<f32>,
+    ) -> Option<Self> {
+        if source_channels == 0
+            || output_channels == 0
+            || coefficients.len() != source_channels * output_channels
+        {
+            return None;
+        }
+
+        Some(Self {
+            source_channels,
+            output_channels,
+            coefficients,
+        })
+    }
+
+    /// Creates a sensible matrix for the conversion between the given channel counts:
+    ///
+    /// - 5.1 and 7.1 to stereo use the ITU-R BS.775 downmix (center and surround channels at `-3 dB`, the low
+    ///   frequency effects channel is dropped).
+    /// - Mono is copied to both front channels (or to the only output channel).
+    /// - Downmix to mono averages all source channels.
+    /// - Any other conversion maps the channels one-to-one, extra source channels are dropped and extra
+    ///   output channels are silent.
+    pub fn default_for(source_channels: usize, output_channels: usize) -> Self {
+        let source = source_channels.max(1);
+        let output = output_channels.max(1);
+        let mut coefficients = vec![0.0; source * output];
+        let mut set = |output_channel: usize, source_channel: usize, gain: f32| {
+            coefficients[output_channel * source + source_channel] = gain;
+        };
+
+        match (source, output) {
+            (6, 2) | (8, 2) => {
+                // Front channels, then center, then back (and side) channels. LFE is dropped.
+                for (output_channel, surround_channels) in [(0, [4, 6]), (1, [5, 7])] {
+                    set(output_channel, output_channel, 1.0);
+                    set(output_channel, 2, ITU_GAIN);
+                    for &surround_channel in surround_channels.iter() {
+                        if surround_channel < source {
+                            set(output_channel, surround_channel, ITU_GAIN);
+                        }
+                    }
+                }
+            }
+            (1, _) => {
+                for output_channel in 0..output.min(2) {
+                    set(output_channel, 0, 1.0);
+                }
+            }
+            (_, 1) => {
+                for source_channel in 0..source {
+                    set(0, source_channel, 1.0 / source as f32);
+                }
+            }
+            _ => {
+                for channel in 0..source.min(output) {
+                    set(channel, channel, 1.0);
+                }
+            }
+        }
+
+        Self {
+            source_channels: source,
+            output_channels: output,
+            coefficients,
+        }
+    }
+
+    /// Returns the amount of channels in a source frame.
+    pub fn source_channels(&self) -> usize {
+        self.source_channels
+    }
+
+    /// Returns the amount of channels in an output frame.
+    pub fn output_channels(&self) -> usize {
+        self.output_channels
+    }
+
+    /// Mixes the interleaved `source` frames into the interleaved `output` frames. Only the frames that
+    /// present in both buffers are processed.
+    pub fn apply(&self, source: &[f32], output: &mut [f32]) {
+        for (source_frame, output_frame) in source
+            .chunks_exact(self.source_channels)
+            .zip(output.chunks_exact_mut(self.output_channels))
+        {
+            for (output_sample, row) in output_frame
+                .iter_mut()
+                .zip(self.coefficients.chunks_exact(self.source_channels))
+            {
+                *output_sample = source_frame
+                    .iter()
+                    .zip(row)
+                    .map(|(sample, gain)| sample * gain)
+                    .sum();
+            }
+        }
+    }
+}
+
+/// A speaker position, the names and order follow the WAVE channel mask (`dwChannelMask`).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Speaker {
+    /// Front left speaker.
+    FrontLeft,
+    /// Front right speaker.
+    FrontRight,
+    /// Front center speaker.
+    FrontCenter,
+    /// Low frequency effects (subwoofer).
+    LowFrequency,
+    /// Back (rear) left speaker.
+    BackLeft,
+    /// Back (rear) right speaker.
+    BackRight,
+    /// Front speaker between the left and the center ones.
+    FrontLeftOfCenter,
+    /// Front speaker between the right and the center ones.
+    FrontRightOfCenter,
+    /// Back center speaker.
+    BackCenter,
+    /// Side left speaker.
+    SideLeft,
+    /// Side right speaker.
+    SideRight,
+}
+
+impl Speaker {
+    /// Returns the bit of the speaker in the WAVE channel mask. CoreAudio channel bitmap uses the same bits.
+    pub fn mask(self) -> u32 {
+        match self {
+            Speaker::FrontLeft => 0x1,
+            Speaker::FrontRight => 0x2,
+            Speaker::FrontCenter => 0x4,
+            Speaker::LowFrequency => 0x8,
+            Speaker::BackLeft => 0x10,
+            Speaker::BackRight => 0x20,
+            Speaker::FrontLeftOfCenter => 0x40,
+            Speaker::FrontRightOfCenter => 0x80,
+            Speaker::BackCenter => 0x100,
+            Speaker::SideLeft => 0x200,
+            Speaker::SideRight => 0x400,
+        }
+    }
+}
+
+/// Assignment of the interleaved channels to the speakers. See
+/// [`crate::OutputDeviceParameters::channel_layout`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ChannelLayout {
+    /// Front center speaker only.
+    Mono,
+    /// Front left and right speakers.
+    Stereo,
+    /// Front left, front right, back left and back right speakers.
+    Quad,
+    /// Front left, front right, front center, low frequency, back left and back right speakers.
+    FivePointOne,
+    /// Front left, front right, front center, low frequency, back left, back right, side left and side right
+    /// speakers.
+    SevenPointOne,
+    /// Custom assignment, the channel `i` is played by the speaker `i` of the slice. Speakers must be listed
+    /// in the order of their [`Speaker::mask`] bits, since that's how the operating systems map the
+    /// channels.
+    Custom(&'static [Speaker]),
+}
+
+impl ChannelLayout {
+    /// Returns the standard layout for the given amount of channels, if there's one.
+    pub fn for_channels_count(channels_count: usize) -> Option<Self> {
+        match channels_count {
+            1 => Some(ChannelLayout::Mono),
+            2 => Some(ChannelLayout::Stereo),
+            4 => Some(ChannelLayout::Quad),
+            6 => Some(ChannelLayout::FivePointOne),
+            8 => Some(ChannelLayout::SevenPointOne),
+            _ => None,
+        }
+    }
+
+    /// Returns the speakers of the layout, in the order of channels.
+    pub fn speakers(&self) -> &'static [Speaker] {
+        use Speaker::*;
+        match self {
+            ChannelLayout::Mono => &[FrontCenter],
+            ChannelLayout::Stereo => &[FrontLeft, FrontRight],
+            ChannelLayout::Quad => &[FrontLeft, FrontRight, BackLeft, BackRight],
+            ChannelLayout::FivePointOne => &[
+                FrontLeft,
+                FrontRight,
+                FrontCenter,
+                LowFrequency,
+                BackLeft,
+                BackRight,
+            ],
+            ChannelLayout::SevenPointOne => &[
+                FrontLeft,
+                FrontRight,
+                FrontCenter,
+                LowFrequency,
+                BackLeft,
+                BackRight,
+                SideLeft,
+                SideRight,
+            ],
+            ChannelLayout::Custom(speakers) => speakers,
+        }
+    }
+
+    /// Returns the amount of channels in the layout.
+    pub fn channels_count(&self) -> usize {
+        self.speakers().len()
+    }
+
+    /// Returns the WAVE channel mask (`dwChannelMask`) of the layout.
+    pub fn channel_mask(&self) -> u32 {
+        self.speakers()
+            .iter()
+            .fold(0, |mask, speaker| mask | speaker.mask())
+    }
+}
+
+/// Interleaves the samples of separate channels (`planar[0]` is the first channel, and so on) into the
+/// `output` frames laid out like so: `[ch0 ch1 ...][ch0 ch1 ...]...`. Only the frames that present in all the
+/// buffers are processed.
+///
+/// ## Examples
+///
+/// ```rust
+/// # use tinyaudio::interleave;
+/// let mut output = [0.0; 4];
+/// interleave(&[&[1.0, 2.0], &[3.0, 4.0]], &mut output);
+/// assert_eq!(output, [1.0, 3.0, 2.0, 4.0]);
+/// ```
+pub fn interleave(planar: &[&[f32]], output: &mut [f32]) {
+    if planar.is_empty() {
+        return;
+    }
+
+    for (frame_index, frame) in output.chunks_exact_mut(planar.len()).enumerate() {
+        if planar.iter().any(|channel| frame_index >= channel.len()) {
+            break;
+        }
+        for (sample, channel) in frame.iter_mut().zip(planar) {
+            *sample = channel[frame_index];
+        }
+    }
+}
+
+/// Splits the interleaved frames of `channels_count` channels into separate buffers, one per channel
+/// (`output[0]` receives the first channel, and so on). The channels without an output buffer are skipped.
+/// Only the frames that present in all the buffers are processed. `channels_count` must be greater than
+/// zero.
+///
+/// ## Examples
+///
+/// ```rust
+/// # use tinyaudio::deinterleave;
+/// let (mut left, mut right) = ([0.0; 2], [0.0; 2]);
+/// deinterleave(&[1.0, 3.0, 2.0, 4.0], 2, &mut [&mut left, &mut right]);
+/// assert_eq!((left, right), ([1.0, 2.0], [3.0, 4.0]));
+/// ```
+pub fn deinterleave(interleaved: &[f32], channels_count: usize, output: &mut [&mut [f32]]) {
+    for (frame_index, frame) in interleaved.chunks_exact(channels_count).enumerate() {
+        if output.iter().any(|channel| frame_index >= channel.len()) {
+            break;
+        }
+        for (sample, channel) in frame.iter().zip(output.iter_mut()) {
+            channel[frame_index] = *sample;
+        }
+    }
+}
+
+/// Splits the interleaved frames of `channels_count` channels into the single `planar` buffer laid out like
+/// so: `[ch0 samples...][ch1 samples...]...`, that is what planar APIs (such as `AudioBuffer` of `WebAudio`)
+/// usually expect. Unlike [`deinterleave`], it does not need a separate buffer per channel, so it could be
+/// used in a data callback without allocating. Only the whole frames that fit into `planar` are processed.
+/// `channels_count` must be greater than zero.
+///
+/// ## Examples
+///
+/// ```rust
+/// # use tinyaudio::deinterleave_planar;
+/// let mut planar = [0.0; 4];
+/// deinterleave_planar(&[1.0, 3.0, 2.0, 4.0], 2, &mut planar);
+/// assert_eq!(planar, [1.0, 2.0, 3.0, 4.0]);
+/// ```
+pub fn deinterleave_planar(interleaved: &[f32], channels_count: usize, planar: &mut [f32]) {
+    let channel_sample_count = interleaved.len().min(planar.len()) / channels_count;
+    for (frame_index, frame) in interleaved
+        .chunks_exact(channels_count)
+        .take(channel_sample_count)
+        .enumerate()
+    {
+        for (channel_index, sample) in frame.iter().enumerate() {
+            planar[channel_index * channel_sample_count + frame_index] = *sample;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{deinterleave, deinterleave_planar, interleave};
+
+    #[test]
+    fn test_deinterleave_planar() {
+        let interleaved = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let mut planar = [0.0; 6];
+        deinterleave_planar(&interleaved, 3, &mut planar);
+        assert_eq!(planar, [1.0, 4.0, 2.0, 5.0, 3.0, 6.0]);
+
+        // Agrees with the buffer-per-channel form.
+        let (mut a, mut b, mut c) = ([0.0; 2], [0.0; 2], [0.0; 2]);
+        deinterleave(&interleaved, 3, &mut [&mut a, &mut b, &mut c]);
+        assert_eq!([a, b, c].concat(), planar);
+    }
+
+    #[test]
+    fn test_interleave_round_trip() {
+        let interleaved = [1.0, 2.0, 3.0, 4.0];
+        let (mut left, mut right) = ([0.0; 2], [0.0; 2]);
+        deinterleave(&interleaved, 2, &mut [&mut left, &mut right]);
+        let mut output = [0.0; 4];
+        interleave(&[&left, &right], &mut output);
+        assert_eq!(output, interleaved);
+    }
+}
@@ -0,0 +1,129 @@
+//! Chunk-style output, where the device plays the chunks of samples sent over a bounded channel.
+
+use crate::{run_output_device, CallbackResult, OutputDevice, OutputDeviceParameters};
+use std::{
+    error::Error,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, SendError, SyncSender, TryRecvError, TrySendError},
+        Arc,
+    },
+};
+
+/// A sender of the chunks of samples to the output device created by [`run_output_device_channel`]. Dropping
+/// the sender ends the stream once the queued chunks are played.
+#[derive(Debug)]
+pub struct ChunkSender {
+    sender: SyncSender<Vec<f32>>,
+    underrun_count: Arc<AtomicUsize>,
+}
+
+impl ChunkSender {
+    /// Sends the chunk of interleaved samples to the device, blocks while the channel is full. Fails only if
+    /// the device was closed, the chunk is returned back then.
+    pub fn send(&self, chunk: Vec<f32>) -> Result<(), SendError<Vec<f32>>> {
+        self.sender.send(chunk)
+    }
+
+    /// Sends the chunk of interleaved samples to the device without blocking. Fails with
+    /// [`TrySendError::Full`] if the producer is ahead of the device, the chunk is returned back to try later.
+    pub fn try_send(&self, chunk: Vec<f32>) -> Result<(), TrySendError<Vec<f32>>> {
+        self.sender.try_send(chunk)
+    }
+
+    /// Returns how many buffers of the device were not filled completely, because the channel was empty. The
+    /// missing samples are played as silence. The buffers before the first chunk are not counted.
+    pub fn underrun_count(&self) -> usize {
+        self.underrun_count.load(Ordering::SeqCst)
+    }
+}
+
+/// Plays the received chunks one after another, the chunks could have any length.
+struct ChunkReceiver {
+    receiver: Receiver<Vec<f32>>,
+    chunk: Vec<f32>,
+    // Position of the next sample to play in the chunk.
+    position: usize,
+    has_started: bool,
+    underrun_count: Arc<AtomicUsize>,
+}
+
+impl ChunkReceiver {
+    fn fill(&mut self, data: &mut [f32]) -> CallbackResult {
+        let mut written = 0;
+        while written < data.len() {
+            if self.position == self.chunk.len() {
+                match self.receiver.try_recv() {
+                    Ok(chunk) => {
+                        self.chunk = chunk;
+                        self.position = 0;
+                        self.has_started = true;
+                        continue;
+                    }
+                    Err(TryRecvError::Empty) => {
+                        if self.has_started {
+                            self.underrun_count.fetch_add(1, Ordering::SeqCst);
+                        }
+                        break;
+                    }
+                    // The sender was dropped and every chunk was played, the rest is silent.
+                    Err(TryRecvError::Disconnected) => return CallbackResult::Stop,
+                }
+            }
+
+            let count = (data.len() - written).min(self.chunk.len() - self.position);
+            data[written..written + count]
+                .copy_from_slice(&self.chunk[self.position..self.position + count]);
+            written += count;
+            self.position += count;
+        }
+        CallbackResult::Continue
+    }
+}
+
+/// Creates a new output device, that plays the chunks of interleaved samples sent to the returned
+/// [`ChunkSender`]. The channel holds at most `capacity` chunks, so the producer is naturally throttled
+/// by the device: [`ChunkSender::send`] blocks and [`ChunkSender::try_send`] fails while the channel is full.
+/// Zero `capacity` makes each send wait until the device takes the chunk. Unlike
+/// [`crate::run_output_device_push`], the samples are not copied into a fixed ring buffer, so the chunks
+/// could be of any size. The chunks should contain whole frames, otherwise the channels are swapped after
+/// an underrun. The device plays silence when the channel is empty (see [`ChunkSender::underrun_count`])
+/// and stops after the sender is dropped and the queued chunks are played. On WebAssembly the device is fed
+/// from the main thread, so use `try_send` there, `send` would block it forever once the channel is full.
+///
+/// ## Examples
+///
+/// ```rust,no_run
+/// # use tinyaudio::prelude::*;
+/// let params = OutputDeviceParameters::default();
+///
+/// let (_device, sender) = run_output_device_channel(params, 4).unwrap();
+///
+/// // Send ten seconds of silence in chunks of 100 ms, `send` blocks while the device is behind.
+/// for _ in 0..100 {
+///     let chunk = vec![0.0; params.sample_rate / 10 * params.channels_count];
+///     sender.send(chunk).unwrap();
+/// }
+/// ```
+pub fn run_output_device_channel(
+    params: OutputDeviceParameters,
+    capacity: usize,
+) -> Result<(OutputDevice, ChunkSender), Box<dyn Error>> {
+    let (sender, receiver) = mpsc::sync_channel(capacity);
+    let underrun_count = Arc::new(AtomicUsize::new(0));
+    let mut chunk_receiver = ChunkReceiver {
+        receiver,
+        chunk: Vec::new(),
+        position: 0,
+        has_started: false,
+        underrun_count: underrun_count.clone(),
+    };
+    let device = run_output_device(params, move |data| chunk_receiver.fill(data))?;
+    Ok((
+        device,
+        ChunkSender {
+            sender,
+            underrun_count,
+        },
+    ))
+}
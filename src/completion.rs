@@ -0,0 +1,172 @@
+//! Awaitable completion of the playback, for async applications (`async` feature).
+
+#![cfg(feature = "async")]
+
+use crate::{
+    run_output_device, CallbackResult, IntoCallbackResult, MaybeSend, OutputDevice,
+    OutputDeviceParameters,
+};
+use std::{
+    error::Error,
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Waker},
+};
+
+// State of the oneshot channel between the feed thread and the future.
+struct Completion {
+    is_complete: bool,
+    waker: Option<Waker>,
+}
+
+type SharedCompletion = Arc<Mutex<Completion>>;
+
+/// Sending half of the oneshot channel, completes the playback when dropped. Dropping it (instead of an
+/// explicit send) makes sure the future resolves even if the data callback is destroyed without stopping.
+struct CompletionSender {
+    completion: SharedCompletion,
+}
+
+impl Drop for CompletionSender {
+    fn drop(&mut self) {
+        let mut completion = self.completion.lock().unwrap();
+        completion.is_complete = true;
+        if let Some(waker) = completion.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// A handle that stops the playback started by [`run_output_device_until_complete`]. Could be cloned and
+/// sent to other threads.
+#[derive(Clone, Debug)]
+pub struct StopHandle {
+    is_stop_requested: Arc<AtomicBool>,
+}
+
+impl StopHandle {
+    /// Asks the device to stop. The data callback is not called anymore and the [`PlaybackCompletion`]
+    /// resolves on the next portion of samples.
+    pub fn stop(&self) {
+        self.is_stop_requested.store(true, Ordering::SeqCst);
+    }
+}
+
+/// A future that resolves when the data callback has returned [`CallbackResult::Stop`], when the
+/// [`StopHandle`] was triggered, or when the output device was dropped. It is runtime-agnostic, so it works
+/// with any async executor.
+///
+/// The future resolves as soon as the last portion of samples is handed over to the device, so keep the
+/// device alive for [`OutputDeviceParameters::buffer_duration`] to let it play the last portion.
+pub struct PlaybackCompletion {
+    completion: SharedCompletion,
+    stop_handle: StopHandle,
+}
+
+impl PlaybackCompletion {
+    /// Returns a handle that could be used to stop the playback explicitly.
+    pub fn stop_handle(&self) -> StopHandle {
+        self.stop_handle.clone()
+    }
+}
+
+impl fmt::Debug for PlaybackCompletion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let is_complete = self
+            .completion
+            .lock()
+            .map_or(false, |completion| completion.is_complete);
+        f.debug_struct("PlaybackCompletion")
+            .field("is_complete", &is_complete)
+            .finish()
+    }
+}
+
+impl Future for PlaybackCompletion {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut completion = self.completion.lock().unwrap();
+        if completion.is_complete {
+            Poll::Ready(())
+        } else {
+            completion.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+/// The same as [`run_output_device`], but also returns a [`PlaybackCompletion`] future, that could be
+/// awaited instead of blocking a thread until the sound is over. Requires `async` feature.
+///
+/// ## Examples
+///
+/// ```rust,no_run
+/// # use tinyaudio::prelude::*;
+/// async fn play_beep() {
+///     let params = OutputDeviceParameters::default();
+///
+///     let mut frames_left = params.sample_rate;
+///     let (_device, completion) = run_output_device_until_complete(params, move |data| {
+///         for frame in data.chunks_mut(params.channels_count) {
+///             frames_left = frames_left.saturating_sub(1);
+///             frame.fill(if frames_left % 100 < 50 { 0.5 } else { -0.5 });
+///         }
+///         if frames_left == 0 {
+///             CallbackResult::Stop
+///         } else {
+///             CallbackResult::Continue
+///         }
+///     })
+///     .unwrap();
+///
+///     completion.await;
+/// }
+/// ```
+pub fn run_output_device_until_complete<C, R>(
+    params: OutputDeviceParameters,
+    mut data_callback: C,
+) -> Result<(OutputDevice, PlaybackCompletion), Box<dyn Error>>
+where
+    C: FnMut(&mut [f32]) -> R + MaybeSend + 'static,
+    R: IntoCallbackResult,
+{
+    let completion = Arc::new(Mutex::new(Completion {
+        is_complete: false,
+        waker: None,
+    }));
+    let stop_handle = StopHandle {
+        is_stop_requested: Arc::new(AtomicBool::new(false)),
+    };
+
+    let device = run_output_device(params, {
+        let mut sender = Some(CompletionSender {
+            completion: completion.clone(),
+        });
+        let stop_handle = stop_handle.clone();
+        move |data| {
+            let result = if stop_handle.is_stop_requested.load(Ordering::SeqCst) {
+                CallbackResult::Stop
+            } else {
+                data_callback(data).into_callback_result()
+            };
+            if result == CallbackResult::Stop {
+                sender.take();
+            }
+            result
+        }
+    })?;
+
+    Ok((
+        device,
+        PlaybackCompletion {
+            completion,
+            stop_handle,
+        },
+    ))
+}
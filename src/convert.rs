@@ -0,0 +1,250 @@
+//! Sample format conversion for the backends that output integer samples.
+
+#![cfg(not(all(target_os = "unknown", target_arch = "wasm32")))]
+
+use crate::DitherMode;
+
+/// Converts the samples in `[-1.0, 1.0]` range to `i16` using [`convert_f32_to_i16`].
+pub fn convert_samples_f32_to_i16(input: &[f32], output: &mut [i16]) {
+    debug_assert_eq!(input.len(), output.len());
+
+    for (in_sample, out_sample) in input.iter().zip(output.iter_mut()) {
+        *out_sample = convert_f32_to_i16(*in_sample);
+    }
+}
+
+/// Converts a sample in `[-1.0, 1.0]` range to `i16`, rounding to the nearest value. Out-of-range samples are
+/// clamped, so a callback that overshoots produces clipping instead of loud clicks. NaN is converted to
+/// silence. Rounding makes `i16` samples (see [`crate::Sample`]) pass through the `f32` buffer losslessly.
+#[inline(always)]
+pub fn convert_f32_to_i16(sample: f32) -> i16 {
+    let scaled = sample * i16::MAX as f32;
+    // `as` truncates towards zero, saturates out-of-range values (infinities too) and converts NaN to zero.
+    (scaled + 0.5f32.copysign(scaled)) as i16
+}
+
+/// Converts a sample in `[-1.0, 1.0]` range to unsigned 8-bit PCM centered at 128, rounding to the nearest
+/// value. The scale is 128, the same as of `u8` samples of [`crate::Sample`], so -1.0 is 0 and they pass
+/// through the `f32` buffer losslessly, 1.0 is clamped to 255. NaN is converted to silence.
+#[inline(always)]
+pub fn convert_f32_to_u8(sample: f32) -> u8 {
+    if sample.is_nan() {
+        128
+    } else {
+        ((sample * 128.0).round().clamp(-128.0, 127.0) + 128.0) as u8
+    }
+}
+
+/// Magnitude above which [`soft_clip`] starts to compress the samples.
+const LIMITER_THRESHOLD: f32 = 0.75;
+
+/// Soft limiter, the samples below [`LIMITER_THRESHOLD`] in magnitude pass through unchanged, the louder ones
+/// are mapped onto the remaining headroom with `tanh`. The curve is continuous and monotonic with a
+/// continuous slope at the threshold, so over-unity signals never reach 1.0 and never click. NaN is kept as
+/// is, the conversion turns it into silence.
+#[inline(always)]
+pub fn soft_clip(sample: f32) -> f32 {
+    let magnitude = sample.abs();
+    if magnitude > LIMITER_THRESHOLD {
+        let headroom = 1.0 - LIMITER_THRESHOLD;
+        let limited =
+            LIMITER_THRESHOLD + headroom * ((magnitude - LIMITER_THRESHOLD) / headroom).tanh();
+        limited.copysign(sample)
+    } else {
+        sample
+    }
+}
+
+/// Converts `f32` samples to `i16` with the noise of the given [`DitherMode`] added to each sample. Keeps the
+/// state of the noise generator between the buffers. Optionally passes the samples through [`soft_clip`]
+/// first.
+pub struct Dither {
+    mode: DitherMode,
+    limiter: bool,
+    // State of the xorshift generator, never zero.
+    state: u32,
+}
+
+impl Dither {
+    pub fn new(mode: DitherMode, limiter: bool) -> Self {
+        Self {
+            mode,
+            limiter,
+            state: 0x9e37_79b9,
+        }
+    }
+
+    /// Applies [`soft_clip`] to the sample if the limiter is enabled.
+    #[inline(always)]
+    pub fn limit(&self, sample: f32) -> f32 {
+        if self.limiter {
+            soft_clip(sample)
+        } else {
+            sample
+        }
+    }
+
+    /// Returns the next uniformly distributed value in `[-0.5, 0.5)` range.
+    #[inline(always)]
+    fn next_uniform(&mut self) -> f32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        // The upper 24 bits fit into the mantissa of `f32` exactly.
+        (self.state >> 8) as f32 / (1 << 24) as f32 - 0.5
+    }
+
+    /// Converts the samples in `[-1.0, 1.0]` range to `i16`, the noise (in the units of the least significant
+    /// bit) is added before rounding. Without dithering and limiting it is the same as
+    /// [`convert_samples_f32_to_i16`].
+    pub fn convert(&mut self, input: &[f32], output: &mut [i16]) {
+        debug_assert_eq!(input.len(), output.len());
+
+        let (has_noise, is_triangular) = match self.mode {
+            DitherMode::None if !self.limiter => return convert_samples_f32_to_i16(input, output),
+            DitherMode::None => (false, false),
+            DitherMode::Rectangular => (true, false),
+            DitherMode::Triangular => (true, true),
+        };
+
+        for (in_sample, out_sample) in input.iter().zip(output.iter_mut()) {
+            // The sum of two uniform values has a triangular distribution in `[-1.0, 1.0)` range.
+            let mut noise = 0.0;
+            if has_noise {
+                noise += self.next_uniform();
+            }
+            if is_triangular {
+                noise += self.next_uniform();
+            }
+
+            let sample = self.limit(*in_sample);
+            *out_sample = if sample.is_nan() {
+                0
+            } else {
+                (sample * i16::MAX as f32 + noise)
+                    .round()
+                    .clamp(i16::MIN as f32, i16::MAX as f32) as i16
+            };
+        }
+    }
+
+    /// Converts the samples in `[-1.0, 1.0]` range to unsigned 8-bit PCM with [`convert_f32_to_u8`]. The
+    /// limiter is applied, but the noise is not, the samples are just rounded.
+    pub fn convert_u8(&self, input: &[f32], output: &mut [u8]) {
+        debug_assert_eq!(input.len(), output.len());
+
+        for (in_sample, out_sample) in input.iter().zip(output.iter_mut()) {
+            *out_sample = convert_f32_to_u8(self.limit(*in_sample));
+        }
+    }
+}
+
+/// Converts `i16` samples to `f32` samples in `[-1.0, 1.0]` range. Used for the captured samples.
+#[cfg(target_os = "linux")]
+pub fn convert_samples_i16_to_f32(input: &[i16], output: &mut [f32]) {
+    debug_assert_eq!(input.len(), output.len());
+
+    for (in_sample, out_sample) in input.iter().zip(output.iter_mut()) {
+        *out_sample = *in_sample as f32 / i16::MAX as f32;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert_f32_to_i16_clamps() {
+        assert_eq!(convert_f32_to_i16(0.0), 0);
+        assert_eq!(convert_f32_to_i16(1.0), i16::MAX);
+        assert_eq!(convert_f32_to_i16(-1.0), -i16::MAX);
+        assert_eq!(convert_f32_to_i16(2.0), i16::MAX);
+        assert_eq!(convert_f32_to_i16(-2.0), i16::MIN);
+        assert_eq!(convert_f32_to_i16(f32::INFINITY), i16::MAX);
+        assert_eq!(convert_f32_to_i16(f32::NEG_INFINITY), i16::MIN);
+        assert_eq!(convert_f32_to_i16(f32::NAN), 0);
+    }
+
+    #[test]
+    fn test_convert_f32_to_i16_is_lossless() {
+        for value in i16::MIN..=i16::MAX {
+            assert_eq!(convert_f32_to_i16(value as f32 / i16::MAX as f32), value);
+        }
+    }
+
+    #[test]
+    fn test_convert_samples_f32_to_i16() {
+        // Not a multiple of any vector width, the tail must be converted too.
+        let input = [
+            1.0,
+            -1.0,
+            2.0,
+            -2.0,
+            f32::NAN,
+            0.5,
+            -0.5,
+            0.0,
+            1.5,
+            -1.5,
+            f32::NAN,
+        ];
+        let mut output = [1; 11];
+        convert_samples_f32_to_i16(&input, &mut output);
+        for (in_sample, out_sample) in input.iter().zip(output.iter()) {
+            assert_eq!(*out_sample, convert_f32_to_i16(*in_sample));
+        }
+        assert_eq!(output[10], 0);
+    }
+
+    #[test]
+    fn test_soft_clip_is_monotonic_and_click_free() {
+        let step = 1.0 / 4096.0;
+        let inputs = (-8192..=8192).map(|i| i as f32 * step).collect::<Vec<_>>();
+        let outputs = inputs.iter().map(|&x| soft_clip(x)).collect::<Vec<_>>();
+        for (pair, output) in inputs.windows(2).zip(outputs.windows(2)) {
+            assert!(output[1] >= output[0], "not monotonic at {}", pair[0]);
+            // The slope never exceeds 1, so the curve has no jumps.
+            assert!(output[1] - output[0] <= pair[1] - pair[0] + f32::EPSILON);
+        }
+        for (&input, &output) in inputs.iter().zip(outputs.iter()) {
+            assert!(output.abs() < 1.0);
+            assert_eq!(soft_clip(-input), -output);
+            if input.abs() <= LIMITER_THRESHOLD {
+                assert_eq!(output, input);
+            }
+        }
+        assert!(soft_clip(f32::NAN).is_nan());
+    }
+
+    #[test]
+    fn test_limiter_never_clips() {
+        let input = (-2048..=2048)
+            .map(|i| i as f32 / 1024.0)
+            .collect::<Vec<_>>();
+        let mut output = vec![0; input.len()];
+        Dither::new(DitherMode::None, true).convert(&input, &mut output);
+        assert!(output.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(output
+            .iter()
+            .all(|&sample| sample > -i16::MAX && sample < i16::MAX));
+    }
+
+    #[test]
+    fn test_convert_f32_to_u8() {
+        assert_eq!(convert_f32_to_u8(-1.0), 0);
+        assert_eq!(convert_f32_to_u8(0.0), 128);
+        assert_eq!(convert_f32_to_u8(1.0), 255);
+        assert_eq!(convert_f32_to_u8(-2.0), 0);
+        assert_eq!(convert_f32_to_u8(2.0), 255);
+        assert_eq!(convert_f32_to_u8(f32::NAN), 128);
+    }
+
+    #[test]
+    fn test_convert_f32_to_u8_is_lossless() {
+        use crate::Sample;
+
+        for value in u8::MIN..=u8::MAX {
+            assert_eq!(convert_f32_to_u8(value.to_f32()), value);
+        }
+    }
+}
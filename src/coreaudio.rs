@@ -2,11 +2,40 @@
 
 #![cfg(any(target_os = "macos", target_os = "ios"))]
 
-use crate::{AudioOutputDevice, BaseAudioOutputDevice, OutputDeviceParameters};
+#[cfg(not(feature = "coreaudio-f32"))]
+use crate::convert::Dither;
+#[cfg(target_os = "macos")]
+use crate::{
+    device::{COMMON_SAMPLE_RATES, MAX_CHANNELS_COUNT},
+    DeviceInfo, TinyAudioError,
+};
+use crate::{
+    AudioOutputDevice, Backend, BaseAudioOutputDevice, CallbackResult, ChannelLayout,
+    CoreAudioRunLoop, NativeHandle, OutputDeviceParameters,
+};
+use core_foundation_sys::runloop::{kCFRunLoopCommonModes, CFRunLoopGetCurrent, CFRunLoopGetMain};
 use coreaudio_sys::*;
-use std::{error::Error, ffi::c_void, mem::size_of};
+#[cfg(target_os = "macos")]
+use std::sync::Mutex;
+use std::{
+    error::Error,
+    ffi::c_void,
+    mem::size_of,
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::atomic::{AtomicBool, Ordering},
+};
 
+#[cfg(not(feature = "coreaudio-f32"))]
 type NativeSample = i16;
+// The system converts float samples to the format of the hardware (if needed at all).
+#[cfg(feature = "coreaudio-f32")]
+type NativeSample = f32;
+
+#[cfg(not(feature = "coreaudio-f32"))]
+const FORMAT_FLAGS: AudioFormatFlags =
+    kLinearPCMFormatFlagIsSignedInteger | kLinearPCMFormatFlagIsPacked;
+#[cfg(feature = "coreaudio-f32")]
+const FORMAT_FLAGS: AudioFormatFlags = kLinearPCMFormatFlagIsFloat | kLinearPCMFormatFlagIsPacked;
 
 pub struct CoreaudioSoundDevice {
     // Keep send context alive while the device is alive.
@@ -17,15 +46,25 @@ pub struct CoreaudioSoundDevice {
 unsafe impl Send for CoreaudioSoundDevice {}
 
 struct SendContext {
-    data_callback: Box<dyn FnMut(&mut [f32]) + Send + 'static>,
+    data_callback: Box<dyn FnMut(&mut [f32], Option<f64>) -> CallbackResult + Send + 'static>,
+    #[cfg(not(feature = "coreaudio-f32"))]
     out_data: Vec<NativeSample>,
+    #[cfg(not(feature = "coreaudio-f32"))]
+    dither: Dither,
+    #[cfg(not(feature = "coreaudio-f32"))]
     mix_buffer: Vec<f32>,
     queue: AudioQueueRef,
     bufs: [AudioQueueBufferRef; 2],
+    sample_rate: f64,
+    // The callback has asked to stop, the queue plays the remaining buffers and stops.
+    is_stopping: bool,
+    // Cleared as soon as the shutdown starts, the queue callback must not touch the data callback then.
+    is_running: AtomicBool,
 }
 
 impl Drop for SendContext {
     fn drop(&mut self) {
+        self.is_running.store(false, Ordering::SeqCst);
         unsafe {
             AudioQueueStop(self.queue, true as u8);
             // Dispose audio queue and all of its resources, including its buffers
@@ -42,47 +81,354 @@ fn check(error: OSStatus, msg: &str) -> Result<(), Box<dyn Error>> {
     }
 }
 
+/// Returns the current time (in seconds) of the queue timeline. It is unavailable until the queue has
+/// started playing.
+unsafe fn current_time(queue: AudioQueueRef, sample_rate: f64) -> Option<f64> {
+    let mut time_stamp: AudioTimeStamp = std::mem::zeroed();
+    let res = AudioQueueGetCurrentTime(
+        queue,
+        std::ptr::null_mut(),
+        &mut time_stamp,
+        std::ptr::null_mut(),
+    );
+    if res == noErr as i32 && time_stamp.mFlags & kAudioTimeStampSampleTimeValid != 0 {
+        Some(time_stamp.mSampleTime / sample_rate)
+    } else {
+        None
+    }
+}
+
 unsafe extern "C" fn audio_queue_callback(
     user_data: *mut c_void,
     queue: AudioQueueRef,
     buf: AudioQueueBufferRef,
 ) {
-    let inner: &mut SendContext = &mut *(user_data as *mut SendContext);
+    let buffer_len_bytes = (*buf).mAudioDataByteSize as usize;
 
-    let buffer_len_bytes = inner.out_data.len() * size_of::<NativeSample>();
+    // The device is being dropped, the queue could still call back until it is stopped. Only the atomic flag
+    // is read, keep the queue fed with silence.
+    if !(*(user_data as *const SendContext))
+        .is_running
+        .load(Ordering::SeqCst)
+    {
+        std::ptr::write_bytes((*buf).mAudioData as *mut u8, 0u8, buffer_len_bytes);
+        AudioQueueEnqueueBuffer(queue, buf, 0, std::ptr::null_mut());
+        return;
+    }
 
-    (inner.data_callback)(&mut inner.mix_buffer);
+    let inner: &mut SendContext = &mut *(user_data as *mut SendContext);
 
-    // Convert i16 -> f32
-    debug_assert_eq!(inner.mix_buffer.len(), inner.out_data.len());
-    for (in_sample, out_sample) in inner.mix_buffer.iter().zip(inner.out_data.iter_mut()) {
-        *out_sample = (*in_sample * i16::MAX as f32) as i16;
+    if inner.is_stopping {
+        return;
     }
 
-    // set the buffer data
-    let src = inner.out_data.as_mut_ptr() as *mut u8;
-    let dst = (*buf).mAudioData as *const u8 as *mut u8;
-    std::ptr::copy_nonoverlapping(src, dst, buffer_len_bytes);
+    // The float format is rendered right into the buffer of the queue, the integer one is converted from
+    // the mix buffer.
+    #[cfg(feature = "coreaudio-f32")]
+    let output = std::slice::from_raw_parts_mut(
+        (*buf).mAudioData as *mut f32,
+        buffer_len_bytes / size_of::<f32>(),
+    );
+    #[cfg(not(feature = "coreaudio-f32"))]
+    let output = &mut inner.mix_buffer;
+
+    let device_time = current_time(queue, inner.sample_rate);
+    // Unwinding into the audio queue is undefined behavior, a panicking callback stops the device instead.
+    // The panic message is printed by the panic hook, `is_playing` returns `false` after the queue stops.
+    let data_callback = &mut inner.data_callback;
+    let result = match catch_unwind(AssertUnwindSafe(|| data_callback(output, device_time))) {
+        Ok(result) => result,
+        Err(_) => {
+            output.fill(0.0);
+            CallbackResult::Stop
+        }
+    };
+
+    // Convert f32 -> i16 and set the buffer data.
+    #[cfg(not(feature = "coreaudio-f32"))]
+    {
+        inner.dither.convert(&inner.mix_buffer, &mut inner.out_data);
+        let dst = (*buf).mAudioData as *const u8 as *mut u8;
+        std::ptr::copy_nonoverlapping(inner.out_data.as_ptr() as *const u8, dst, buffer_len_bytes);
+    }
 
     AudioQueueEnqueueBuffer(queue, buf, 0, std::ptr::null_mut());
+
+    if result == CallbackResult::Stop {
+        // Asynchronous stop lets the queue play all the enqueued buffers before stopping, flushing
+        // ensures that nothing is left in the queue when it stops.
+        inner.is_stopping = true;
+        AudioQueueFlush(queue);
+        AudioQueueStop(queue, false as u8);
+    }
+}
+
+/// Reads the variable-sized property of the default output device into a vector of `T`.
+#[cfg(target_os = "macos")]
+unsafe fn default_output_device_property<T: Copy>(
+    selector: AudioObjectPropertySelector,
+    scope: AudioObjectPropertyScope,
+    zero: T,
+) -> Option<Vec<T>> {
+    let mut address = AudioObjectPropertyAddress {
+        mSelector: kAudioHardwarePropertyDefaultOutputDevice,
+        mScope: kAudioObjectPropertyScopeGlobal,
+        mElement: kAudioObjectPropertyElementMaster,
+    };
+    let mut device_id: AudioObjectID = kAudioObjectUnknown;
+    let mut size = size_of::<AudioObjectID>() as u32;
+    let res = AudioObjectGetPropertyData(
+        kAudioObjectSystemObject,
+        &address,
+        0,
+        std::ptr::null(),
+        &mut size,
+        &mut device_id as *mut AudioObjectID as *mut c_void,
+    );
+    if res != noErr as i32 || device_id == kAudioObjectUnknown {
+        return None;
+    }
+
+    address.mSelector = selector;
+    address.mScope = scope;
+    let res = AudioObjectGetPropertyDataSize(device_id, &address, 0, std::ptr::null(), &mut size);
+    if res != noErr as i32 {
+        return None;
+    }
+
+    // Round up, so the variable-sized structures will fit entirely.
+    let mut values = vec![zero; (size as usize + size_of::<T>() - 1) / size_of::<T>()];
+    let res = AudioObjectGetPropertyData(
+        device_id,
+        &address,
+        0,
+        std::ptr::null(),
+        &mut size,
+        values.as_mut_ptr() as *mut c_void,
+    );
+    if res != noErr as i32 {
+        return None;
+    }
+    values.truncate(size as usize / size_of::<T>());
+    Some(values)
+}
+
+/// Converts the string to UTF-8 and releases it.
+#[cfg(target_os = "macos")]
+unsafe fn take_cf_string(string: CFStringRef) -> Option<String> {
+    use core_foundation_sys::{base::CFRelease, string::*};
+
+    if string.is_null() {
+        return None;
+    }
+    let string = string as core_foundation_sys::string::CFStringRef;
+    let size =
+        CFStringGetMaximumSizeForEncoding(CFStringGetLength(string), kCFStringEncodingUTF8) + 1;
+    let mut buffer = vec![0u8; size as usize];
+    let is_converted = CFStringGetCString(
+        string,
+        buffer.as_mut_ptr() as *mut _,
+        size,
+        kCFStringEncodingUTF8,
+    ) != 0;
+    CFRelease(string as *const c_void);
+    if !is_converted {
+        return None;
+    }
+    buffer.truncate(buffer.iter().position(|&byte| byte == 0).unwrap_or(0));
+    String::from_utf8(buffer).ok()
 }
 
-impl BaseAudioOutputDevice for CoreaudioSoundDevice {}
+#[cfg(target_os = "macos")]
+pub fn default_output_device_info() -> Result<DeviceInfo, TinyAudioError> {
+    unsafe {
+        let name = default_output_device_property(
+            kAudioObjectPropertyName,
+            kAudioObjectPropertyScopeGlobal,
+            std::ptr::null() as CFStringRef,
+        )
+        .and_then(|names| names.first().copied())
+        .ok_or_else(|| {
+            TinyAudioError::DeviceUnavailable("There's no default output device".to_string())
+        })?;
+        let name = take_cf_string(name).unwrap_or_else(|| "default".to_string());
+
+        let default_sample_rate = default_output_device_property(
+            kAudioDevicePropertyNominalSampleRate,
+            kAudioObjectPropertyScopeGlobal,
+            0.0f64,
+        )
+        .and_then(|rates| rates.first().map(|&rate| rate.round() as usize));
+
+        Ok(DeviceInfo::new_default(
+            "default".to_string(),
+            name,
+            supported_channel_counts().last().copied(),
+            default_sample_rate,
+        ))
+    }
+}
+
+#[cfg(target_os = "macos")]
+pub fn supported_sample_rates() -> Vec<usize> {
+    let ranges = unsafe {
+        default_output_device_property(
+            kAudioDevicePropertyAvailableNominalSampleRates,
+            kAudioObjectPropertyScopeGlobal,
+            AudioValueRange {
+                mMinimum: 0.0,
+                mMaximum: 0.0,
+            },
+        )
+    };
+
+    ranges
+        .map(|ranges| {
+            COMMON_SAMPLE_RATES
+                .iter()
+                .copied()
+                .filter(|&rate| {
+                    ranges
+                        .iter()
+                        .any(|range| range.mMinimum <= rate as f64 && rate as f64 <= range.mMaximum)
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(target_os = "macos")]
+pub fn supported_channel_counts() -> Vec<usize> {
+    // The stream configuration is an `AudioBufferList`, a buffer of `u64` keeps it properly aligned.
+    let buffer_list = unsafe {
+        default_output_device_property(
+            kAudioDevicePropertyStreamConfiguration,
+            kAudioObjectPropertyScopeOutput,
+            0u64,
+        )
+    };
+
+    buffer_list
+        .map(|buffer_list| unsafe {
+            let buffer_list = &*(buffer_list.as_ptr() as *const AudioBufferList);
+            let buffers = std::slice::from_raw_parts(
+                buffer_list.mBuffers.as_ptr(),
+                buffer_list.mNumberBuffers as usize,
+            );
+            let channels_count: usize = buffers
+                .iter()
+                .map(|buffer| buffer.mNumberChannels as usize)
+                .sum();
+            (1..=channels_count.min(MAX_CHANNELS_COUNT)).collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(target_os = "macos")]
+type DefaultDeviceChangedCallback = Box<dyn FnMut() + Send + 'static>;
+
+#[cfg(target_os = "macos")]
+const DEFAULT_OUTPUT_DEVICE_ADDRESS: AudioObjectPropertyAddress = AudioObjectPropertyAddress {
+    mSelector: kAudioHardwarePropertyDefaultOutputDevice,
+    mScope: kAudioObjectPropertyScopeGlobal,
+    mElement: kAudioObjectPropertyElementMaster,
+};
+
+/// Listens to the changes of `kAudioHardwarePropertyDefaultOutputDevice`, the listener is removed on drop.
+#[cfg(target_os = "macos")]
+pub struct DefaultDeviceListener {
+    // The listener could be called from any thread, the box keeps the address passed to CoreAudio stable.
+    callback: Box<Mutex<DefaultDeviceChangedCallback>>,
+}
+
+#[cfg(target_os = "macos")]
+unsafe extern "C" fn default_device_listener_proc(
+    _object_id: AudioObjectID,
+    _addresses_count: u32,
+    _addresses: *const AudioObjectPropertyAddress,
+    user_data: *mut c_void,
+) -> OSStatus {
+    let callback = &*(user_data as *const Mutex<DefaultDeviceChangedCallback>);
+    if let Ok(mut callback) = callback.lock() {
+        (callback)();
+    }
+    noErr as OSStatus
+}
+
+#[cfg(target_os = "macos")]
+impl DefaultDeviceListener {
+    pub fn new(callback: DefaultDeviceChangedCallback) -> Result<Self, Box<dyn Error>> {
+        let listener = Self {
+            callback: Box::new(Mutex::new(callback)),
+        };
+        let res = unsafe {
+            AudioObjectAddPropertyListener(
+                kAudioObjectSystemObject,
+                &DEFAULT_OUTPUT_DEVICE_ADDRESS,
+                Some(default_device_listener_proc),
+                listener.user_data(),
+            )
+        };
+        check(res, "Failed to `AudioObjectAddPropertyListener`")?;
+        Ok(listener)
+    }
+
+    fn user_data(&self) -> *mut c_void {
+        &*self.callback as *const Mutex<DefaultDeviceChangedCallback> as *mut c_void
+    }
+}
+
+#[cfg(target_os = "macos")]
+impl Drop for DefaultDeviceListener {
+    fn drop(&mut self) {
+        unsafe {
+            AudioObjectRemovePropertyListener(
+                kAudioObjectSystemObject,
+                &DEFAULT_OUTPUT_DEVICE_ADDRESS,
+                Some(default_device_listener_proc),
+                self.user_data(),
+            );
+        }
+    }
+}
+
+impl BaseAudioOutputDevice for CoreaudioSoundDevice {
+    fn is_playing(&self) -> bool {
+        let mut is_running: u32 = 0;
+        let mut size = size_of::<u32>() as u32;
+        let res = unsafe {
+            AudioQueueGetProperty(
+                self.inner.queue,
+                kAudioQueueProperty_IsRunning,
+                &mut is_running as *mut u32 as *mut c_void,
+                &mut size,
+            )
+        };
+        res == 0 && is_running != 0 && self.inner.is_running.load(Ordering::SeqCst)
+    }
+
+    fn backend(&self) -> Backend {
+        Backend::CoreAudio
+    }
+
+    fn native_handle(&self) -> NativeHandle<'_> {
+        NativeHandle::CoreAudio(self.inner.queue)
+    }
+}
 
 impl AudioOutputDevice for CoreaudioSoundDevice {
     fn new<C>(params: OutputDeviceParameters, data_callback: C) -> Result<Self, Box<dyn Error>>
     where
-        C: FnMut(&mut [f32]) + Send + 'static,
+        C: FnMut(&mut [f32], Option<f64>) -> CallbackResult + Send + 'static,
     {
-        let buffer_len_bytes =
-            params.channel_sample_count * params.channels_count * size_of::<NativeSample>();
+        let buffer_len_bytes = params.total_samples() * size_of::<NativeSample>();
 
-        // 16-bit linear PCM
+        // 16-bit integer or 32-bit float linear PCM
         let desc = AudioStreamBasicDescription {
             mSampleRate: params.sample_rate as f64,
             mFormatID: kAudioFormatLinearPCM,
-            mFormatFlags: kLinearPCMFormatFlagIsSignedInteger | kLinearPCMFormatFlagIsPacked,
-            mBitsPerChannel: 16,
+            mFormatFlags: FORMAT_FLAGS,
+            mBitsPerChannel: (size_of::<NativeSample>() * 8) as u32,
             mFramesPerPacket: 1,
             mChannelsPerFrame: params.channels_count as u32,
             mBytesPerFrame: (params.channels_count * size_of::<NativeSample>()) as u32,
@@ -93,12 +439,28 @@ impl AudioOutputDevice for CoreaudioSoundDevice {
         // create data at fixed memory location
         let mut inner = Box::new(SendContext {
             data_callback: Box::new(data_callback),
-            out_data: vec![0i16; params.channel_sample_count * params.channels_count],
-            mix_buffer: vec![0.0; params.channel_sample_count * params.channels_count],
+            #[cfg(not(feature = "coreaudio-f32"))]
+            out_data: vec![0i16; params.total_samples()],
+            #[cfg(not(feature = "coreaudio-f32"))]
+            dither: Dither::new(params.dither, params.limiter),
+            #[cfg(not(feature = "coreaudio-f32"))]
+            mix_buffer: vec![0.0; params.total_samples()],
             queue: std::ptr::null_mut(),
             bufs: [std::ptr::null_mut(); 2],
+            sample_rate: params.sample_rate as f64,
+            is_stopping: false,
+            is_running: AtomicBool::new(true),
         });
 
+        // Null run loop means the internal thread of the queue.
+        let (run_loop, run_loop_mode) = unsafe {
+            match params.core_audio.run_loop {
+                CoreAudioRunLoop::Internal => (std::ptr::null_mut(), std::ptr::null()),
+                CoreAudioRunLoop::Current => (CFRunLoopGetCurrent(), kCFRunLoopCommonModes),
+                CoreAudioRunLoop::Main => (CFRunLoopGetMain(), kCFRunLoopCommonModes),
+            }
+        };
+
         inner.queue = {
             let mut queue = std::ptr::null_mut();
             let res = unsafe {
@@ -107,8 +469,8 @@ impl AudioOutputDevice for CoreaudioSoundDevice {
                     Some(self::audio_queue_callback),
                     // `user_data` passed to ^ (`self::audio_queue_callback`)
                     (&mut *inner) as *const SendContext as *const c_void as *mut c_void,
-                    std::ptr::null_mut(),
-                    std::ptr::null(),
+                    run_loop as _,
+                    run_loop_mode as _,
                     0,
                     &mut queue,
                 )
@@ -122,6 +484,28 @@ impl AudioOutputDevice for CoreaudioSoundDevice {
             queue
         };
 
+        // More than two channels must be explicitly assigned to the speakers. CoreAudio channel bitmap
+        // uses the same bits as the WAVE channel mask.
+        if params.channels_count > 2 || params.channel_layout.is_some() {
+            if let Some(channel_layout) = params
+                .channel_layout
+                .or_else(|| ChannelLayout::for_channels_count(params.channels_count))
+            {
+                let mut layout: AudioChannelLayout = unsafe { std::mem::zeroed() };
+                layout.mChannelLayoutTag = kAudioChannelLayoutTag_UseChannelBitmap;
+                layout.mChannelBitmap = channel_layout.channel_mask();
+                let res = unsafe {
+                    AudioQueueSetProperty(
+                        inner.queue,
+                        kAudioQueueProperty_ChannelLayout,
+                        &layout as *const AudioChannelLayout as *const c_void,
+                        size_of::<AudioChannelLayout>() as u32,
+                    )
+                };
+                check(res, "Failed to set `kAudioQueueProperty_ChannelLayout`")?;
+            }
+        }
+
         // create two audio buffers
         for i in 0..2 {
             inner.bufs[i] = {
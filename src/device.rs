@@ -0,0 +1,271 @@
+//! Information about output devices.
+
+use crate::{MaybeSend, TinyAudioError};
+use std::{error::Error, fmt, str::FromStr};
+
+/// Sample rates that are checked when querying the supported sample rates of a device.
+#[allow(dead_code)]
+pub(crate) const COMMON_SAMPLE_RATES: [usize; 11] = [
+    8000, 11025, 16000, 22050, 32000, 44100, 48000, 88200, 96000, 176400, 192000,
+];
+
+/// Maximum amount of channels that is checked when querying the supported channel counts of a device.
+#[allow(dead_code)]
+pub(crate) const MAX_CHANNELS_COUNT: usize = 32;
+
+/// Information about an output device of your operating system. Use [`default_output_device`] to get the
+/// default one.
+#[derive(Clone, Debug)]
+pub struct DeviceInfo {
+    // The name the backend opens the device by, it could differ from the human-readable name.
+    id: String,
+    name: String,
+    is_default: bool,
+    channels_count: Option<usize>,
+    default_sample_rate: Option<usize>,
+}
+
+impl DeviceInfo {
+    pub(crate) fn new(
+        id: String,
+        name: String,
+        is_default: bool,
+        channels_count: Option<usize>,
+        default_sample_rate: Option<usize>,
+    ) -> Self {
+        Self {
+            id,
+            name,
+            is_default,
+            channels_count,
+            default_sample_rate,
+        }
+    }
+
+    pub(crate) fn new_default(
+        id: String,
+        name: String,
+        channels_count: Option<usize>,
+        default_sample_rate: Option<usize>,
+    ) -> Self {
+        Self::new(id, name, true, channels_count, default_sample_rate)
+    }
+
+    /// Returns the identifier the backend opens the device by (for example, `default` for ALSA, or the id
+    /// reported by the browser on WebAssembly). Unlike [`Self::name`], it is not meant to be shown to users.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Returns the human-readable name of the device (for example, the name of a headset), if the backend
+    /// reports it. Otherwise, it is the name the backend opens the device by (`default`).
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the amount of channels of the device, if the backend reports it. Only CoreAudio (macOS) and
+    /// Windows report it, other backends accept (and mix) any channel count, see
+    /// [`Self::supported_channel_counts`].
+    pub fn channels_count(&self) -> Option<usize> {
+        self.channels_count
+    }
+
+    /// Returns the sample rate (in Hz) the device currently runs at, if the backend reports it. Only
+    /// CoreAudio (macOS) and Windows report it, other backends resample the samples as needed.
+    pub fn default_sample_rate(&self) -> Option<usize> {
+        self.default_sample_rate
+    }
+
+    /// Returns `true` if this is the default output device of your operating system. The device returned by
+    /// [`default_output_device`] always refers to the current default one, even if it was changed after the
+    /// info was queried.
+    pub fn is_default(&self) -> bool {
+        self.is_default
+    }
+
+    /// Finds the output device by its human-readable name (case-insensitive) or by its id, for example, to
+    /// pick the device from a command line flag. Fails with [`TinyAudioError::DeviceUnavailable`] if there's
+    /// no such device. Only the default output device is known on native platforms, since the crate does not
+    /// enumerate the devices there (see `web_output_devices` for WebAssembly).
+    pub fn by_name(name: &str) -> Result<DeviceInfo, TinyAudioError> {
+        let device = default_output_device()?;
+        if device.name.eq_ignore_ascii_case(name) || device.id == name {
+            Ok(device)
+        } else {
+            Err(TinyAudioError::DeviceUnavailable(format!(
+                "There's no output device named `{}`",
+                name
+            )))
+        }
+    }
+
+    /// Returns `true` if the device is held by another application (or by another device of this process), so
+    /// it cannot be opened right now. Opening it fails with [`TinyAudioError::DeviceBusy`] then. Only ALSA
+    /// (Linux) could be queried, it is always `false` on other platforms.
+    #[allow(clippy::needless_return)]
+    pub fn is_in_use(&self) -> bool {
+        #[cfg(target_os = "linux")]
+        {
+            return crate::alsa::is_device_in_use(&self.id);
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            false
+        }
+    }
+
+    /// Returns the sample rates (in Hz) supported by the device, only the common values (from 8000 Hz to
+    /// 192000 Hz) are checked. The list is empty if the backend cannot tell which sample rates are
+    /// supported (or the device is busy), it does not mean that the device cannot be opened.
+    #[allow(clippy::needless_return)]
+    pub fn supported_sample_rates(&self) -> Vec<usize> {
+        #[cfg(target_os = "linux")]
+        {
+            return crate::alsa::supported_sample_rates(&self.id);
+        }
+
+        #[cfg(target_os = "macos")]
+        {
+            return crate::coreaudio::supported_sample_rates();
+        }
+
+        #[cfg(all(target_os = "unknown", target_arch = "wasm32"))]
+        {
+            return crate::web::supported_sample_rates();
+        }
+
+        #[cfg(not(any(
+            target_os = "linux",
+            target_os = "macos",
+            all(target_os = "unknown", target_arch = "wasm32")
+        )))]
+        {
+            Vec::new()
+        }
+    }
+
+    /// Returns the channel counts supported by the device, up to 32 channels. The list is empty if the
+    /// backend cannot tell which channel counts are supported (or the device is busy), it does not mean that
+    /// the device cannot be opened.
+    #[allow(clippy::needless_return)]
+    pub fn supported_channel_counts(&self) -> Vec<usize> {
+        #[cfg(target_os = "linux")]
+        {
+            return crate::alsa::supported_channel_counts(&self.id);
+        }
+
+        #[cfg(target_os = "macos")]
+        {
+            return crate::coreaudio::supported_channel_counts();
+        }
+
+        #[cfg(all(target_os = "unknown", target_arch = "wasm32"))]
+        {
+            return (1..=MAX_CHANNELS_COUNT).collect();
+        }
+
+        #[cfg(not(any(
+            target_os = "linux",
+            target_os = "macos",
+            all(target_os = "unknown", target_arch = "wasm32")
+        )))]
+        {
+            Vec::new()
+        }
+    }
+}
+
+/// Shows the name and the id of the device, for example, `Default ALSA Output (default)`.
+impl fmt::Display for DeviceInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.name, self.id)
+    }
+}
+
+/// Finds the device with [`DeviceInfo::by_name`].
+impl FromStr for DeviceInfo {
+    type Err = TinyAudioError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Self::by_name(name)
+    }
+}
+
+/// Returns the information about the default output device of your operating system. This is the device
+/// that is used by [`crate::run_output_device`]. The device is queried without opening a stream, so it could
+/// be used to pre-populate the settings. Fails with [`TinyAudioError::DeviceUnavailable`] if there's no
+/// default output device.
+#[allow(clippy::needless_return)]
+pub fn default_output_device() -> Result<DeviceInfo, TinyAudioError> {
+    #[cfg(target_os = "linux")]
+    {
+        return Ok(DeviceInfo::new_default(
+            "default".to_string(),
+            crate::alsa::device_description("default").unwrap_or_else(|| "default".to_string()),
+            None,
+            None,
+        ));
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        return crate::coreaudio::default_output_device_info();
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        return crate::mmdevice::default_output_device_info();
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+    {
+        Ok(DeviceInfo::new_default(
+            "default".to_string(),
+            "default".to_string(),
+            None,
+            None,
+        ))
+    }
+}
+
+/// Returns the audio outputs reported by the browser via `navigator.mediaDevices.enumerateDevices`, use
+/// [`DeviceInfo::id`] of one of them with [`crate::OutputDevice::set_sink_id`]. The names of the devices are
+/// empty (the ids are used instead) until the page is allowed to access the media devices, for example, by
+/// `getUserMedia`. Fails with [`TinyAudioError::DeviceUnavailable`] if the browser does not support the
+/// enumeration. Only available on WebAssembly.
+#[cfg(all(target_os = "unknown", target_arch = "wasm32"))]
+pub async fn web_output_devices() -> Result<Vec<DeviceInfo>, TinyAudioError> {
+    crate::web::output_devices().await
+}
+
+/// Keeps the listener of the default output device changes registered, the listener is removed when it is
+/// dropped.
+pub(crate) type DefaultDeviceListener = Box<dyn Send>;
+
+/// Registers the callback that is called when the default output device of the operating system changes.
+/// Returns `None` if the platform does not report such changes.
+pub(crate) fn listen_default_output_device<F>(
+    callback: F,
+) -> Result<Option<DefaultDeviceListener>, Box<dyn Error>>
+where
+    F: FnMut() + MaybeSend + 'static,
+{
+    #[cfg(target_os = "macos")]
+    {
+        let listener = crate::coreaudio::DefaultDeviceListener::new(Box::new(callback))?;
+        Ok(Some(Box::new(listener)))
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        let listener = crate::mmdevice::DefaultDeviceListener::new(Box::new(callback))?;
+        Ok(Some(Box::new(listener)))
+    }
+
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    {
+        drop(callback);
+        Ok(None)
+    }
+}
@@ -3,7 +3,13 @@
 #![cfg(target_os = "windows")]
 #![allow(non_snake_case)]
 
-use crate::{AudioOutputDevice, BaseAudioOutputDevice, OutputDeviceParameters};
+use crate::{
+    convert::Dither,
+    thread::{set_current_thread_affinity, set_current_thread_priority},
+    AudioOutputDevice, Backend, BaseAudioOutputDevice, CallbackResult, ChannelLayout,
+    DirectSoundCooperativeLevel, NativeHandle, OutputDeviceParameters, Sample, SampleFormat,
+    ThreadPriority, TinyAudioError,
+};
 use std::{
     error::Error,
     mem::size_of,
@@ -17,15 +23,18 @@ use std::{
 use winapi::{
     ctypes::c_void,
     shared::{
-        guiddef::IID_NULL,
+        guiddef::{GUID, IID_NULL},
         minwindef::{DWORD, WORD},
-        mmreg::{WAVEFORMATEX, WAVE_FORMAT_PCM},
+        mmreg::{WAVEFORMATEX, WAVEFORMATEXTENSIBLE, WAVE_FORMAT_EXTENSIBLE, WAVE_FORMAT_PCM},
         ntdef::{HANDLE, PVOID},
+        windef::HWND,
         winerror::HRESULT,
     },
     um::{
         dsound::*,
-        synchapi::{CreateEventA, WaitForMultipleObjects},
+        errhandlingapi::GetLastError,
+        handleapi::CloseHandle,
+        synchapi::{CreateEventA, SetEvent, WaitForMultipleObjects},
         unknwnbase::{IUnknown, IUnknownVtbl},
         winbase::{INFINITE, WAIT_OBJECT_0},
         winuser::{GetDesktopWindow, GetForegroundWindow},
@@ -47,6 +56,22 @@ interface IDirectSoundNotify(IDirectSoundNotifyVtbl): IUnknown(IUnknownVtbl) {
         ) -> HRESULT,
 }}
 
+// `KSDATAFORMAT_SUBTYPE_PCM` from `ksmedia.h`.
+const KSDATAFORMAT_SUBTYPE_PCM: GUID = GUID {
+    Data1: 0x0000_0001,
+    Data2: 0x0000,
+    Data3: 0x0010,
+    Data4: [0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71],
+};
+
+// Maximum size of a secondary buffer in bytes.
+const DSBSIZE_MAX: usize = 0x0fff_ffff;
+
+const DSSCL_NORMAL: DWORD = 0x00000001;
+const DSSCL_EXCLUSIVE: DWORD = 0x00000003;
+
+const DSBLOCK_ENTIREBUFFER: DWORD = 0x00000002;
+
 const DSERR_BUFFERLOST: u32 = 0x88780096;
 const DSERR_INVALIDCALL: u32 = 0x88780032;
 const DSERR_INVALIDPARAM: u32 = 0x80070057;
@@ -59,12 +84,38 @@ const DSERR_UNSUPPORTED: u32 = 0x80004001;
 const DSERR_CONTROLUNAVAIL: u32 = 0x8878001E;
 const DSERR_BADFORMAT: u32 = 0x88780064;
 
-type DeviceSample = i16;
+/// Size of a sample of the given format in bytes.
+fn sample_size(sample_format: SampleFormat) -> usize {
+    match sample_format {
+        SampleFormat::I16 => size_of::<i16>(),
+        SampleFormat::U8 => size_of::<u8>(),
+    }
+}
+
+/// The byte the buffer is filled with to be silent, 8-bit samples are unsigned and centered at 128.
+fn silence_byte(sample_format: SampleFormat) -> u8 {
+    match sample_format {
+        SampleFormat::I16 => 0,
+        SampleFormat::U8 => u8::SILENCE,
+    }
+}
 
 pub struct DirectSoundDevice {
     direct_sound: *mut IDirectSound,
+    // The secondary buffer and its notification interface, both are released with the device.
+    buffer: *mut IDirectSoundBuffer,
+    notify: *mut IDirectSoundNotify,
+    notify_points: [HANDLE; 2],
     data_sender_thread_handle: Option<JoinHandle<()>>,
     is_running: Arc<AtomicBool>,
+    // The buffer of the device holds two buffers of the data callback.
+    buffer_size: usize,
+    // The feed thread was asked to raise its priority.
+    is_realtime: bool,
+    // Asks the feed thread to restart the buffer, see `OutputDevice::restart`.
+    restart_request: Arc<AtomicBool>,
+    // Wakes up the feed thread waiting for the notification points, so the device closes right away.
+    stop_event: HANDLE,
 }
 
 fn check<S>(code: HRESULT, message: S) -> Result<(), Box<dyn Error>>
@@ -106,39 +157,101 @@ where
     };
 
     if code == DS_OK {
-        Ok(())
-    } else {
-        Err(format!("{}. Reason: {}", message.as_ref(), code_description).into())
+        return Ok(());
+    }
+
+    let reason = format!("{}. Reason: {}", message.as_ref(), code_description);
+    match code as u32 {
+        DSERR_ALLOCATED => Err(Box::new(TinyAudioError::DeviceBusy(reason))),
+        DSERR_NODRIVER => Err(Box::new(TinyAudioError::DeviceUnavailable(reason))),
+        _ => Err(reason.into()),
     }
 }
 
-impl BaseAudioOutputDevice for DirectSoundDevice {}
+impl BaseAudioOutputDevice for DirectSoundDevice {
+    fn is_playing(&self) -> bool {
+        self.is_running.load(Ordering::SeqCst)
+    }
+
+    fn backend(&self) -> Backend {
+        Backend::DirectSound
+    }
+
+    fn callback_is_realtime(&self) -> bool {
+        self.is_realtime
+    }
+
+    fn native_handle(&self) -> NativeHandle<'_> {
+        NativeHandle::DirectSound(self.direct_sound)
+    }
+
+    fn device_buffer_size(&self) -> Option<usize> {
+        Some(self.buffer_size)
+    }
+
+    fn restart(&self) -> bool {
+        self.restart_request.store(true, Ordering::SeqCst);
+        true
+    }
+}
 
 unsafe impl Send for DirectSoundDevice {}
 
 impl AudioOutputDevice for DirectSoundDevice {
     fn new<C>(params: OutputDeviceParameters, data_callback: C) -> Result<Self, Box<dyn Error>>
     where
-        C: FnMut(&mut [f32]) + Send + 'static,
+        C: FnMut(&mut [f32], Option<f64>) -> CallbackResult + Send + 'static,
     {
         let OutputDeviceParameters {
             channels_count,
             channel_sample_count,
             sample_rate,
+            thread_priority,
+            cpu_affinity,
+            channel_layout,
+            windows,
+            ..
         } = params;
 
-        let byte_per_sample = size_of::<DeviceSample>();
+        let byte_per_sample = sample_size(params.sample_format);
         let buffer_len_bytes = channels_count * byte_per_sample * channel_sample_count;
         let block_align = byte_per_sample * channels_count;
 
-        let mut buffer_format = WAVEFORMATEX {
-            wFormatTag: WAVE_FORMAT_PCM,
-            nChannels: channels_count as WORD,
-            nSamplesPerSec: sample_rate as DWORD,
-            nAvgBytesPerSec: (sample_rate * block_align) as DWORD,
-            nBlockAlign: block_align as WORD,
-            wBitsPerSample: (8 * byte_per_sample) as WORD,
-            cbSize: size_of::<WAVEFORMATEX>() as WORD,
+        if 2 * buffer_len_bytes > DSBSIZE_MAX {
+            return Err(Box::new(TinyAudioError::InvalidParameters(format!(
+                "`channel_sample_count` is {}, but DirectSound allows at most {} for {} channels",
+                channel_sample_count,
+                DSBSIZE_MAX / (2 * block_align),
+                channels_count
+            ))));
+        }
+
+        // More than two channels must be explicitly assigned to the speakers, otherwise they're routed to
+        // arbitrary speakers.
+        let is_extensible = channels_count > 2 || channel_layout.is_some();
+        let mut buffer_format = WAVEFORMATEXTENSIBLE {
+            Format: WAVEFORMATEX {
+                wFormatTag: if is_extensible {
+                    WAVE_FORMAT_EXTENSIBLE
+                } else {
+                    WAVE_FORMAT_PCM
+                },
+                nChannels: channels_count as WORD,
+                nSamplesPerSec: sample_rate as DWORD,
+                nAvgBytesPerSec: (sample_rate * block_align) as DWORD,
+                nBlockAlign: block_align as WORD,
+                wBitsPerSample: (8 * byte_per_sample) as WORD,
+                cbSize: if is_extensible {
+                    (size_of::<WAVEFORMATEXTENSIBLE>() - size_of::<WAVEFORMATEX>()) as WORD
+                } else {
+                    size_of::<WAVEFORMATEX>() as WORD
+                },
+            },
+            Samples: (8 * byte_per_sample) as WORD,
+            dwChannelMask: channel_layout
+                .or_else(|| ChannelLayout::for_channels_count(channels_count))
+                .map_or(0, |channel_layout| channel_layout.channel_mask()),
+            SubFormat: KSDATAFORMAT_SUBTYPE_PCM,
         };
 
         let buffer_desc = DSBUFFERDESC {
@@ -147,7 +260,7 @@ impl AudioOutputDevice for DirectSoundDevice {
             // Buffer consists of two halves so we double the size here.
             dwBufferBytes: (2 * buffer_len_bytes) as DWORD,
             dwReserved: 0,
-            lpwfxFormat: &mut buffer_format,
+            lpwfxFormat: &mut buffer_format as *mut WAVEFORMATEXTENSIBLE as *mut WAVEFORMATEX,
             guid3DAlgorithm: IID_NULL,
         };
 
@@ -158,13 +271,28 @@ impl AudioOutputDevice for DirectSoundDevice {
                 "Failed to initialize DirectSound.",
             )?;
 
-            let mut hwnd = GetForegroundWindow();
-            if hwnd.is_null() {
-                hwnd = GetDesktopWindow();
-            }
+            // The foreground window could be owned by another process, so the window of the application is
+            // preferred if it is specified.
+            let hwnd = match windows.window_handle {
+                Some(window_handle) => window_handle as HWND,
+                None => {
+                    let hwnd = GetForegroundWindow();
+                    if hwnd.is_null() {
+                        GetDesktopWindow()
+                    } else {
+                        hwnd
+                    }
+                }
+            };
+
+            let cooperative_level = match windows.cooperative_level {
+                DirectSoundCooperativeLevel::Normal => DSSCL_NORMAL,
+                DirectSoundCooperativeLevel::Priority => DSSCL_PRIORITY,
+                DirectSoundCooperativeLevel::Exclusive => DSSCL_EXCLUSIVE,
+            };
 
             check(
-                (*direct_sound).SetCooperativeLevel(hwnd, DSSCL_PRIORITY),
+                (*direct_sound).SetCooperativeLevel(hwnd, cooperative_level),
                 "Failed to set cooperative level.",
             )?;
 
@@ -207,29 +335,78 @@ impl AudioOutputDevice for DirectSoundDevice {
                 "Failed to set notification positions.",
             )?;
 
+            // The contents of a new buffer are undefined, and the first half is played before the feed
+            // thread writes anything, so the buffer is filled with silence first.
+            let mut device_buffer = null_mut();
+            let mut size = 0;
+            check(
+                (*buffer).Lock(
+                    0,
+                    0,
+                    &mut device_buffer,
+                    &mut size,
+                    null_mut(),
+                    null_mut(),
+                    DSBLOCK_ENTIREBUFFER,
+                ),
+                "Failed to lock the render buffer.",
+            )?;
+            std::ptr::write_bytes(
+                device_buffer as *mut u8,
+                silence_byte(params.sample_format),
+                size as usize,
+            );
+            check(
+                (*buffer).Unlock(device_buffer, size, null_mut(), 0),
+                "Failed to unlock the render buffer.",
+            )?;
+
             check(
                 (*buffer).Play(0, 0, DSBPLAY_LOOPING),
                 "Failed to begin playing the render buffer.",
             )?;
 
             let is_running = Arc::new(AtomicBool::new(true));
+            let restart_request = Arc::new(AtomicBool::new(false));
+            // Manual-reset, so it stays signaled once the device is dropped.
+            let stop_event = CreateEventA(null_mut(), 1, 0, null());
+            if stop_event.is_null() {
+                return Err(format!(
+                    "Failed to create the stop event. Error code {:#x}",
+                    GetLastError()
+                )
+                .into());
+            }
 
             let data_sender_thread_handle = Some(
                 DataSender {
                     buffer,
                     notify_points,
+                    stop_event,
                     data_callback,
                     channels_count,
                     channel_sample_count,
+                    thread_priority,
+                    cpu_affinity,
+                    dither: Dither::new(params.dither, params.limiter),
+                    sample_format: params.sample_format,
                     is_running: is_running.clone(),
+                    restart_request: restart_request.clone(),
                 }
-                .run_in_thread(),
+                .run_in_thread(params.thread_name("DirectSoundFeedThread"))?,
             );
 
             Ok(Self {
                 direct_sound,
+                buffer,
+                notify,
+                notify_points,
                 data_sender_thread_handle,
                 is_running,
+                buffer_size: 2 * channel_sample_count,
+                is_realtime: thread_priority.is_some(),
+                restart_request,
+                stop_event,
             })
         }
     }
@@ -238,18 +415,26 @@ impl AudioOutputDevice for DirectSoundDevice {
 impl Drop for DirectSoundDevice {
     fn drop(&mut self) {
         unsafe {
-            // Notify data sender thread that it should be stopped.
+            // Notify data sender thread that it should be stopped, it could be waiting for the next
+            // notification point.
             self.is_running.store(false, Ordering::SeqCst);
+            SetEvent(self.stop_event);
 
-            // Wait the thread to exit.
-            self.data_sender_thread_handle
-                .take()
-                .expect("Malformed join handle!")
-                .join()
-                .expect("The thread must exist!");
-
-            // Ensure that the ref counter is zero to the device is actually destroyed.
-            assert_eq!((*self.direct_sound).Release(), 0);
+            // Wait the thread to exit. It could have panicked in the data callback, the device must be
+            // released anyway.
+            if let Some(thread_handle) = self.data_sender_thread_handle.take() {
+                let _ = thread_handle.join();
+            }
+            CloseHandle(self.stop_event);
+
+            // The remaining reference counts are not checked: the driver or the system could hold their own
+            // references, the objects are destroyed once they're released too.
+            (*self.notify).Release();
+            (*self.buffer).Release();
+            (*self.direct_sound).Release();
+            for notify_point in self.notify_points {
+                CloseHandle(notify_point);
+            }
         }
     }
 }
@@ -257,79 +442,227 @@ impl Drop for DirectSoundDevice {
 struct DataSender<C> {
     buffer: *mut IDirectSoundBuffer,
     notify_points: [*mut c_void; 2],
+    stop_event: HANDLE,
     data_callback: C,
     channels_count: usize,
     channel_sample_count: usize,
+    thread_priority: Option<ThreadPriority>,
+    cpu_affinity: Option<usize>,
+    dither: Dither,
+    sample_format: SampleFormat,
     is_running: Arc<AtomicBool>,
+    restart_request: Arc<AtomicBool>,
 }
 
 unsafe impl<C> Send for DataSender<C> {}
 
+impl<C> Drop for DataSender<C> {
+    fn drop(&mut self) {
+        // The feed thread has exited (normally or because of a panic in the data callback), let the
+        // device know that it no longer plays anything.
+        self.is_running.store(false, Ordering::SeqCst);
+    }
+}
+
 impl<C> DataSender<C>
 where
-    C: FnMut(&mut [f32]) + Send + 'static,
+    C: FnMut(&mut [f32], Option<f64>) -> CallbackResult + Send + 'static,
 {
-    #[must_use]
-    fn run_in_thread(mut self) -> JoinHandle<()> {
-        std::thread::Builder::new()
-            .name("DirectSoundFeedThread".to_string())
-            .spawn(move || unsafe { self.run_send_loop() })
-            .expect("Failed to create sender thread!")
+    fn run_in_thread(mut self, thread_name: String) -> Result<JoinHandle<()>, Box<dyn Error>> {
+        Ok(std::thread::Builder::new()
+            .name(thread_name)
+            .spawn(move || unsafe { self.run_send_loop() })?)
     }
 
     unsafe fn run_send_loop(&mut self) {
+        if let Some(thread_priority) = self.thread_priority {
+            set_current_thread_priority(thread_priority);
+        }
+        if let Some(core) = self.cpu_affinity {
+            set_current_thread_affinity(core);
+        }
+
         let mut data_buffer = vec![0.0; self.channel_sample_count * self.channels_count];
-        let device_buffer_half_len_bytes = (data_buffer.len() * size_of::<DeviceSample>()) as DWORD;
+        let device_buffer_half_len_bytes =
+            (data_buffer.len() * sample_size(self.sample_format)) as DWORD;
 
         while self.is_running.load(Ordering::SeqCst) {
-            (self.data_callback)(&mut data_buffer);
+            if self.restart_request.swap(false, Ordering::SeqCst) {
+                if let Err(err) = self.restart() {
+                    eprintln!("DirectSound: {}", err);
+                    break;
+                }
+            }
 
-            // Wait and send.
-            const WAIT_OBJECT_1: u32 = WAIT_OBJECT_0 + 1;
-            match WaitForMultipleObjects(2, self.notify_points.as_ptr(), 0, INFINITE) {
-                WAIT_OBJECT_0 => self.write(
-                    device_buffer_half_len_bytes,
-                    device_buffer_half_len_bytes,
-                    &data_buffer,
-                ),
-                WAIT_OBJECT_1 => self.write(0, device_buffer_half_len_bytes, &data_buffer),
-                _ => panic!("Unknown buffer point!"),
+            let result = (self.data_callback)(&mut data_buffer, None);
+
+            match self.wait_and_write(device_buffer_half_len_bytes, &data_buffer) {
+                Ok(true) => (),
+                // The device is being closed.
+                Ok(false) => break,
+                Err(err) => {
+                    // The device cannot be fed anymore, the device reports that it is no longer playing.
+                    eprintln!("DirectSound: {}", err);
+                    break;
+                }
+            }
+
+            if result == CallbackResult::Stop {
+                // The buffer is looped, so the other half is replaced with silence to not repeat it.
+                data_buffer.iter_mut().for_each(|sample| *sample = 0.0);
+                if let Err(err) = self.drain(device_buffer_half_len_bytes, &data_buffer) {
+                    eprintln!("DirectSound: {}", err);
+                }
+                break;
             }
         }
     }
 
-    unsafe fn write(&self, offset_bytes: DWORD, len_bytes: DWORD, data_buffer: &[f32]) {
-        let mut size = 0;
+    /// Stops the buffer, replaces its contents with silence and plays it again from the start, see
+    /// `OutputDevice::restart`.
+    unsafe fn restart(&mut self) -> Result<(), Box<dyn Error>> {
+        check((*self.buffer).Stop(), "Failed to stop the render buffer!")?;
+
         let mut device_buffer = null_mut();
+        let mut size = 0;
         check(
             (*self.buffer).Lock(
-                offset_bytes,
-                len_bytes,
+                0,
+                0,
                 &mut device_buffer,
                 &mut size,
                 null_mut(),
                 null_mut(),
-                0,
+                DSBLOCK_ENTIREBUFFER,
             ),
-            "Failed to lock the device buffer!",
+            "Failed to lock the render buffer.",
+        )?;
+        std::ptr::write_bytes(
+            device_buffer as *mut u8,
+            silence_byte(self.sample_format),
+            size as usize,
+        );
+        check(
+            (*self.buffer).Unlock(device_buffer, size, null_mut(), 0),
+            "Failed to unlock the render buffer.",
+        )?;
+
+        check(
+            (*self.buffer).SetCurrentPosition(0),
+            "Failed to rewind the render buffer.",
+        )?;
+        check(
+            (*self.buffer).Play(0, 0, DSBPLAY_LOOPING),
+            "Failed to begin playing the render buffer.",
         )
-        .unwrap();
+    }
 
-        let device_buffer_slice = std::slice::from_raw_parts_mut::<DeviceSample>(
-            device_buffer as *mut _,
-            data_buffer.len(),
-        );
+    /// Waits until the last portion of samples is played, writing the silent `data_buffer` after it, and
+    /// stops the buffer. Returns early if the device is being closed.
+    unsafe fn drain(
+        &mut self,
+        device_buffer_half_len_bytes: DWORD,
+        data_buffer: &[f32],
+    ) -> Result<(), Box<dyn Error>> {
+        for _ in 0..2 {
+            if !self.wait_and_write(device_buffer_half_len_bytes, data_buffer)? {
+                return Ok(());
+            }
+        }
+        check((*self.buffer).Stop(), "Failed to stop the render buffer!")
+    }
 
-        debug_assert_eq!(size as usize, data_buffer.len() * size_of::<DeviceSample>());
-        debug_assert_eq!(device_buffer_slice.len(), data_buffer.len());
-        for (in_sample, out_sample) in data_buffer.iter().zip(device_buffer_slice) {
-            *out_sample = (in_sample * DeviceSample::MAX as f32) as DeviceSample;
+    /// Waits until one half of the buffer starts playing and writes the samples to the other one. Returns
+    /// `false` without writing anything if the device is being closed.
+    unsafe fn wait_and_write(
+        &mut self,
+        device_buffer_half_len_bytes: DWORD,
+        data_buffer: &[f32],
+    ) -> Result<bool, Box<dyn Error>> {
+        const WAIT_OBJECT_1: u32 = WAIT_OBJECT_0 + 1;
+        const WAIT_OBJECT_2: u32 = WAIT_OBJECT_0 + 2;
+        let events = [
+            self.notify_points[0],
+            self.notify_points[1],
+            self.stop_event,
+        ];
+        match WaitForMultipleObjects(events.len() as DWORD, events.as_ptr(), 0, INFINITE) {
+            WAIT_OBJECT_0 => self
+                .write(
+                    device_buffer_half_len_bytes,
+                    device_buffer_half_len_bytes,
+                    data_buffer,
+                )
+                .map(|_| true),
+            WAIT_OBJECT_1 => self
+                .write(0, device_buffer_half_len_bytes, data_buffer)
+                .map(|_| true),
+            WAIT_OBJECT_2 => Ok(false),
+            code => Err(format!(
+                "Failed to wait for the notification points. Wait result {:#x}, error code {:#x}",
+                code,
+                GetLastError()
+            )
+            .into()),
+        }
+    }
+
+    unsafe fn lock(
+        &mut self,
+        offset_bytes: DWORD,
+        len_bytes: DWORD,
+        device_buffer: &mut *mut c_void,
+        size: &mut DWORD,
+    ) -> HRESULT {
+        (*self.buffer).Lock(
+            offset_bytes,
+            len_bytes,
+            device_buffer,
+            size,
+            null_mut(),
+            null_mut(),
+            0,
+        )
+    }
+
+    unsafe fn write(
+        &mut self,
+        offset_bytes: DWORD,
+        len_bytes: DWORD,
+        data_buffer: &[f32],
+    ) -> Result<(), Box<dyn Error>> {
+        let mut size = 0;
+        let mut device_buffer = null_mut();
+        let mut result = self.lock(offset_bytes, len_bytes, &mut device_buffer, &mut size);
+        // The memory of the buffer could be taken away by another application with the exclusive access, it
+        // must be restored before it could be locked again.
+        if result as u32 == DSERR_BUFFERLOST {
+            check(
+                (*self.buffer).Restore(),
+                "Failed to restore the lost buffer!",
+            )?;
+            result = self.lock(offset_bytes, len_bytes, &mut device_buffer, &mut size);
+        }
+        check(result, "Failed to lock the device buffer!")?;
+
+        debug_assert_eq!(
+            size as usize,
+            data_buffer.len() * sample_size(self.sample_format)
+        );
+        match self.sample_format {
+            SampleFormat::I16 => self.dither.convert(
+                data_buffer,
+                std::slice::from_raw_parts_mut(device_buffer as *mut i16, data_buffer.len()),
+            ),
+            SampleFormat::U8 => self.dither.convert_u8(
+                data_buffer,
+                std::slice::from_raw_parts_mut(device_buffer as *mut u8, data_buffer.len()),
+            ),
         }
 
         check(
             (*self.buffer).Unlock(device_buffer, size, null_mut(), 0),
             "Failed to unlock the device buffer!",
         )
-        .unwrap();
     }
 }
@@ -0,0 +1,97 @@
+//! Full-duplex audio, where the captured samples are passed to the data callback together with the output
+//! buffer.
+
+use crate::{BaseAudioOutputDevice, IntoCallbackResult, MaybeSend, OutputDeviceParameters};
+use std::{error::Error, fmt};
+
+/// An opaque "handle" to the full-duplex device, created by [`run_duplex_device`]. The capture and the
+/// playback are running while the handle is alive, drop it (or call [`DuplexDevice::close`]) to stop them.
+#[must_use = "the device stops when the handle is dropped"]
+pub struct DuplexDevice {
+    device: Option<Box<dyn BaseAudioOutputDevice>>,
+}
+
+impl DuplexDevice {
+    /// Closes the device and releases all of its resources. Does nothing if the device is already closed.
+    pub fn close(&mut self) {
+        self.device.take();
+    }
+
+    /// Returns `true` if the device is still running. It returns `false` after the data callback has
+    /// returned [`crate::CallbackResult::Stop`] or after the device was closed.
+    pub fn is_playing(&self) -> bool {
+        self.device
+            .as_ref()
+            .map_or(false, |device| device.is_playing())
+    }
+}
+
+impl fmt::Debug for DuplexDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DuplexDevice")
+            .field("is_playing", &self.is_playing())
+            .finish()
+    }
+}
+
+/// Creates a full-duplex device, that captures samples from the default input device and plays the samples
+/// on the default output device. The data callback receives the captured samples (`input`) and the output
+/// buffer (`output`, filled with silence), both of them are interleaved, have `params.channels_count`
+/// channels and `params.channel_sample_count` samples per channel. `params.source_channels` and
+/// `params.channel_layout` are ignored.
+///
+/// The capture and the playback share the same clock, so there's no drift between them. The latency from
+/// the input to the output is about two buffers (one buffer is captured while the previous one is played),
+/// use smaller `channel_sample_count` to lower it.
+///
+/// Full-duplex is supported by the ALSA backend (Linux) only, the function returns
+/// [`TinyAudioError::DeviceUnavailable`] on other platforms.
+///
+/// ## Examples
+///
+/// Pass the input to the output at half of the volume:
+///
+/// ```rust,no_run
+/// # use tinyaudio::prelude::*;
+/// let _device = run_duplex_device(OutputDeviceParameters::default(), |input, output| {
+///     for (out_sample, in_sample) in output.iter_mut().zip(input) {
+///         *out_sample = *in_sample * 0.5;
+///     }
+/// })
+/// .unwrap();
+///
+/// std::thread::sleep(std::time::Duration::from_secs(5));
+/// ```
+pub fn run_duplex_device<C, R>(
+    params: OutputDeviceParameters,
+    data_callback: C,
+) -> Result<DuplexDevice, Box<dyn Error>>
+where
+    C: FnMut(&[f32], &mut [f32]) -> R + MaybeSend + 'static,
+    R: IntoCallbackResult,
+{
+    let params = params.resolve_channel_sample_count();
+    params.validate()?;
+
+    #[cfg(target_os = "linux")]
+    {
+        let mut data_callback = data_callback;
+        let device = crate::alsa::AlsaDuplexDevice::new(params, move |input, output| {
+            output.fill(0.0);
+            data_callback(input, output).into_callback_result()
+        })?;
+
+        Ok(DuplexDevice {
+            device: Some(Box::new(device)),
+        })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        drop(data_callback);
+        Err(crate::TinyAudioError::DeviceUnavailable(
+            "Full-duplex audio is not supported on this platform".to_string(),
+        )
+        .into())
+    }
+}
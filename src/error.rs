@@ -0,0 +1,47 @@
+//! Typed errors that may be produced by the crate.
+
+use std::{error::Error, fmt};
+
+/// A typed error that could be produced by an output device. All functions of the crate return
+/// `Box<dyn Error>`, use [`Box::downcast_ref`] (or `downcast`) to check whether the error is one of these
+/// variants and react accordingly.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum TinyAudioError {
+    /// The requested audio backend is not available on this system right now. For example, the JACK
+    /// server is not running. It is safe to try another backend when this error occurs.
+    DeviceUnavailable(String),
+
+    /// The device exists, but it cannot be opened because another application (or another device of this
+    /// process) holds it exclusively. Unlike [`Self::DeviceUnavailable`], it is worth retrying later.
+    DeviceBusy(String),
+
+    /// The backend has failed to open or start the device. Contains the description of the underlying
+    /// error.
+    BackendError(String),
+
+    /// The parameters of the device are invalid (for example, `channels_count` is zero). Contains the name
+    /// of the offending field and the reason. Checked before any call to the backend.
+    InvalidParameters(String),
+}
+
+impl fmt::Display for TinyAudioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TinyAudioError::DeviceUnavailable(reason) => {
+                write!(f, "Audio device is unavailable. Reason: {}", reason)
+            }
+            TinyAudioError::DeviceBusy(reason) => {
+                write!(f, "Audio device is busy. Reason: {}", reason)
+            }
+            TinyAudioError::BackendError(reason) => {
+                write!(f, "Audio backend error: {}", reason)
+            }
+            TinyAudioError::InvalidParameters(reason) => {
+                write!(f, "Invalid output device parameters: {}", reason)
+            }
+        }
+    }
+}
+
+impl Error for TinyAudioError {}
@@ -0,0 +1,63 @@
+//! Shareable control surface of an output device.
+
+use crate::PlaybackState;
+use std::{
+    fmt,
+    sync::{atomic::Ordering, Arc},
+};
+
+/// A handle that controls the playback of an [`crate::OutputDevice`] from any thread, see
+/// [`crate::OutputDevice::handle`]. It is `Send + Sync` and cheap to clone, so it could be shared behind an
+/// `Arc` or cloned into other threads, while the device itself stays with its owner. The handle only
+/// requests the changes, the data callback picks them up at the next buffer. It does not keep the device
+/// alive: once the device is dropped, the handle does nothing.
+#[derive(Clone)]
+pub struct OutputDeviceHandle {
+    playback_state: Arc<PlaybackState>,
+}
+
+impl OutputDeviceHandle {
+    pub(crate) fn new(playback_state: Arc<PlaybackState>) -> Self {
+        Self { playback_state }
+    }
+
+    /// The same as [`crate::OutputDevice::pause`].
+    pub fn pause(&self) {
+        self.playback_state.paused.store(true, Ordering::SeqCst);
+    }
+
+    /// The same as [`crate::OutputDevice::resume`].
+    pub fn resume(&self) {
+        self.playback_state.paused.store(false, Ordering::SeqCst);
+    }
+
+    /// The same as [`crate::OutputDevice::is_paused`].
+    pub fn is_paused(&self) -> bool {
+        self.playback_state.paused.load(Ordering::SeqCst)
+    }
+
+    /// The same as [`crate::OutputDevice::frames_played`].
+    pub fn frames_played(&self) -> u64 {
+        self.playback_state.frames_played.load(Ordering::SeqCst)
+    }
+
+    /// Asks the device to stop, the counterpart of [`crate::OutputDevice::close`] that does not wait. The
+    /// samples fade out first if [`crate::OutputDeviceParameters::fade_out`] is set, then the data callback is
+    /// not called anymore and [`crate::OutputDevice::is_playing`] returns `false` once the device has played
+    /// the last buffer (a paused device stops right away). The system resources are released when the
+    /// owner drops (or closes) the device, and [`crate::OutputDevice::reconfigure`] starts it again.
+    pub fn stop(&self) {
+        self.playback_state
+            .fade_out_request
+            .store(true, Ordering::SeqCst);
+    }
+}
+
+impl fmt::Debug for OutputDeviceHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OutputDeviceHandle")
+            .field("is_paused", &self.is_paused())
+            .field("frames_played", &self.frames_played())
+            .finish()
+    }
+}
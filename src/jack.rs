@@ -0,0 +1,205 @@
+//! Linux output device via `JACK`.
+
+#![cfg(all(target_os = "linux", feature = "jack"))]
+
+use crate::{
+    block::BlockAdapter, AudioOutputDevice, Backend, BaseAudioOutputDevice, CallbackResult,
+    NativeHandle, OutputDeviceParameters, TinyAudioError,
+};
+use ::jack::{
+    AsyncClient, AudioOut, Client, ClientOptions, ClientStatus, Control, Frames,
+    NotificationHandler, Port, PortFlags, PortSpec, ProcessHandler, ProcessScope,
+};
+use std::{
+    error::Error,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+type DataCallback = Box<dyn FnMut(&mut [f32], Option<f64>) -> CallbackResult + Send + 'static>;
+
+pub struct JackOutputDevice {
+    // Deactivates and closes the client when dropped.
+    _client: AsyncClient<ShutdownNotifier, DataSender>,
+    is_running: Arc<AtomicBool>,
+}
+
+impl BaseAudioOutputDevice for JackOutputDevice {
+    fn is_playing(&self) -> bool {
+        self.is_running.load(Ordering::SeqCst)
+    }
+
+    fn backend(&self) -> Backend {
+        Backend::Jack
+    }
+
+    // The process callback runs on the realtime thread of the server, unless `jackd` was started without
+    // realtime scheduling, which cannot be told from the client.
+    fn callback_is_realtime(&self) -> bool {
+        true
+    }
+
+    fn native_handle(&self) -> NativeHandle<'_> {
+        NativeHandle::Jack(self._client.as_client())
+    }
+}
+
+/// Connects to a running JACK server. Never starts a new server, so the error could be used to fall back
+/// to some other backend.
+pub fn open_client(params: &OutputDeviceParameters) -> Result<Client, TinyAudioError> {
+    let (client, _status) = Client::new("tinyaudio", ClientOptions::NO_START_SERVER)
+        .map_err(|err| TinyAudioError::DeviceUnavailable(format!("JACK: {}", err)))?;
+
+    let server_sample_rate = client.sample_rate() as usize;
+    if server_sample_rate != params.sample_rate {
+        return Err(TinyAudioError::DeviceUnavailable(format!(
+            "JACK server runs at {} Hz, but {} Hz was requested",
+            server_sample_rate, params.sample_rate
+        )));
+    }
+
+    Ok(client)
+}
+
+impl JackOutputDevice {
+    pub fn start<C>(
+        client: Client,
+        params: OutputDeviceParameters,
+        data_callback: C,
+    ) -> Result<Self, Box<dyn Error>>
+    where
+        C: FnMut(&mut [f32], Option<f64>) -> CallbackResult + Send + 'static,
+    {
+        let mut ports = Vec::with_capacity(params.channels_count);
+        for channel_index in 0..params.channels_count {
+            ports.push(
+                client.register_port(&format!("out_{}", channel_index + 1), AudioOut::default())?,
+            );
+        }
+
+        let port_names = ports
+            .iter()
+            .map(|port| port.name())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let is_running = Arc::new(AtomicBool::new(true));
+
+        let data_sender = DataSender {
+            ports,
+            callback: Box::new(data_callback),
+            adapter: BlockAdapter::new(&params),
+            period: vec![0.0f32; client.buffer_size() as usize * params.channels_count],
+            is_running: is_running.clone(),
+            params,
+        };
+
+        let client = client.activate_async(
+            ShutdownNotifier {
+                is_running: is_running.clone(),
+            },
+            data_sender,
+        )?;
+
+        // Connect the ports to the system playback, so the sound could be heard right away. A port that
+        // cannot be connected is not fatal, it could be connected manually (for example, with a patchbay).
+        let playback_ports = client.as_client().ports(
+            None,
+            Some(AudioOut::default().jack_port_type()),
+            PortFlags::IS_INPUT | PortFlags::IS_PHYSICAL,
+        );
+        for (port_name, playback_port_name) in port_names.iter().zip(playback_ports.iter()) {
+            if let Err(err) = client
+                .as_client()
+                .connect_ports_by_name(port_name, playback_port_name)
+            {
+                eprintln!(
+                    "JACK: unable to connect `{}` to `{}` - {}",
+                    port_name, playback_port_name, err
+                );
+            }
+        }
+
+        Ok(Self {
+            _client: client,
+            is_running,
+        })
+    }
+}
+
+impl AudioOutputDevice for JackOutputDevice {
+    fn new<C>(params: OutputDeviceParameters, data_callback: C) -> Result<Self, Box<dyn Error>>
+    where
+        C: FnMut(&mut [f32], Option<f64>) -> CallbackResult + Send + 'static,
+        Self: Sized,
+    {
+        Self::start(open_client(&params)?, params, data_callback)
+    }
+}
+
+struct ShutdownNotifier {
+    is_running: Arc<AtomicBool>,
+}
+
+impl NotificationHandler for ShutdownNotifier {
+    unsafe fn shutdown(&mut self, _status: ClientStatus, _reason: &str) {
+        // The server was shut down or has kicked the client out, nothing will be played anymore.
+        self.is_running.store(false, Ordering::SeqCst);
+    }
+}
+
+struct DataSender {
+    ports: Vec<Port<AudioOut>>,
+    callback: DataCallback,
+    // JACK dictates its own buffer size, which may differ from the requested one, so the blocks of the data
+    // callback are repartitioned across as many process calls as needed.
+    adapter: BlockAdapter,
+    // Interleaved samples of the current process call, sized for the buffer size of the server.
+    period: Vec<f32>,
+    is_running: Arc<AtomicBool>,
+    params: OutputDeviceParameters,
+}
+
+impl ProcessHandler for DataSender {
+    fn process(&mut self, _client: &Client, process_scope: &ProcessScope) -> Control {
+        let frame_count = process_scope.n_frames() as usize;
+        let sample_count = (frame_count * self.params.channels_count).min(self.period.len());
+        let period = &mut self.period[..sample_count];
+
+        if self.is_running.load(Ordering::SeqCst) {
+            let callback = &mut self.callback;
+            let result = self.adapter.fill(period, |block| callback(block, None));
+            if result == CallbackResult::Stop {
+                // The client stays active until the device is dropped, so just output silence.
+                self.is_running.store(false, Ordering::SeqCst);
+            }
+        } else {
+            period.fill(0.0);
+        }
+
+        for (channel_index, port) in self.ports.iter_mut().enumerate() {
+            let out_samples = port.as_mut_slice(process_scope);
+            let played_count = out_samples
+                .len()
+                .min(sample_count / self.params.channels_count);
+            let (played_samples, rest) = out_samples.split_at_mut(played_count);
+            let frames = period.chunks_exact(self.params.channels_count);
+            for (out_sample, frame) in played_samples.iter_mut().zip(frames) {
+                *out_sample = frame[channel_index];
+            }
+            // The period is smaller if the server has grown its buffer before `buffer_size` was called, the
+            // port must not keep the samples of the previous cycle.
+            rest.fill(0.0);
+        }
+
+        Control::Continue
+    }
+
+    // Called outside of the process calls, so the buffer could be reallocated here.
+    fn buffer_size(&mut self, _client: &Client, frame_count: Frames) -> Control {
+        self.period
+            .resize(frame_count as usize * self.params.channels_count, 0.0);
+        Control::Continue
+    }
+}
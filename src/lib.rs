@@ -1,24 +1,109 @@
 #![doc = include_str!("../README.md")]
 #![warn(missing_docs)]
 
-use std::error::Error;
+use std::{
+    any::Any,
+    error::Error,
+    fmt,
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 
 #[cfg(all(target_os = "unknown", target_arch = "wasm32"))]
 use wasm_bindgen::prelude::wasm_bindgen;
 
 mod aaudio;
+mod adapters;
 mod alsa;
+mod block;
+mod channels;
+mod chunk;
+mod completion;
+mod convert;
 mod coreaudio;
+mod device;
 mod directsound;
+mod duplex;
+mod error;
+mod handle;
+mod jack;
+mod manual;
+mod mmdevice;
+mod multi;
+mod native;
+mod oss;
+mod push;
+mod sample;
+pub mod signal;
+mod sndio;
+mod source;
+mod thread;
+mod warmup;
 mod web;
+mod writer;
+
+pub use adapters::{remix_channels, upmix_mono};
+pub use channels::{
+    deinterleave, deinterleave_planar, interleave, ChannelLayout, ChannelMatrix, Speaker,
+};
+pub use chunk::{run_output_device_channel, ChunkSender};
+#[cfg(feature = "async")]
+pub use completion::{run_output_device_until_complete, PlaybackCompletion, StopHandle};
+#[cfg(all(target_os = "unknown", target_arch = "wasm32"))]
+pub use device::web_output_devices;
+pub use device::{default_output_device, DeviceInfo};
+pub use duplex::{run_duplex_device, DuplexDevice};
+pub use error::TinyAudioError;
+pub use handle::OutputDeviceHandle;
+pub use manual::{render, run_output_device_manual};
+pub use multi::run_output_device_multi;
+pub use native::{Backend, NativeHandle};
+pub use push::{run_output_device_push, AudioSink};
+pub use sample::{run_output_device_typed, Sample};
+pub use source::run_output_device_from_source;
+#[cfg(not(all(target_os = "unknown", target_arch = "wasm32")))]
+pub use writer::{run_output_device_to_writer, RawSampleFormat, WriterDevice};
 
-#[doc(hidden)]
+/// Re-exports all public functions and types of the crate, so a single `use tinyaudio::prelude::*;` is
+/// enough to use the crate.
 pub mod prelude {
-    pub use super::{run_output_device, OutputDevice, OutputDeviceParameters};
+    pub use super::{
+        default_output_device, deinterleave, deinterleave_planar, duration_to_samples, interleave,
+        remix_channels, render, run_duplex_device, run_output_device, run_output_device_channel,
+        run_output_device_f64, run_output_device_from_source, run_output_device_manual,
+        run_output_device_multi, run_output_device_push, run_output_device_typed,
+        run_output_device_with_time_info, samples_to_duration, upmix_mono, version,
+        AndroidContentType, AndroidParameters, AndroidPerformanceMode, AndroidSharingMode,
+        AndroidUsage, AudioSink, AudioTimeInfo, Backend, CallbackResult, ChannelMatrix,
+        ChunkSender, CoreAudioParameters, CoreAudioRunLoop, DeviceInfo,
+        DirectSoundCooperativeLevel, DitherMode, DuplexDevice, IntoCallbackResult, LinuxBackend,
+        LinuxParameters, MaybeSend, NativeHandle, OutputDevice, OutputDeviceHandle,
+        OutputDeviceParameters, Sample, SampleFormat, ThreadPriority, TinyAudioError,
+        WebLatencyHint, WebOutput, WebParameters, WindowsParameters,
+    };
+
+    #[cfg(not(all(target_os = "unknown", target_arch = "wasm32")))]
+    pub use super::{
+        play_for, play_until, run_output_device_to_writer, RawSampleFormat, WriterDevice,
+    };
+
+    #[cfg(all(target_os = "unknown", target_arch = "wasm32"))]
+    pub use super::web_output_devices;
+
+    #[cfg(feature = "async")]
+    pub use super::{run_output_device_until_complete, PlaybackCompletion, StopHandle};
 }
 
 /// Parameters of an output device.
-#[derive(Copy, Clone)]
+///
+/// New parameters are added over time, so the struct is `#[non_exhaustive]` and can't be created with a
+/// struct expression outside of the crate. Start from [`Default::default`], [`Self::mono`] or
+/// [`Self::stereo`] and set the fields that differ. The same applies to the platform-specific parameters.
+#[derive(Copy, Clone, Debug)]
+#[non_exhaustive]
 pub struct OutputDeviceParameters {
     /// Sample rate of your audio data. Typical values are: 11025 Hz, 22050 Hz, 44100 Hz (default), 48000 Hz,
     /// 96000 Hz.
@@ -37,47 +122,1346 @@ pub struct OutputDeviceParameters {
     /// If you need to get a specific length in **seconds**, then you need to use sampling rate to calculate
     /// the required amount of samples per channel: `channel_sample_count = sample_rate * time_in_seconds`.
     ///
-    /// The crate guarantees, that the intermediate buffer size will match the requested value.
+    /// The crate guarantees, that the intermediate buffer size will match the requested value. If the device
+    /// works with its own period (JACK, AAudio), the samples are buffered between the periods of the device
+    /// and the buffers of the data callback, so the callback still gets exactly `channel_sample_count`
+    /// samples per channel.
+    ///
+    /// Zero lets the crate choose a size that is stable on the platform (see
+    /// [`Self::resolve_channel_sample_count`]), the chosen size is reported by [`OutputDevice::params`].
     pub channel_sample_count: usize,
+
+    /// Optional scheduling priority of the thread that feeds the output device with samples. `None` (default)
+    /// leaves the priority unchanged. Raise it if you're getting dropouts when the system is under load.
+    ///
+    /// On Linux the thread gets a moderate realtime priority of 20, which is below the interrupt handlers of
+    /// the kernel and the threads of the sound servers. Keep the data callback short and never let it spin or
+    /// wait for other threads: a realtime thread that doesn't yield can freeze the whole system, as threads
+    /// of normal priority (including the one the callback waits for) don't get any CPU time.
+    ///
+    /// Elevated priority may require extra permissions (for example, `CAP_SYS_NICE` or an `rtprio` limit on
+    /// Linux). If the priority cannot be set, a warning is printed to stderr and the device keeps working at
+    /// the default priority. Only the ALSA (Linux) and DirectSound (Windows) backends have their own feed
+    /// thread, other backends ignore this value.
+    pub thread_priority: Option<ThreadPriority>,
+
+    /// Optional index of the CPU core the feed thread is pinned to. `None` (default) lets the operating system
+    /// schedule the thread on any core. Pinning to a dedicated core gives more deterministic latency, when
+    /// combined with [`Self::thread_priority`].
+    ///
+    /// If the thread cannot be pinned (for example, there's no such core), a warning is printed to stderr and
+    /// the device keeps working without pinning. Only the ALSA (Linux) and DirectSound (Windows) backends have
+    /// their own feed thread, other backends ignore this value.
+    pub cpu_affinity: Option<usize>,
+
+    /// Grows the buffer of the device (up to four times) when it underruns repeatedly, so the data callback
+    /// gets more time to render the samples on slow hardware. Each change is reported to stderr. The size of
+    /// the buffer passed to the data callback is never changed, only the latency grows. Default is `false`.
+    /// Only the ALSA (Linux) and AAudio (Android) backends support it, other backends ignore this value.
+    /// AAudio reopens the stream once (without the low latency mode, in the shared mode and with a twice
+    /// larger buffer) if it underruns repeatedly during the first second, which is counted by
+    /// [`OutputDevice::reconnect_count`].
+    pub adaptive_latency: bool,
+
+    /// Dithering applied when the samples are converted to 16-bit integers, it masks the quantization
+    /// distortion of quiet signals with a bit of noise. Default is [`DitherMode::None`]. Only the backends that
+    /// send 16-bit samples to the device (ALSA, DirectSound, CoreAudio without the `coreaudio-f32` feature,
+    /// OSS and sndio) apply it, other backends ignore this value.
+    pub dither: DitherMode,
+
+    /// Passes the samples through a soft limiter before they are converted to integers, so a callback that
+    /// overshoots `[-1.0, 1.0]` range is gently compressed instead of being clipped. The samples below
+    /// 0.75 in magnitude are not changed, the louder ones approach 1.0 smoothly. Default is `false`. Applied
+    /// by the same backends as [`Self::dither`] and by the integer formats of
+    /// [`run_output_device_to_writer`], other backends ignore this value.
+    pub limiter: bool,
+
+    /// Format of the integer samples sent to the device. Default is [`SampleFormat::I16`]. Only the ALSA
+    /// (Linux) and DirectSound (Windows) output devices support [`SampleFormat::U8`], other backends (and the
+    /// full-duplex ALSA device) ignore this value and send the samples in their usual format. Opening the
+    /// device fails if it does not accept the format.
+    pub sample_format: SampleFormat,
+
+    /// Optional amount of channels the data callback renders, if it differs from [`Self::channels_count`].
+    /// `None` (default) means the callback renders exactly `channels_count` channels. When set, the buffer
+    /// passed to the data callback contains `source_channels`-wide interleaved frames, that are mixed into
+    /// the layout of the device with [`ChannelMatrix::default_for`] (for example, 5.1 is downmixed to
+    /// stereo with ITU coefficients). Use [`remix_channels`] for a custom matrix.
+    pub source_channels: Option<usize>,
+
+    /// Optional assignment of the channels to the speakers, it must have exactly `channels_count` speakers.
+    /// `None` (default) uses the standard layout for the channel count (see
+    /// [`ChannelLayout::for_channels_count`]). Used by DirectSound (Windows) and CoreAudio (macOS, iOS) to
+    /// route more than two channels to the right speakers, other backends ignore this value.
+    pub channel_layout: Option<ChannelLayout>,
+
+    /// Optional duration of the fade-in at the start of the stream (and after [`OutputDevice::reconfigure`]).
+    /// The samples are multiplied by a linear gain ramp, so the sound doesn't start with a click. `None`
+    /// (default) starts the sound immediately.
+    pub fade_in: Option<Duration>,
+
+    /// Optional duration of the fade-out when the device is closed with [`OutputDevice::close`]. The next
+    /// samples are multiplied by a linear gain ramp and the device stops after the ramp is played, so the
+    /// sound doesn't end with a click. `close` blocks until then. `None` (default) stops the sound
+    /// immediately. The fade-out is not applied when the device is dropped without `close`, nor on
+    /// WebAssembly (where `close` cannot block) and for the manual devices.
+    pub fade_out: Option<Duration>,
+
+    /// Creates the device paused, the data callback is not called until [`OutputDevice::resume`] is
+    /// called, so the settings of the device (for example, [`OutputDevice::set_pan`]) could be changed
+    /// before the first audible sample. The device outputs silence while it is paused. Default is `false`.
+    pub start_paused: bool,
+
+    /// Amount of buffers (of `channel_sample_count` frames each) rendered by the data callback before the
+    /// stream starts (and after [`OutputDevice::reconfigure`]). The device plays them first, so the stream
+    /// starts with valid audio even if the first calls of the callback are slow (lazy initialization,
+    /// allocations, caches). The buffers are rendered on the thread that creates the device (or on the audio
+    /// thread when the device is resumed, if it was created paused), and every later sample is delayed by
+    /// the same amount of frames, so it adds to the latency. Zero (default) disables the warmup.
+    pub warmup_buffers: usize,
+
+    /// Measures the time spent in the data callback and reports it as a fraction of the duration of the
+    /// buffer, see [`OutputDevice::cpu_load`]. The measurement adds two clock reads per buffer, so it is
+    /// opt-in. Not supported on WebAssembly. Default is `false`.
+    pub measure_cpu_load: bool,
+
+    /// Optional label of the stream, that is appended to the names of the threads spawned by the crate (for
+    /// example, `AlsaDataSender[music]`). Helps to tell the threads of several devices apart in a profiler
+    /// or a debugger. `None` (default) keeps the plain names. The label must not contain null bytes.
+    ///
+    /// The label is a `&'static str` rather than a `String` (or a `Cow<'static, str>`, which can own a
+    /// `String` too) because the parameters are `Copy`: they are passed by value to every backend and are
+    /// often captured by the data callback, which an owned string would break. A label built at runtime can
+    /// be leaked once per stream with `Box::leak(label.into_boxed_str())`.
+    pub stream_label: Option<&'static str>,
+
+    /// Parameters specific to the AAudio backend, ignored on other platforms.
+    pub android: AndroidParameters,
+
+    /// Parameters specific to the Linux backends, ignored on other platforms.
+    pub linux: LinuxParameters,
+
+    /// Parameters specific to the CoreAudio backend, ignored on other platforms.
+    pub core_audio: CoreAudioParameters,
+
+    /// Parameters specific to the DirectSound backend, ignored on other platforms.
+    pub windows: WindowsParameters,
+
+    /// Parameters specific to the WebAudio backend, ignored on other platforms.
+    pub web: WebParameters,
+}
+
+impl Default for OutputDeviceParameters {
+    fn default() -> Self {
+        Self {
+            sample_rate: 44100,
+            channels_count: 2,
+            channel_sample_count: 4410,
+            thread_priority: None,
+            cpu_affinity: None,
+            adaptive_latency: false,
+            dither: DitherMode::None,
+            limiter: false,
+            sample_format: SampleFormat::I16,
+            source_channels: None,
+            channel_layout: None,
+            fade_in: None,
+            fade_out: None,
+            start_paused: false,
+            warmup_buffers: 0,
+            measure_cpu_load: false,
+            stream_label: None,
+            android: Default::default(),
+            linux: Default::default(),
+            core_audio: Default::default(),
+            windows: Default::default(),
+            web: Default::default(),
+        }
+    }
+}
+
+/// Duration (in milliseconds) of the buffer chosen when `channel_sample_count` is zero. The crate keeps
+/// about two buffers in flight, AAudio repartitions the buffers across its bursts, while WebAudio schedules
+/// the buffers from the main thread, which needs much more headroom.
+#[cfg(target_os = "android")]
+const PREFERRED_BUFFER_DURATION_MS: usize = 20;
+#[cfg(all(target_os = "unknown", target_arch = "wasm32"))]
+const PREFERRED_BUFFER_DURATION_MS: usize = 100;
+#[cfg(not(any(
+    target_os = "android",
+    all(target_os = "unknown", target_arch = "wasm32")
+)))]
+const PREFERRED_BUFFER_DURATION_MS: usize = 40;
+
+/// The chosen buffer size is a multiple of this amount of frames, which is the render quantum of WebAudio
+/// and a multiple of the typical burst sizes of the devices.
+const PREFERRED_BUFFER_GRANULARITY: usize = 128;
+
+impl OutputDeviceParameters {
+    /// Creates the parameters for a single channel at the given sample rate. The buffer size is chosen by
+    /// the crate (see [`Self::resolve_channel_sample_count`]), other parameters have their default values.
+    pub fn mono(sample_rate: usize) -> Self {
+        Self {
+            sample_rate,
+            channels_count: 1,
+            channel_sample_count: 0,
+            ..Default::default()
+        }
+    }
+
+    /// Creates the parameters for two channels at the given sample rate. The buffer size is chosen by the
+    /// crate (see [`Self::resolve_channel_sample_count`]), other parameters have their default values.
+    ///
+    /// ## Examples
+    ///
+    /// ```rust,no_run
+    /// # use tinyaudio::prelude::*;
+    /// let _device = run_output_device(
+    ///     OutputDeviceParameters::stereo(44100),
+    ///     upmix_mono(2, {
+    ///         let mut phase = 0.0f32;
+    ///         move |data| {
+    ///             for sample in data {
+    ///                 phase = (phase + 440.0 / 44100.0) % 1.0;
+    ///                 *sample = (phase * 2.0 * std::f32::consts::PI).sin();
+    ///             }
+    ///         }
+    ///     }),
+    /// )
+    /// .unwrap();
+    ///
+    /// std::thread::sleep(std::time::Duration::from_secs(5));
+    /// ```
+    pub fn stereo(sample_rate: usize) -> Self {
+        Self {
+            sample_rate,
+            channels_count: 2,
+            channel_sample_count: 0,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the parameters with `channel_sample_count` chosen by the crate if it is zero, otherwise the
+    /// parameters are returned as is. The chosen size depends on the platform (about 20 ms on Android,
+    /// 100 ms on WebAssembly and 40 ms on other platforms) and is rounded up to a multiple of 128 frames.
+    /// Every function that opens a device calls this method, so there's no need to call it manually, unless
+    /// the size is needed in advance.
+    pub fn resolve_channel_sample_count(mut self) -> Self {
+        if self.channel_sample_count == 0 {
+            let frame_count = (self.sample_rate * PREFERRED_BUFFER_DURATION_MS + 999) / 1000;
+            self.channel_sample_count = ((frame_count + PREFERRED_BUFFER_GRANULARITY - 1)
+                / PREFERRED_BUFFER_GRANULARITY)
+                .max(1)
+                * PREFERRED_BUFFER_GRANULARITY;
+        }
+        self
+    }
+
+    /// Returns the total amount of interleaved samples (of all channels) in the buffer passed to the data
+    /// callback, i.e. `channel_sample_count * channels_count`.
+    pub fn total_samples(&self) -> usize {
+        self.channel_sample_count * self.channels_count
+    }
+
+    /// Returns the duration of the buffer passed to the data callback, which is roughly the latency of the
+    /// device. It is zero if the sample rate is zero (such parameters are invalid, see [`Self::validate`]).
+    pub fn buffer_duration(&self) -> Duration {
+        samples_to_duration(self.channel_sample_count, self.sample_rate)
+    }
+
+    /// Converts the time in seconds to the amount of samples per channel at the sample rate of the device.
+    /// Could be used to get a value for [`Self::channel_sample_count`] from the desired latency. Negative
+    /// time is converted to zero.
+    pub fn seconds_to_sample_count(&self, secs: f32) -> usize {
+        (secs as f64 * self.sample_rate as f64).round() as usize
+    }
+
+    /// Returns the name of a thread spawned by the crate, with the stream label appended (if any).
+    #[cfg_attr(all(target_os = "unknown", target_arch = "wasm32"), allow(dead_code))]
+    pub(crate) fn thread_name(&self, name: &str) -> String {
+        match self.stream_label {
+            Some(label) => format!("{}[{}]", name, label),
+            None => name.to_string(),
+        }
+    }
+
+    /// Checks that the parameters could be used to open a device, the error names the offending field.
+    /// Every function that opens a device calls this method first, so there's no need to call it manually,
+    /// unless the parameters need to be checked in advance (for example, when they come from a config file).
+    pub fn validate(&self) -> Result<(), TinyAudioError> {
+        let check = |value: usize, field: &str| {
+            if value == 0 {
+                Err(TinyAudioError::InvalidParameters(format!(
+                    "`{}` must be greater than zero",
+                    field
+                )))
+            } else {
+                Ok(())
+            }
+        };
+        check(self.sample_rate, "sample_rate")?;
+        check(self.channels_count, "channels_count")?;
+        if let Some(source_channels) = self.source_channels {
+            check(source_channels, "source_channels")?;
+        }
+        if self
+            .stream_label
+            .map_or(false, |label| label.contains('\0'))
+        {
+            return Err(TinyAudioError::InvalidParameters(
+                "`stream_label` must not contain null bytes".to_string(),
+            ));
+        }
+        if let Some(channel_layout) = self.channel_layout {
+            if channel_layout.channels_count() != self.channels_count {
+                return Err(TinyAudioError::InvalidParameters(format!(
+                    "`channel_layout` has {} speakers, but `channels_count` is {}",
+                    channel_layout.channels_count(),
+                    self.channels_count
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the version of the crate (for example, `"1.1.0"`), useful for logs and bug reports together with
+/// [`OutputDevice::backend`].
+pub fn version() -> &'static str {
+    env!("CARGO_PKG_VERSION")
+}
+
+/// Converts the amount of samples per channel (frames) to their duration at the given sample rate. The result
+/// is rounded to the nearest nanosecond. Zero samples (or zero sample rate) is always a zero duration.
+///
+/// ## Examples
+///
+/// ```rust
+/// # use tinyaudio::samples_to_duration;
+/// assert_eq!(
+///     samples_to_duration(4410, 44100),
+///     std::time::Duration::from_millis(100)
+/// );
+/// ```
+pub fn samples_to_duration(samples: usize, sample_rate: usize) -> Duration {
+    if sample_rate == 0 {
+        return Duration::ZERO;
+    }
+    let sample_rate = sample_rate as u128;
+    let nanos = (samples as u128 * 1_000_000_000 + sample_rate / 2) / sample_rate;
+    Duration::new(
+        (nanos / 1_000_000_000) as u64,
+        (nanos % 1_000_000_000) as u32,
+    )
+}
+
+/// Converts the duration to the amount of samples per channel (frames) at the given sample rate, for
+/// example, to get [`OutputDeviceParameters::channel_sample_count`] from the desired latency. The result is
+/// rounded to the nearest sample (halves are rounded up). Zero duration (or zero sample rate) is always zero
+/// samples.
+///
+/// ## Examples
+///
+/// ```rust
+/// # use tinyaudio::duration_to_samples;
+/// assert_eq!(duration_to_samples(std::time::Duration::from_millis(100), 44100), 4410);
+/// ```
+pub fn duration_to_samples(duration: Duration, sample_rate: usize) -> usize {
+    ((duration.as_nanos() * sample_rate as u128 + 500_000_000) / 1_000_000_000) as usize
+}
+
+/// Realtime scheduling priority of the thread that feeds the output device with samples. See
+/// [`OutputDeviceParameters::thread_priority`] for more info.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ThreadPriority {
+    /// First-in, first-out realtime scheduling. `SCHED_FIFO` with priority 20 on Linux,
+    /// `THREAD_PRIORITY_TIME_CRITICAL` on Windows.
+    Fifo,
+
+    /// Round-robin realtime scheduling. `SCHED_RR` with priority 20 on Linux, `THREAD_PRIORITY_TIME_CRITICAL`
+    /// on Windows.
+    RoundRobin,
+}
+
+/// Noise added to the samples before they are converted to 16-bit integers, see
+/// [`OutputDeviceParameters::dither`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DitherMode {
+    /// No noise, the samples are just scaled and rounded.
+    None,
+
+    /// Uniformly distributed noise of ±0.5 LSB (least significant bit).
+    Rectangular,
+
+    /// Noise with triangular probability density function (TPDF) of ±1 LSB. It is the standard choice, since
+    /// the quantization error becomes independent from the signal.
+    Triangular,
+}
+
+/// Format of the integer samples sent to the device, see [`OutputDeviceParameters::sample_format`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SampleFormat {
+    /// 16-bit signed integers in native byte order.
+    I16,
+
+    /// 8-bit unsigned integers centered at 128, for retro sound and cheap hardware that wants 8-bit samples.
+    /// The samples are rounded, [`OutputDeviceParameters::dither`] is not applied, but
+    /// [`OutputDeviceParameters::limiter`] is.
+    U8,
+}
+
+/// Performance mode of the AAudio stream on Android. See [`AndroidParameters::performance_mode`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AndroidPerformanceMode {
+    /// No particular performance needs.
+    None,
+
+    /// Extending battery life is more important than low latency. Allows heavier data callbacks.
+    PowerSaving,
+
+    /// Reducing latency is more important than battery life. The device may use a tiny buffer, which may
+    /// cause glitches with heavy data callbacks.
+    LowLatency,
+}
+
+/// Sharing mode of the AAudio stream on Android. See [`AndroidParameters::sharing_mode`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AndroidSharingMode {
+    /// The device is shared with other applications, the stream goes through the mixer.
+    Shared,
+
+    /// The stream takes exclusive access to the device, which gives lower latency. The system may still
+    /// give a shared stream if the exclusive access is not available.
+    Exclusive,
+}
+
+/// Usage of the AAudio stream on Android, it affects the routing, the volume and the focus handling of
+/// the stream. See [`AndroidParameters::usage`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AndroidUsage {
+    /// Streaming media, music performance, video, podcasts.
+    Media,
+
+    /// Voice over IP, telephony.
+    VoiceCommunication,
+
+    /// Sounds that demand the attention of the user, such as alarms.
+    Alarm,
+
+    /// Notifications about messages or other background events.
+    Notification,
+
+    /// Game audio and sound effects.
+    Game,
+
+    /// User interface sounds, such as clicks and beeps.
+    AssistanceSonification,
+}
+
+/// Content type of the AAudio stream on Android, it may affect the audio effects applied by the system.
+/// See [`AndroidParameters::content_type`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AndroidContentType {
+    /// Spoken voice, audio books.
+    Speech,
+
+    /// Pre-recorded or live music.
+    Music,
+
+    /// Soundtrack of a movie or a video.
+    Movie,
+
+    /// Sounds that accompany the actions of the user, such as clicks or beeps.
+    Sonification,
+}
+
+/// Parameters specific to the AAudio backend on Android.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct AndroidParameters {
+    /// Performance mode of the stream. Default is [`AndroidPerformanceMode::LowLatency`].
+    pub performance_mode: AndroidPerformanceMode,
+
+    /// Sharing mode of the stream. Default is [`AndroidSharingMode::Shared`].
+    pub sharing_mode: AndroidSharingMode,
+
+    /// Optional usage of the stream, `None` (default) keeps the default of the system
+    /// ([`AndroidUsage::Media`]). Applied only if the `android-api-28` feature is enabled, since AAudio
+    /// supports it since API level 28.
+    pub usage: Option<AndroidUsage>,
+
+    /// Optional content type of the stream, `None` (default) keeps the default of the system
+    /// ([`AndroidContentType::Music`]). Applied only if the `android-api-28` feature is enabled, since
+    /// AAudio supports it since API level 28.
+    pub content_type: Option<AndroidContentType>,
 }
 
-trait BaseAudioOutputDevice: Send + 'static {}
+impl Default for AndroidParameters {
+    fn default() -> Self {
+        Self {
+            performance_mode: AndroidPerformanceMode::LowLatency,
+            sharing_mode: AndroidSharingMode::Shared,
+            usage: None,
+            content_type: None,
+        }
+    }
+}
+
+/// The backend that plays the samples on Linux. See [`LinuxParameters::backend`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum LinuxBackend {
+    /// JACK if its server is running (and the `jack` feature is enabled), ALSA otherwise.
+    Auto,
+
+    /// ALSA only, even if the JACK server is running.
+    Alsa,
+
+    /// JACK only, fails with [`TinyAudioError::DeviceUnavailable`] if its server is not running or the
+    /// `jack` feature is disabled.
+    Jack,
+}
+
+/// Parameters specific to the Linux backends.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct LinuxParameters {
+    /// The backend that plays the samples, chosen at runtime. Default is [`LinuxBackend::Auto`].
+    pub backend: LinuxBackend,
+
+    /// How many times ALSA tries to recover the device (`snd_pcm_recover`) after a failed write or read,
+    /// for example, after an underrun or a system suspend. Once the attempts are exhausted, the device gives
+    /// up instead of going on in a bad state: the error is printed to stderr and
+    /// [`OutputDevice::is_playing`] returns `false`. Zero gives up on the first failure. Default is `10`.
+    pub xrun_recovery_attempts: usize,
+
+    /// Writes one buffer of silence after ALSA has recovered the device, so the device restarts with a
+    /// buffer in flight and the fresh samples do not underrun right away, at the cost of one buffer of
+    /// silence per recovery. Otherwise the device restarts with the next portion of samples. The late samples
+    /// are never replayed in either case. Default is `false`.
+    pub recover_silence: bool,
+
+    /// Makes ALSA open the first device of the first sound card (`hw:0,0`) directly, bypassing `dmix` and
+    /// the sound server, for the lowest latency. No other application could play to the device meanwhile,
+    /// and the device must accept 16-bit samples and the channel count as is (the sample rate is the nearest
+    /// one it supports). Fails with [`TinyAudioError::DeviceBusy`] if another application holds the device.
+    /// The JACK server holds the hardware device by itself, so it is still preferred with
+    /// [`LinuxBackend::Auto`]. Default is `false`.
+    pub exclusive: bool,
+}
+
+impl Default for LinuxParameters {
+    fn default() -> Self {
+        Self {
+            backend: LinuxBackend::Auto,
+            xrun_recovery_attempts: 10,
+            recover_silence: false,
+            exclusive: false,
+        }
+    }
+}
+
+/// The run loop the audio queue callbacks (and so the data callback) are called on by CoreAudio. See
+/// [`CoreAudioParameters::run_loop`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CoreAudioRunLoop {
+    /// An internal thread of the audio queue.
+    Internal,
+
+    /// The run loop of the thread that creates (or reconfigures) the device. The thread must keep running
+    /// its run loop, otherwise the data callback is never called.
+    Current,
+
+    /// The main run loop of the application. Useful when the data callback must synchronize with the main
+    /// thread, but heavy work on the main thread will cause glitches.
+    Main,
+}
+
+/// Parameters specific to the CoreAudio backend on macOS and iOS.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct CoreAudioParameters {
+    /// The run loop the data callback is called on. Default is [`CoreAudioRunLoop::Internal`].
+    pub run_loop: CoreAudioRunLoop,
+}
+
+impl Default for CoreAudioParameters {
+    fn default() -> Self {
+        Self {
+            run_loop: CoreAudioRunLoop::Internal,
+        }
+    }
+}
+
+/// Cooperative level of the DirectSound device on Windows. See [`WindowsParameters::cooperative_level`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DirectSoundCooperativeLevel {
+    /// `DSSCL_NORMAL`, the format of the primary buffer is never changed, so other applications are not
+    /// affected. Before Windows Vista this limits the output to 8-bit samples at 22050 Hz.
+    Normal,
+
+    /// `DSSCL_PRIORITY`, the application may change the format of the primary buffer.
+    Priority,
+
+    /// `DSSCL_EXCLUSIVE`, it has the same effect as [`Self::Priority`] since DirectX 8.
+    Exclusive,
+}
+
+/// Parameters specific to the DirectSound backend on Windows.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct WindowsParameters {
+    /// Cooperative level passed to `SetCooperativeLevel`. Default is
+    /// [`DirectSoundCooperativeLevel::Priority`].
+    pub cooperative_level: DirectSoundCooperativeLevel,
+
+    /// Optional raw `HWND` of the window of the application, the cooperative level is set for it. `None`
+    /// (default) uses the foreground window (or the desktop window if there's none), which could be owned by
+    /// another process.
+    pub window_handle: Option<isize>,
+}
+
+impl Default for WindowsParameters {
+    fn default() -> Self {
+        Self {
+            cooperative_level: DirectSoundCooperativeLevel::Priority,
+            window_handle: None,
+        }
+    }
+}
+
+/// Latency hint of the WebAudio context, see [`WebParameters::latency_hint`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WebLatencyHint {
+    /// `"interactive"`, the lowest latency the browser can provide without glitching.
+    Interactive,
+
+    /// `"balanced"`, a balance between the latency and the power consumption.
+    Balanced,
+
+    /// `"playback"`, the latency is traded for the power consumption and the stability of the playback.
+    /// Suits music players, which have fewer dropouts when the tab is in the background.
+    Playback,
+}
+
+impl Default for WebLatencyHint {
+    fn default() -> Self {
+        Self::Interactive
+    }
+}
+
+/// Where the WebAudio device sends its samples, see [`WebParameters::output`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WebOutput {
+    /// The destination of the audio context, i.e. the speakers.
+    Speakers,
+
+    /// A `MediaStreamAudioDestinationNode`, its stream (see `OutputDevice::web_media_stream`) could be
+    /// attached to an `RTCPeerConnection` or recorded with a `MediaRecorder`. Nothing is heard locally.
+    MediaStream,
+
+    /// Both the speakers and the media stream.
+    SpeakersAndMediaStream,
+}
+
+impl Default for WebOutput {
+    fn default() -> Self {
+        Self::Speakers
+    }
+}
+
+/// Parameters specific to the WebAudio backend.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct WebParameters {
+    /// Keeps the audio context busy with a looping silent source, so the browser never sees a gap between
+    /// the buffers and does not suspend the context (mobile Safari does it aggressively). Also, the playback
+    /// is resumed (and restarted, if the buffers have stopped being scheduled) when the page becomes visible
+    /// again. Default is `false`.
+    pub keep_alive: bool,
+
+    /// Opens the audio context at its preferred sample rate (usually the rate of the output device) instead
+    /// of [`OutputDeviceParameters::sample_rate`], and resamples the samples of the data callback by linear
+    /// interpolation. Some browsers fail to open the context (or resample poorly) at arbitrary rates. The
+    /// actual rate is reported by [`OutputDevice::device_sample_rate`]. Default is `false`.
+    pub native_sample_rate: bool,
+
+    /// Latency hint passed to the audio context (`latencyHint`), the browser picks the size of its internal
+    /// buffers accordingly. Default is [`WebLatencyHint::Interactive`].
+    pub latency_hint: WebLatencyHint,
+
+    /// Amount of the buffers of the data callback (of `channel_sample_count` frames each) packed into a
+    /// single buffer scheduled on the audio context. The data callback is called several times in a row to
+    /// fill it, so its buffer size stays the same. Fewer and larger buffers are more reliable on mobile
+    /// browsers, which may fail to schedule small buffers in time, at the cost of higher latency. Zero
+    /// (default) is the same as one.
+    pub blocks_per_buffer: usize,
 
-impl BaseAudioOutputDevice for () {}
+    /// Plays through a single audio context shared by all the devices created with this flag, instead of
+    /// opening a new context per device. Browsers limit the amount of audio contexts per page (often to
+    /// about six), so this is needed to play many streams at once. The context is opened by the first device
+    /// (with its latency hint and sample rate, the samples of other devices are resampled if needed) and is
+    /// closed when the last device is dropped. Default is `false`.
+    pub shared_context: bool,
+
+    /// Where the samples are sent to. Default is [`WebOutput::Speakers`].
+    pub output: WebOutput,
+}
+
+/// A bound of the data callbacks, that is the same as `Send` on all platforms except WebAssembly. On
+/// WebAssembly the data callback is called on the main thread, so it may hold non-`Send` state (such as `Rc`).
+#[cfg(not(all(target_os = "unknown", target_arch = "wasm32")))]
+pub trait MaybeSend: Send {}
+
+#[cfg(not(all(target_os = "unknown", target_arch = "wasm32")))]
+impl<T: Send> MaybeSend for T {}
+
+/// A bound of the data callbacks, that is the same as `Send` on all platforms except WebAssembly. On
+/// WebAssembly the data callback is called on the main thread, so it may hold non-`Send` state (such as `Rc`).
+#[cfg(all(target_os = "unknown", target_arch = "wasm32"))]
+pub trait MaybeSend {}
+
+#[cfg(all(target_os = "unknown", target_arch = "wasm32"))]
+impl<T> MaybeSend for T {}
+
+trait BaseAudioOutputDevice: Send + 'static {
+    fn is_playing(&self) -> bool;
+
+    fn backend(&self) -> Backend;
+
+    fn frames_per_burst(&self) -> Option<usize> {
+        None
+    }
+
+    fn callback_is_realtime(&self) -> bool {
+        false
+    }
+
+    fn reconnect_count(&self) -> usize {
+        0
+    }
+
+    fn device_buffer_size(&self) -> Option<usize> {
+        None
+    }
+
+    fn device_period_size(&self) -> Option<usize> {
+        None
+    }
+
+    fn device_sample_rate(&self) -> Option<usize> {
+        None
+    }
+
+    fn native_handle(&self) -> NativeHandle<'_> {
+        NativeHandle::Unavailable
+    }
+
+    // The stream of the samples sent to `WebOutput::MediaStream`, only the WebAudio device has it.
+    #[cfg(all(target_os = "unknown", target_arch = "wasm32"))]
+    fn media_stream(&self) -> Option<web_sys::MediaStream> {
+        None
+    }
+
+    // Renders the samples into the buffer of the host, only the manual device supports it.
+    fn process(&mut self, _output: &mut [f32]) -> Option<CallbackResult> {
+        None
+    }
+
+    // Asks the playing device to drop the queued samples and restart the stream in place. Returns `false`
+    // if the backend cannot do it, the device is reopened then.
+    fn restart(&self) -> bool {
+        false
+    }
+}
+
+impl BaseAudioOutputDevice for () {
+    fn is_playing(&self) -> bool {
+        false
+    }
+
+    // A placeholder that does not play anything, just like a manual device without a host.
+    fn backend(&self) -> Backend {
+        Backend::Manual
+    }
+}
 
 trait AudioOutputDevice: BaseAudioOutputDevice {
+    // The second argument of the data callback is the device time in seconds (see
+    // `AudioTimeInfo::device_time`), if the backend is able to provide it.
     fn new<C>(params: OutputDeviceParameters, data_callback: C) -> Result<Self, Box<dyn Error>>
     where
-        C: FnMut(&mut [f32]) + Send + 'static,
+        C: FnMut(&mut [f32], Option<f64>) -> CallbackResult + MaybeSend + 'static,
         Self: Sized;
 }
 
-/// An opaque "handle" to platform-dependent audio output device.
+/// Timing information of the portion of samples that is being produced by the data callback. See
+/// [`run_output_device_with_time_info`] for more info.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AudioTimeInfo {
+    /// Position (in frames, i.e. samples per channel) of the first frame of the buffer, counted from the
+    /// moment the device was started (or reconfigured). It increases monotonically by the length of the
+    /// buffer with each call of the data callback.
+    pub frame_position: u64,
+
+    /// Time of the device clock in seconds at the moment the callback was called, if the backend is able
+    /// to provide it. It is the queue timeline time on macOS/iOS and `AudioContext.currentTime` on
+    /// WebAssembly, other backends set it to `None`.
+    pub device_time: Option<f64>,
+}
+
+/// Tells the device whether it should keep playing after the data callback has produced the samples.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CallbackResult {
+    /// Keep playing and calling the data callback.
+    Continue,
+
+    /// The samples produced by this call are the last ones. They will be played, then the device stops
+    /// ([`OutputDevice::is_playing`] will return `false`) and the data callback won't be called anymore.
+    /// The callback may fill only a part of the buffer, the rest of it stays silent.
+    Stop,
+}
+
+/// A value that could be returned from a data callback. Implemented for `()`, which means that the device
+/// keeps playing, and for [`CallbackResult`], which allows the callback to stop the device.
+pub trait IntoCallbackResult {
+    /// Converts the value into [`CallbackResult`].
+    fn into_callback_result(self) -> CallbackResult;
+}
+
+impl IntoCallbackResult for () {
+    fn into_callback_result(self) -> CallbackResult {
+        CallbackResult::Continue
+    }
+}
+
+impl IntoCallbackResult for CallbackResult {
+    fn into_callback_result(self) -> CallbackResult {
+        self
+    }
+}
+
+#[cfg(not(all(target_os = "unknown", target_arch = "wasm32")))]
+type SharedDataCallback =
+    Arc<Mutex<Box<dyn FnMut(&mut [f32], AudioTimeInfo) -> CallbackResult + Send + 'static>>>;
+
+#[cfg(not(all(target_os = "unknown", target_arch = "wasm32")))]
+type SharedMonitorCallback = Arc<Mutex<Option<Box<dyn FnMut(&[f32]) + Send + 'static>>>>;
+
+// Trait objects cannot use `MaybeSend`, so the callbacks are stored without `Send` on WebAssembly.
+#[cfg(all(target_os = "unknown", target_arch = "wasm32"))]
+type SharedDataCallback =
+    Arc<Mutex<Box<dyn FnMut(&mut [f32], AudioTimeInfo) -> CallbackResult + 'static>>>;
+
+#[cfg(all(target_os = "unknown", target_arch = "wasm32"))]
+type SharedMonitorCallback = Arc<Mutex<Option<Box<dyn FnMut(&[f32]) + 'static>>>>;
+
+#[cfg(not(all(target_os = "unknown", target_arch = "wasm32")))]
+type StartedCallback = Box<dyn FnOnce() + Send + 'static>;
+
+#[cfg(all(target_os = "unknown", target_arch = "wasm32"))]
+type StartedCallback = Box<dyn FnOnce() + 'static>;
+
+/// An opaque "handle" to platform-dependent audio output device. The device stops when the handle is
+/// dropped, so keep it alive while the sound is playing (or call [`OutputDevice::detach`]).
+///
+/// The device is `Send` (except on WebAssembly), but not `Sync`: it is owned by one thread at a time, and
+/// the methods that change the device itself (such as [`Self::close`] or [`Self::reconfigure`]) take
+/// `&mut self`. To control the playback from several threads, share an [`OutputDeviceHandle`] (see
+/// [`Self::handle`]) instead.
 #[cfg_attr(all(target_os = "unknown", target_arch = "wasm32"), wasm_bindgen)]
+#[must_use = "the device stops when the handle is dropped"]
 pub struct OutputDevice {
     device: Option<Box<dyn BaseAudioOutputDevice>>,
+    // The data callback is kept here, so the device could be reopened with the same callback.
+    data_callback: SharedDataCallback,
+    monitor_callback: SharedMonitorCallback,
+    default_device_listener: Option<device::DefaultDeviceListener>,
+    // The device is driven by the host (see `run_output_device_manual`), it must stay manual on reconfiguration.
+    is_manual: bool,
+    params: OutputDeviceParameters,
+    // Shared with the data callback, survives reconfiguration.
+    playback_state: Arc<PlaybackState>,
+}
+
+// The state that is updated by the data callback and could be read (or requested) from any thread.
+#[derive(Default)]
+struct PlaybackState {
+    // Asks the data callback to fade out and stop, see `OutputDeviceParameters::fade_out`.
+    fade_out_request: AtomicBool,
+    frames_played: AtomicU64,
+    // The data callback is not called while it is set, see `OutputDevice::pause`.
+    paused: AtomicBool,
+    // Bits of the `f32` pan, zero bits is the center.
+    pan: AtomicU32,
+    // Bits of the `f32` smoothed load of the data callback, see `OutputDevice::cpu_load`.
+    cpu_load: AtomicU32,
+    // Payload of the panic caught in the data callback, see `OutputDevice::take_panic`.
+    panic: Mutex<Option<Box<dyn Any + Send + 'static>>>,
+    // The device has asked for the first portion of samples to play, see `OutputDevice::on_started`.
+    has_started: AtomicBool,
+    started_callback: Mutex<Option<StartedCallback>>,
 }
 
 impl OutputDevice {
-    fn new<D: BaseAudioOutputDevice>(device: D) -> Self {
-        Self {
-            device: Some(Box::new(device)),
+    /// Stops the device and starts it again with the new parameters, reusing the same data callback. Could
+    /// be used to change the sample rate, channel count or buffer size without creating a new device. Also
+    /// reopens the device if it was closed. If the new parameters are invalid, the device keeps playing with
+    /// the old ones. If the device fails to start with the new parameters, it stays closed.
+    pub fn reconfigure(
+        &mut self,
+        new_params: OutputDeviceParameters,
+    ) -> Result<(), TinyAudioError> {
+        let new_params = new_params.resolve_channel_sample_count();
+        new_params.validate()?;
+
+        // The old device must be stopped before the new one is opened, because the device could be
+        // exclusive and because the data callback cannot be called from two threads at once.
+        self.device.take();
+        let device = open_device(
+            new_params,
+            self.data_callback.clone(),
+            self.monitor_callback.clone(),
+            self.playback_state.clone(),
+            self.is_manual,
+        )
+        .map_err(|err| match err.downcast::<TinyAudioError>() {
+            Ok(err) => *err,
+            Err(err) => TinyAudioError::BackendError(err.to_string()),
+        })?;
+        self.device = Some(device);
+        self.params = new_params;
+        Ok(())
+    }
+
+    /// Drops the samples queued in the device and starts the stream again with the same parameters and data
+    /// callback, for example, to recover from a glitch. It is lighter than [`Self::reconfigure`]: the ALSA
+    /// and DirectSound streams are restarted in place by the feed thread before the next buffer (ALSA drops
+    /// and prepares the PCM, DirectSound stops, rewinds and plays the silenced buffer), the restart errors
+    /// are printed to stderr then. Other devices, and the devices that have stopped or were closed, are
+    /// reopened just like by [`Self::reconfigure`] with the current parameters.
+    pub fn restart(&mut self) -> Result<(), TinyAudioError> {
+        match self.device.as_ref() {
+            Some(device) if device.is_playing() && device.restart() => Ok(()),
+            _ => self.reconfigure(self.params),
+        }
+    }
+
+    /// Asks the data callback to fade out and waits until the device stops by itself. The wait is limited,
+    /// so a stuck device does not block forever.
+    #[cfg(not(all(target_os = "unknown", target_arch = "wasm32")))]
+    fn fade_out(&self) {
+        let (device, fade_out) = match (self.device.as_ref(), self.params.fade_out) {
+            (Some(device), Some(fade_out))
+                if !self.is_manual && !self.is_paused() && device.is_playing() =>
+            {
+                (device, fade_out)
+            }
+            _ => return,
+        };
+
+        self.playback_state
+            .fade_out_request
+            .store(true, Ordering::SeqCst);
+
+        // The ramp is rendered into the next buffers, then the device plays them and stops.
+        let deadline = std::time::Instant::now() + fade_out + self.params.buffer_duration() * 3;
+        while device.is_playing() && std::time::Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    /// Returns the amount of frames (samples per channel) that were handed over to the device since it was
+    /// created. It increases monotonically (also across [`Self::reconfigure`]) by the length of each portion
+    /// of samples rendered by the data callback and could be read at any time from any thread, for example,
+    /// for a playhead in the UI. The samples become audible a bit later, the difference is the latency of
+    /// the device (up to two buffers).
+    pub fn frames_played(&self) -> u64 {
+        self.playback_state.frames_played.load(Ordering::SeqCst)
+    }
+
+    /// Pauses the device: the data callback is not called and the device outputs silence from the next
+    /// buffer, until [`Self::resume`] is called. The device stays open, [`AudioTimeInfo::frame_position`]
+    /// and [`Self::frames_played`] do not increase while it is paused.
+    pub fn pause(&self) {
+        self.playback_state.paused.store(true, Ordering::SeqCst);
+    }
+
+    /// Resumes the device paused with [`Self::pause`] or created paused (see
+    /// [`OutputDeviceParameters::start_paused`]), the data callback is called from the next buffer. The
+    /// fade-in is not applied again, unless the device was created paused and has never played.
+    pub fn resume(&self) {
+        self.playback_state.paused.store(false, Ordering::SeqCst);
+    }
+
+    /// Returns `true` if the device is paused, see [`Self::pause`].
+    pub fn is_paused(&self) -> bool {
+        self.playback_state.paused.load(Ordering::SeqCst)
+    }
+
+    /// Returns a `Send + Sync` handle that could be cloned and used to pause, resume or stop the device
+    /// from other threads. The handle stays valid across [`Self::reconfigure`].
+    pub fn handle(&self) -> OutputDeviceHandle {
+        OutputDeviceHandle::new(self.playback_state.clone())
+    }
+
+    /// Sets the stereo pan, from `-1.0` (left) to `1.0` (right), the values out of the range are clamped.
+    /// Default is `0.0` (center), that leaves the samples as is. The equal-power pan law of the `WebAudio`
+    /// `StereoPannerNode` is used: panning to one side attenuates the other channel and mixes it into the
+    /// remaining one, so the loudness stays the same. The new pan is applied from the next buffer.
+    ///
+    /// Returns [`TinyAudioError::InvalidParameters`] if the device is not stereo (or `pan` is NaN). The pan
+    /// survives [`Self::reconfigure`], but it is ignored while the device is not stereo.
+    pub fn set_pan(&mut self, pan: f32) -> Result<(), TinyAudioError> {
+        if self.params.channels_count != 2 {
+            return Err(TinyAudioError::InvalidParameters(format!(
+                "Pan requires a stereo device, the device has {} channels",
+                self.params.channels_count
+            )));
+        }
+        if pan.is_nan() {
+            return Err(TinyAudioError::InvalidParameters(
+                "Pan must not be NaN".to_string(),
+            ));
+        }
+        self.playback_state
+            .pan
+            .store(pan.clamp(-1.0, 1.0).to_bits(), Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Returns the stereo pan set by [`Self::set_pan`].
+    pub fn pan(&self) -> f32 {
+        f32::from_bits(self.playback_state.pan.load(Ordering::SeqCst))
+    }
+
+    /// Returns `true` if the data callback is called on a realtime thread, where blocking (locking a mutex,
+    /// allocating memory, doing I/O) may cause audible glitches. Returns `false` if the device is closed.
+    /// The thread context of each backend:
+    ///
+    /// - ALSA (Linux) and DirectSound (Windows) call it on their own feed thread. It is realtime only if
+    ///   [`OutputDeviceParameters::thread_priority`] is set (the result doesn't tell whether the priority was
+    ///   actually granted, a warning is printed if it wasn't).
+    /// - JACK (Linux) calls it on the realtime process thread of the server.
+    /// - AAudio (Android) calls it on a high-priority thread of the system, it is realtime.
+    /// - CoreAudio (macOS, iOS) calls it on the internal thread of the audio queue (or on the run loop given
+    ///   by [`CoreAudioParameters::run_loop`]), it is not realtime.
+    /// - OSS and sndio call it on their own feed thread at the default priority.
+    /// - WebAudio calls it on the main thread of the page, between the other tasks of the event loop.
+    /// - Manual devices call it on the thread of the host that renders the samples.
+    ///
+    /// Even when the callback is not realtime it should return quickly, the next buffer must be rendered
+    /// before the current one is played.
+    pub fn callback_is_realtime(&self) -> bool {
+        self.device
+            .as_ref()
+            .map_or(false, |device| device.callback_is_realtime())
+    }
+
+    /// Returns the backend that plays the samples, or `None` if the device is closed. On Linux it tells
+    /// whether JACK or ALSA was chosen (see [`LinuxParameters::backend`]). Useful for logs and bug reports.
+    pub fn backend(&self) -> Option<Backend> {
+        self.device.as_ref().map(|device| device.backend())
+    }
+
+    /// Returns the parameters the device was opened with (or reconfigured to last time), with
+    /// `channel_sample_count` chosen by the crate if it was zero.
+    pub fn params(&self) -> OutputDeviceParameters {
+        self.params
+    }
+
+    /// Returns the time spent in the data callback as a fraction of the duration of the buffer (`1.0` means
+    /// the callback takes as long as the buffer plays, so the device is about to underrun), smoothed over
+    /// the last few buffers. Always returns `0.0` unless [`OutputDeviceParameters::measure_cpu_load`] is
+    /// set.
+    pub fn cpu_load(&self) -> f32 {
+        f32::from_bits(self.playback_state.cpu_load.load(Ordering::SeqCst))
+    }
+
+    /// Takes the payload of the panic caught in the data callback, if any. A panic in the data callback
+    /// never unwinds into the backend (and never aborts the feed thread): the buffer is filled with silence
+    /// and the device stops as if the callback has returned [`CallbackResult::Stop`]. Call this method at
+    /// some safe point (for example, when [`Self::is_playing`] returns `false`) and pass the payload to
+    /// [`std::panic::resume_unwind`] to re-raise the panic, or inspect the message to report it.
+    pub fn take_panic(&mut self) -> Option<Box<dyn Any + Send + 'static>> {
+        self.playback_state.panic.lock().unwrap().take()
+    }
+
+    /// Returns the native handle of the backend, that could be used to call the platform APIs that are not
+    /// wrapped by the crate (for example, to set a CoreAudio property). Returns
+    /// [`NativeHandle::Unavailable`] if the device is closed.
+    ///
+    /// # Safety
+    ///
+    /// The handle is owned by the device: it must not be closed, released or used after the device was
+    /// closed or reconfigured. Changing the state of the handle (for example, stopping the playback or
+    /// changing the format) may break the device and lead to crashes.
+    pub unsafe fn native_handle(&self) -> NativeHandle<'_> {
+        self.device
+            .as_ref()
+            .map_or(NativeHandle::Unavailable, |device| device.native_handle())
+    }
+
+    /// Routes the samples to the output with the given id (see [`web_output_devices`] and [`DeviceInfo::id`])
+    /// via `AudioContext.setSinkId`, so a web app could let users pick headphones or speakers. An empty id
+    /// routes them to the default output. The switch itself is asynchronous, its failures are reported to
+    /// the console. Fails with [`TinyAudioError::DeviceUnavailable`] if the browser does not support
+    /// `setSinkId` or the device is closed. The new context created by [`Self::reconfigure`] plays to the
+    /// default output again. Only available on WebAssembly.
+    #[cfg(all(target_os = "unknown", target_arch = "wasm32"))]
+    pub fn set_sink_id(&self, id: &str) -> Result<(), TinyAudioError> {
+        // SAFETY: the context is only routed to another output, its state is not changed otherwise.
+        match unsafe { self.native_handle() } {
+            NativeHandle::Web(audio_context) => web::set_sink_id(audio_context, id),
+            _ => Err(TinyAudioError::DeviceUnavailable(
+                "The device is closed".to_string(),
+            )),
+        }
+    }
+
+    /// Returns the audio context the device plays through, so additional nodes (for example, an
+    /// `AnalyserNode` for a visualizer) could be created in the same graph instead of opening another
+    /// context, browsers limit their amount. The device connects its sources to the destination of the
+    /// context (see [`WebParameters::output`]), other nodes could be connected there too. Do not close or
+    /// suspend the context, the device stops then (and so do the other devices if the context is shared, see
+    /// [`WebParameters::shared_context`]). `None` if the device is closed. Only available on WebAssembly.
+    #[cfg(all(target_os = "unknown", target_arch = "wasm32"))]
+    pub fn audio_context(&self) -> Option<&web_sys::AudioContext> {
+        match self.device.as_ref().map(|device| device.native_handle()) {
+            Some(NativeHandle::Web(audio_context)) => Some(audio_context),
+            _ => None,
+        }
+    }
+
+    /// Returns the `MediaStream` the samples are sent to, if the device was created with
+    /// [`WebOutput::MediaStream`] or [`WebOutput::SpeakersAndMediaStream`] (see [`WebParameters::output`]).
+    /// The stream could be attached to an `RTCPeerConnection` to send the audio to a peer. `None` if the
+    /// device plays to the speakers only or is closed. Only available on WebAssembly.
+    #[cfg(all(target_os = "unknown", target_arch = "wasm32"))]
+    pub fn web_media_stream(&self) -> Option<web_sys::MediaStream> {
+        self.device
+            .as_ref()
+            .and_then(|device| device.media_stream())
+    }
+
+    /// Replaces the data callback of the device while it keeps playing. The callback is called under a lock,
+    /// so the new one is picked up at the next buffer boundary: the buffer that is being rendered right now
+    /// is finished by the old callback. Fade-in, fade-out and channel mapping are not affected. The old
+    /// callback is dropped on the calling thread, not on the audio one.
+    pub fn set_callback<C, R>(&mut self, mut data_callback: C)
+    where
+        C: FnMut(&mut [f32]) -> R + MaybeSend + 'static,
+        R: IntoCallbackResult,
+    {
+        let _old_callback = std::mem::replace(
+            &mut *self.data_callback.lock().unwrap(),
+            Box::new(move |data: &mut [f32], _| data_callback(data).into_callback_result()),
+        );
+    }
+
+    /// Sets the callback that receives a copy of every portion of interleaved samples right after the data
+    /// callback has produced it. Could be used to read back the samples that are sent to the device (for
+    /// example, for a visualizer) without changing the data callback. The monitor is called from the same
+    /// thread as the data callback, so it must be cheap: do not block in it and hand off the samples to
+    /// some other thread (via a ring buffer, for example) if they need heavy processing.
+    pub fn set_monitor_callback<M>(&mut self, monitor_callback: M)
+    where
+        M: FnMut(&[f32]) + MaybeSend + 'static,
+    {
+        *self.monitor_callback.lock().unwrap() = Some(Box::new(monitor_callback));
+    }
+
+    /// Sets the callback that is called once, when the device starts rendering the samples: the first time
+    /// the device itself asks the data callback for the samples to play (the buffers rendered ahead by
+    /// [`OutputDeviceParameters::warmup_buffers`] do not count) while the device is not paused. The samples
+    /// of that buffer become audible after the latency of the device, so the moment could be used to align a
+    /// visual cue with the onset of the sound. The callback is called on the audio thread, so it must be
+    /// cheap, or right away on the calling thread if the device has started already. It is not called again
+    /// after [`Self::reconfigure`]. On WebAssembly the first buffers are rendered as soon as the device is
+    /// created, even while the audio context waits for a user gesture to run.
+    pub fn on_started<F>(&mut self, callback: F)
+    where
+        F: FnOnce() + MaybeSend + 'static,
+    {
+        let mut started_callback = self.playback_state.started_callback.lock().unwrap();
+        if self.playback_state.has_started.load(Ordering::SeqCst) {
+            drop(started_callback);
+            callback();
+        } else {
+            *started_callback = Some(Box::new(callback));
+        }
+    }
+
+    /// Removes the callback set by [`Self::set_monitor_callback`].
+    pub fn remove_monitor_callback(&mut self) {
+        self.monitor_callback.lock().unwrap().take();
+    }
+
+    /// Sets the callback that is called when the default output device of your operating system changes
+    /// (for example, when a USB headset was plugged in). The device keeps playing to the old output device,
+    /// call [`Self::reconfigure`] to follow the new default one. The callback is called from a thread of the
+    /// operating system, so it should not reconfigure the device directly: set a flag or send a message to
+    /// the thread that owns the device instead.
+    ///
+    /// Only CoreAudio (macOS) and Windows report the changes, the callback is never called on other
+    /// platforms. Replaces the previously set callback.
+    pub fn set_default_device_changed_callback<F>(
+        &mut self,
+        callback: F,
+    ) -> Result<(), TinyAudioError>
+    where
+        F: FnMut() + MaybeSend + 'static,
+    {
+        self.default_device_listener.take();
+        self.default_device_listener = device::listen_default_output_device(callback)
+            .map_err(|err| TinyAudioError::BackendError(err.to_string()))?;
+        Ok(())
+    }
+
+    /// Removes the callback set by [`Self::set_default_device_changed_callback`].
+    pub fn remove_default_device_changed_callback(&mut self) {
+        self.default_device_listener.take();
+    }
+
+    /// Intentionally leaks the handle, so the device keeps playing until the data callback returns
+    /// [`CallbackResult::Stop`] or the process exits. Useful for fire-and-forget playback, when there's no
+    /// place to keep the handle. The system resources of the device are never released, so do not detach
+    /// devices repeatedly.
+    pub fn detach(self) {
+        Box::leak(Box::new(self));
+    }
+
+    /// Renders the next portion of interleaved samples into `output` by calling the data callback. Must be
+    /// called by the host (for example, from the audio thread of a plugin host) for the devices created by
+    /// [`run_output_device_manual`], the length of `output` should be a multiple of the channel count. All
+    /// the processing (silence fill, channel mixing, monitor callback) is the same as for other devices.
+    ///
+    /// Returns the result of the data callback. If the callback has already asked to stop, the device was
+    /// closed or the device is not a manual one, `output` is filled with silence and
+    /// [`CallbackResult::Stop`] is returned.
+    pub fn process(&mut self, output: &mut [f32]) -> CallbackResult {
+        match self
+            .device
+            .as_mut()
+            .and_then(|device| device.process(output))
+        {
+            Some(result) => result,
+            None => {
+                output.fill(0.0);
+                CallbackResult::Stop
+            }
         }
     }
 }
 
 #[cfg_attr(all(target_os = "unknown", target_arch = "wasm32"), wasm_bindgen)]
 impl OutputDevice {
-    /// Closes the output device and release all system resources occupied by it. Any calls of this
-    /// method after the device was closed does nothing.
+    /// Closes the output device and release all system resources occupied by it. The data callback is not
+    /// called anymore after this method returns (on WebAssembly, no new buffers are scheduled). Any calls of
+    /// this method after the device was closed does nothing, and it is safe to drop a closed device.
     pub fn close(&mut self) {
+        #[cfg(not(all(target_os = "unknown", target_arch = "wasm32")))]
+        self.fade_out();
         self.device.take();
     }
+
+    /// Returns `true` if the device is actually playing the samples. It is `false` after [`Self::close`]
+    /// was called, or when the device was stopped because of an internal failure (for example, the feed
+    /// thread has exited or the audio server was shut down). On WebAssembly it reflects the state of the
+    /// audio context, which is suspended until the user interacts with the page.
+    pub fn is_playing(&self) -> bool {
+        self.device
+            .as_ref()
+            .map_or(false, |device| device.is_playing())
+    }
+
+    /// Returns the amount of frames the device reads at once (a "burst"), if the backend reports it. Only
+    /// AAudio (Android) reports it, the amount of samples produced by the data callback could be tuned to
+    /// a multiple of this value.
+    pub fn frames_per_burst(&self) -> Option<usize> {
+        self.device
+            .as_ref()
+            .and_then(|device| device.frames_per_burst())
+    }
+
+    /// Returns the size (in frames) of the buffer the backend has actually allocated for the device, if it
+    /// reports it. The device could clamp the requested size to its limits, so the latency of the device
+    /// could differ from [`OutputDeviceParameters::buffer_duration`]. The buffer passed to the data callback
+    /// always has the requested size anyway. If the device cannot hold a single buffer of the data callback,
+    /// it fails to open with [`TinyAudioError::InvalidParameters`]. Only ALSA (Linux) and DirectSound
+    /// (Windows) report it.
+    pub fn device_buffer_size(&self) -> Option<usize> {
+        self.device
+            .as_ref()
+            .and_then(|device| device.device_buffer_size())
+    }
+
+    /// Returns the size (in frames) of the period of the device, i.e. the amount of frames the device
+    /// consumes between the wake-ups of the feed thread, if the backend reports it. Together with
+    /// [`Self::device_buffer_size`] it tells whether `channel_sample_count` was honored or rounded by the
+    /// device. Only ALSA (Linux) reports it.
+    pub fn device_period_size(&self) -> Option<usize> {
+        self.device
+            .as_ref()
+            .and_then(|device| device.device_period_size())
+    }
+
+    /// Returns the sample rate (in Hz) the device actually runs at, if the backend reports it. On WebAudio it
+    /// differs from [`OutputDeviceParameters::sample_rate`] if the samples are resampled (see
+    /// [`WebParameters::native_sample_rate`]). On ALSA (Linux) it differs if the device does not support the
+    /// requested rate and the nearest one was chosen, the samples are played at that rate without
+    /// resampling, so the pitch changes. Only WebAudio and ALSA report it.
+    pub fn device_sample_rate(&self) -> Option<usize> {
+        self.device
+            .as_ref()
+            .and_then(|device| device.device_sample_rate())
+    }
+
+    /// Returns the ratio of [`Self::device_sample_rate`] to [`OutputDeviceParameters::sample_rate`], if the
+    /// backend reports the rate of the device. See [`Self::is_resampling`].
+    pub fn resample_ratio(&self) -> Option<f64> {
+        self.device_sample_rate()
+            .map(|device_sample_rate| device_sample_rate as f64 / self.params.sample_rate as f64)
+    }
+
+    /// Returns `true` if the device runs at another sample rate than the requested one (see
+    /// [`Self::device_sample_rate`]), so the samples are either resampled (WebAudio) or played faster or
+    /// slower (ALSA). Returns `false` if the rates match or the backend does not report the rate.
+    pub fn is_resampling(&self) -> bool {
+        self.device_sample_rate()
+            .map_or(false, |device_sample_rate| {
+                device_sample_rate != self.params.sample_rate
+            })
+    }
+
+    /// Returns how many times the device was reopened after it was disconnected (for example, when
+    /// headphones were plugged or unplugged) or after it has fallen back to a larger buffer (see
+    /// [`OutputDeviceParameters::adaptive_latency`]). Only AAudio (Android) reopens streams, it is always
+    /// zero for other backends.
+    pub fn reconnect_count(&self) -> usize {
+        self.device
+            .as_ref()
+            .map_or(0, |device| device.reconnect_count())
+    }
+}
+
+// The callbacks and the handles of the backend are not printed, only the state of the device.
+impl fmt::Debug for OutputDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OutputDevice")
+            .field("backend", &self.backend())
+            .field("is_playing", &self.is_playing())
+            .field("is_paused", &self.is_paused())
+            .field("params", &self.params)
+            .finish()
+    }
 }
 
 /// Creates a new output device that uses default audio output device of your operating system to play the
 /// samples produced by the specified `data_callback`. The callback will be called periodically to generate
-/// another portion of samples.
+/// another portion of samples. The buffer is filled with silence before each call, so the samples that were
+/// not written by the callback are silent.
 ///
 /// ## Examples
 ///
@@ -85,11 +1469,10 @@ impl OutputDevice {
 ///
 /// ```rust,no_run
 /// # use tinyaudio::prelude::*;
-/// let params = OutputDeviceParameters {
-///     channels_count: 2,
-///     sample_rate: 44100,
-///     channel_sample_count: 4410,
-/// };
+/// let mut params = OutputDeviceParameters::default();
+/// params.channels_count = 2;
+/// params.sample_rate = 44100;
+/// params.channel_sample_count = 4410;
 ///
 /// let _device = run_output_device(params, {
 ///     let mut clock = 0f32;
@@ -108,17 +1491,423 @@ impl OutputDevice {
 ///
 /// std::thread::sleep(std::time::Duration::from_secs(5));
 /// ```
-#[allow(clippy::needless_return)]
-pub fn run_output_device<C>(
+pub fn run_output_device<C, R>(
+    params: OutputDeviceParameters,
+    mut data_callback: C,
+) -> Result<OutputDevice, Box<dyn Error>>
+where
+    C: FnMut(&mut [f32]) -> R + MaybeSend + 'static,
+    R: IntoCallbackResult,
+{
+    run_output_device_with_time_info(params, move |data, _| data_callback(data))
+}
+
+/// The same as [`run_output_device`], but the data callback produces `f64` samples. Useful for high-precision
+/// synthesis (for example, when the phase of oscillators is accumulated in `f64`), since the samples are
+/// converted to the native format of the device only right before sending them to the device. All the
+/// precision of `f64` is kept throughout the processing in the callback.
+pub fn run_output_device_f64<C, R>(
+    params: OutputDeviceParameters,
+    mut data_callback: C,
+) -> Result<OutputDevice, Box<dyn Error>>
+where
+    C: FnMut(&mut [f64]) -> R + MaybeSend + 'static,
+    R: IntoCallbackResult,
+{
+    let mut samples = Vec::new();
+    run_output_device(params, move |data| {
+        // Fill with silence, like the `f32` buffer. The size of the buffer changes only if the device was
+        // reconfigured, so it allocates only once.
+        samples.clear();
+        samples.resize(data.len(), 0.0f64);
+        let result = data_callback(&mut samples);
+        for (out_sample, in_sample) in data.iter_mut().zip(samples.iter()) {
+            *out_sample = *in_sample as f32;
+        }
+        result
+    })
+}
+
+/// The same as [`run_output_device`], but the data callback also receives the timing information of the
+/// portion of samples it produces. It could be used for sample-accurate scheduling of events (for example,
+/// metronome ticks), which cannot be done reliably by counting callback invocations.
+///
+/// ## Examples
+///
+/// The following example plays a short click at the start of each second.
+///
+/// ```rust,no_run
+/// # use tinyaudio::prelude::*;
+/// let params = OutputDeviceParameters::default();
+///
+/// let _device = run_output_device_with_time_info(params, move |data, time_info| {
+///     for (i, samples) in data.chunks_mut(params.channels_count).enumerate() {
+///         let frame = time_info.frame_position + i as u64;
+///         let value = if frame % (params.sample_rate as u64) < 100 {
+///             1.0
+///         } else {
+///             0.0
+///         };
+///         for sample in samples {
+///             *sample = value;
+///         }
+///     }
+/// })
+/// .unwrap();
+///
+/// std::thread::sleep(std::time::Duration::from_secs(5));
+/// ```
+pub fn run_output_device_with_time_info<C, R>(
     params: OutputDeviceParameters,
     data_callback: C,
 ) -> Result<OutputDevice, Box<dyn Error>>
 where
-    C: FnMut(&mut [f32]) + Send + 'static,
+    C: FnMut(&mut [f32], AudioTimeInfo) -> R + MaybeSend + 'static,
+    R: IntoCallbackResult,
+{
+    create_output_device(params, data_callback, false)
+}
+
+fn create_output_device<C, R>(
+    params: OutputDeviceParameters,
+    mut data_callback: C,
+    is_manual: bool,
+) -> Result<OutputDevice, Box<dyn Error>>
+where
+    C: FnMut(&mut [f32], AudioTimeInfo) -> R + MaybeSend + 'static,
+    R: IntoCallbackResult,
 {
+    let params = params.resolve_channel_sample_count();
+    let data_callback: SharedDataCallback =
+        Arc::new(Mutex::new(Box::new(move |data: &mut [f32], time_info| {
+            data_callback(data, time_info).into_callback_result()
+        })));
+    let monitor_callback: SharedMonitorCallback = Arc::new(Mutex::new(None));
+    let playback_state = Arc::new(PlaybackState::default());
+    playback_state
+        .paused
+        .store(params.start_paused, Ordering::SeqCst);
+    Ok(OutputDevice {
+        device: Some(open_device(
+            params,
+            data_callback.clone(),
+            monitor_callback.clone(),
+            playback_state.clone(),
+            is_manual,
+        )?),
+        data_callback,
+        monitor_callback,
+        default_device_listener: None,
+        is_manual,
+        params,
+        playback_state,
+    })
+}
+
+/// Plays the samples produced by the specified `data_callback` for the given wall-clock `duration` and
+/// blocks the current thread until done. The device is closed before the function returns. Useful for
+/// command line tools that play a fixed-length sound and exit. Not available on WebAssembly, since it
+/// cannot block the main thread.
+///
+/// ## Examples
+///
+/// ```rust,no_run
+/// # use tinyaudio::prelude::*;
+/// // Plays silence for one second.
+/// play_for(
+///     OutputDeviceParameters::default(),
+///     std::time::Duration::from_secs(1),
+///     |_| {},
+/// )
+/// .unwrap();
+/// ```
+#[cfg(not(all(target_os = "unknown", target_arch = "wasm32")))]
+pub fn play_for<C, R>(
+    params: OutputDeviceParameters,
+    duration: Duration,
+    data_callback: C,
+) -> Result<(), Box<dyn Error>>
+where
+    C: FnMut(&mut [f32]) -> R + MaybeSend + 'static,
+    R: IntoCallbackResult,
+{
+    let mut device = run_output_device(params, data_callback)?;
+    std::thread::sleep(duration);
+    device.close();
+    Ok(())
+}
+
+/// Plays the samples produced by the specified `data_callback` until the `stop` flag is set to `true` and
+/// blocks the current thread until then. The flag could be set from any other thread (or from the data
+/// callback itself). The device is closed before the function returns. Not available on WebAssembly, since
+/// it cannot block the main thread.
+#[cfg(not(all(target_os = "unknown", target_arch = "wasm32")))]
+pub fn play_until<C, R>(
+    params: OutputDeviceParameters,
+    data_callback: C,
+    stop: Arc<AtomicBool>,
+) -> Result<(), Box<dyn Error>>
+where
+    C: FnMut(&mut [f32]) -> R + MaybeSend + 'static,
+    R: IntoCallbackResult,
+{
+    let mut device = run_output_device(params, data_callback)?;
+    while !stop.load(Ordering::SeqCst) {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    device.close();
+    Ok(())
+}
+
+/// Multiplies the interleaved frames by a linear gain ramp of `length` frames, `position` is the position
+/// of the first frame in the ramp. The gain goes from zero to one for the fade-in and vice versa.
+fn apply_gain_ramp(
+    data: &mut [f32],
+    channels_count: usize,
+    position: u64,
+    length: u64,
+    is_fade_in: bool,
+) {
+    for (i, frame) in data.chunks_mut(channels_count).enumerate() {
+        let progress = if length == 0 {
+            1.0
+        } else {
+            ((position + i as u64) as f32 / length as f32).min(1.0)
+        };
+        let gain = if is_fade_in { progress } else { 1.0 - progress };
+        for sample in frame {
+            *sample *= gain;
+        }
+    }
+}
+
+/// Weight of the last buffer in the smoothed load of the data callback, see `OutputDevice::cpu_load`.
+#[cfg(not(all(target_os = "unknown", target_arch = "wasm32")))]
+const CPU_LOAD_SMOOTHING: f32 = 0.1;
+
+/// Calls the data callback, a panic in it is caught and stored in `playback_state` (see
+/// [`OutputDevice::take_panic`]), the buffer is silenced and the device is asked to stop then.
+fn call_data_callback(
+    data_callback: &SharedDataCallback,
+    playback_state: &PlaybackState,
+    data: &mut [f32],
+    time_info: AudioTimeInfo,
+) -> CallbackResult {
+    // The guard outlives the caught panic, so the mutex is not poisoned and the callback could be replaced.
+    let mut data_callback = data_callback.lock().unwrap();
+    match catch_unwind(AssertUnwindSafe(|| data_callback(data, time_info))) {
+        Ok(result) => result,
+        Err(payload) => {
+            data.fill(0.0);
+            *playback_state.panic.lock().unwrap() = Some(payload);
+            CallbackResult::Stop
+        }
+    }
+}
+
+/// Calls the callback set by [`OutputDevice::on_started`], if there's one. A panic in it is caught and
+/// stored just like a panic in the data callback, but the device keeps playing.
+fn notify_started(playback_state: &PlaybackState) {
+    let started_callback = playback_state.started_callback.lock().unwrap().take();
+    if let Some(started_callback) = started_callback {
+        if let Err(payload) = catch_unwind(AssertUnwindSafe(started_callback)) {
+            *playback_state.panic.lock().unwrap() = Some(payload);
+        }
+    }
+}
+
+/// Calls the data callback through the warmup queue, if there's one. The samples rendered by the callback
+/// are played after the queued ones, so its frame position is ahead by the amount of queued frames.
+fn call_data_callback_queued(
+    data_callback: &SharedDataCallback,
+    playback_state: &PlaybackState,
+    warmup: Option<&mut warmup::WarmupQueue>,
+    data: &mut [f32],
+    time_info: AudioTimeInfo,
+) -> CallbackResult {
+    match warmup {
+        Some(warmup) => warmup.fill(data, |block, queued_frames| {
+            let time_info = AudioTimeInfo {
+                frame_position: time_info.frame_position + queued_frames as u64,
+                ..time_info
+            };
+            call_data_callback(data_callback, playback_state, block, time_info)
+        }),
+        None => call_data_callback(data_callback, playback_state, data, time_info),
+    }
+}
+
+/// Pans the interleaved stereo frames with the equal-power law of the `WebAudio` `StereoPannerNode`.
+fn apply_pan(data: &mut [f32], pan: f32) {
+    let x = if pan <= 0.0 { pan + 1.0 } else { pan } * std::f32::consts::FRAC_PI_2;
+    let (gain_left, gain_right) = (x.cos(), x.sin());
+    for frame in data.chunks_exact_mut(2) {
+        let (left, right) = (frame[0], frame[1]);
+        if pan <= 0.0 {
+            frame[0] = left + right * gain_left;
+            frame[1] = right * gain_right;
+        } else {
+            frame[0] = left * gain_left;
+            frame[1] = right + left * gain_right;
+        }
+    }
+}
+
+#[allow(clippy::needless_return)]
+fn open_device(
+    params: OutputDeviceParameters,
+    data_callback: SharedDataCallback,
+    monitor_callback: SharedMonitorCallback,
+    playback_state: Arc<PlaybackState>,
+    is_manual: bool,
+) -> Result<Box<dyn BaseAudioOutputDevice>, Box<dyn Error>> {
+    params.validate()?;
+
+    let mut frame_position = 0;
+    let fade_in_frames = params.fade_in.map_or(0, |fade_in| {
+        params.seconds_to_sample_count(fade_in.as_secs_f32()) as u64
+    });
+    let fade_out_frames = params.fade_out.map_or(0, |fade_out| {
+        params.seconds_to_sample_count(fade_out.as_secs_f32()) as u64
+    });
+    let mut fade_out_position = 0;
+    // The request could be left from the previous device (if the fade-out has timed out).
+    playback_state
+        .fade_out_request
+        .store(false, Ordering::SeqCst);
+    // The callback renders a different amount of channels, the samples are mixed into the device layout.
+    let mut remixer = params
+        .source_channels
+        .filter(|&source_channels| source_channels != params.channels_count)
+        .map(|source_channels| {
+            (
+                ChannelMatrix::default_for(source_channels, params.channels_count),
+                Vec::new(),
+            )
+        });
+    // The first buffers are rendered before the device is started, unless the callback must not be called
+    // yet.
+    let mut warmup = (params.warmup_buffers > 0).then(|| {
+        let mut warmup = warmup::WarmupQueue::new(
+            params.source_channels.unwrap_or(params.channels_count),
+            params.channel_sample_count,
+            params.warmup_buffers,
+        );
+        if !playback_state.paused.load(Ordering::SeqCst) {
+            warmup.prerender(|block, queued_frames| {
+                let time_info = AudioTimeInfo {
+                    frame_position: queued_frames as u64,
+                    device_time: None,
+                };
+                call_data_callback(&data_callback, &playback_state, block, time_info)
+            });
+        }
+        warmup
+    });
+    let data_callback = move |data: &mut [f32], device_time: Option<f64>| {
+        if playback_state.paused.load(Ordering::SeqCst) {
+            data.fill(0.0);
+            // The device is silent already, there's nothing to fade out.
+            return if playback_state.fade_out_request.load(Ordering::SeqCst) {
+                CallbackResult::Stop
+            } else {
+                CallbackResult::Continue
+            };
+        }
+        if !playback_state.has_started.swap(true, Ordering::SeqCst) {
+            notify_started(&playback_state);
+        }
+        let time_info = AudioTimeInfo {
+            frame_position,
+            device_time,
+        };
+        let frame_count = data.len() / params.channels_count;
+        frame_position += frame_count as u64;
+        // Samples that weren't written by the callback (for example, the tail of the last buffer before
+        // stopping) must be silent instead of repeating the previous portion of samples.
+        data.fill(0.0);
+        #[cfg(not(all(target_os = "unknown", target_arch = "wasm32")))]
+        let started_at = if params.measure_cpu_load && frame_count > 0 {
+            Some(std::time::Instant::now())
+        } else {
+            None
+        };
+        let mut result = if let Some((matrix, source_samples)) = remixer.as_mut() {
+            source_samples.clear();
+            source_samples.resize(frame_count * matrix.source_channels(), 0.0);
+            let result = call_data_callback_queued(
+                &data_callback,
+                &playback_state,
+                warmup.as_mut(),
+                source_samples,
+                time_info,
+            );
+            matrix.apply(source_samples, data);
+            result
+        } else {
+            call_data_callback_queued(
+                &data_callback,
+                &playback_state,
+                warmup.as_mut(),
+                data,
+                time_info,
+            )
+        };
+        #[cfg(not(all(target_os = "unknown", target_arch = "wasm32")))]
+        if let Some(started_at) = started_at {
+            let load =
+                started_at.elapsed().as_secs_f32() * params.sample_rate as f32 / frame_count as f32;
+            let previous = f32::from_bits(playback_state.cpu_load.load(Ordering::SeqCst));
+            playback_state.cpu_load.store(
+                (previous + (load - previous) * CPU_LOAD_SMOOTHING).to_bits(),
+                Ordering::SeqCst,
+            );
+        }
+        let pan = f32::from_bits(playback_state.pan.load(Ordering::SeqCst));
+        if pan != 0.0 && params.channels_count == 2 {
+            apply_pan(data, pan);
+        }
+        if time_info.frame_position < fade_in_frames {
+            apply_gain_ramp(
+                data,
+                params.channels_count,
+                time_info.frame_position,
+                fade_in_frames,
+                true,
+            );
+        }
+        if playback_state.fade_out_request.load(Ordering::SeqCst) {
+            apply_gain_ramp(
+                data,
+                params.channels_count,
+                fade_out_position,
+                fade_out_frames,
+                false,
+            );
+            fade_out_position += frame_count as u64;
+            if fade_out_position >= fade_out_frames {
+                result = CallbackResult::Stop;
+            }
+        }
+        playback_state
+            .frames_played
+            .fetch_add(frame_count as u64, Ordering::SeqCst);
+        // Never wait for the monitor to be replaced, it is better to skip one portion of samples.
+        if let Ok(mut monitor_callback) = monitor_callback.try_lock() {
+            if let Some(monitor_callback) = monitor_callback.as_mut() {
+                monitor_callback(data);
+            }
+        }
+        result
+    };
+
+    if is_manual {
+        return Ok(Box::new(manual::ManualOutputDevice::new(data_callback)));
+    }
+
     #[cfg(target_os = "windows")]
     {
-        return Ok(OutputDevice::new(directsound::DirectSoundDevice::new(
+        return Ok(Box::new(directsound::DirectSoundDevice::new(
             params,
             data_callback,
         )?));
@@ -126,7 +1915,7 @@ where
 
     #[cfg(target_os = "android")]
     {
-        return Ok(OutputDevice::new(aaudio::AAudioOutputDevice::new(
+        return Ok(Box::new(aaudio::AAudioOutputDevice::new(
             params,
             data_callback,
         )?));
@@ -134,23 +1923,59 @@ where
 
     #[cfg(target_os = "linux")]
     {
-        return Ok(OutputDevice::new(alsa::AlsaSoundDevice::new(
+        let backend = params.linux.backend;
+
+        // Prefer JACK if its server is running, otherwise fall back to ALSA (unless JACK was requested
+        // explicitly).
+        #[cfg(feature = "jack")]
+        if backend != LinuxBackend::Alsa {
+            match jack::open_client(&params) {
+                Ok(client) => {
+                    return Ok(Box::new(jack::JackOutputDevice::start(
+                        client,
+                        params,
+                        data_callback,
+                    )?));
+                }
+                Err(TinyAudioError::DeviceUnavailable(_)) if backend == LinuxBackend::Auto => (),
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        #[cfg(not(feature = "jack"))]
+        if backend == LinuxBackend::Jack {
+            return Err(TinyAudioError::DeviceUnavailable(
+                "JACK backend is disabled, enable the `jack` feature".to_string(),
+            )
+            .into());
+        }
+
+        return Ok(Box::new(alsa::AlsaSoundDevice::new(params, data_callback)?));
+    }
+
+    #[cfg(all(target_os = "openbsd", feature = "sndio"))]
+    {
+        let handle = sndio::open_handle()?;
+        return Ok(Box::new(sndio::SndioOutputDevice::start(
+            handle,
             params,
             data_callback,
         )?));
     }
 
+    #[cfg(all(target_os = "freebsd", feature = "oss"))]
+    {
+        return Ok(Box::new(oss::OssOutputDevice::new(params, data_callback)?));
+    }
+
     #[cfg(all(target_os = "unknown", target_arch = "wasm32"))]
     {
-        return Ok(OutputDevice::new(web::WebAudioDevice::new(
-            params,
-            data_callback,
-        )?));
+        return Ok(Box::new(web::WebAudioDevice::new(params, data_callback)?));
     }
 
     #[cfg(any(target_os = "macos", target_os = "ios"))]
     {
-        return Ok(OutputDevice::new(coreaudio::CoreaudioSoundDevice::new(
+        return Ok(Box::new(coreaudio::CoreaudioSoundDevice::new(
             params,
             data_callback,
         )?));
@@ -162,9 +1987,127 @@ where
         target_os = "android",
         target_os = "macos",
         target_os = "ios",
+        all(target_os = "freebsd", feature = "oss"),
+        all(target_os = "openbsd", feature = "sndio"),
         all(target_os = "unknown", target_arch = "wasm32")
     )))]
     {
         Err("Platform is not supported".to_string().into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_position_advances_by_channel_sample_count() {
+        let params = OutputDeviceParameters {
+            channels_count: 2,
+            channel_sample_count: 64,
+            ..Default::default()
+        };
+        let positions = Arc::new(Mutex::new(Vec::new()));
+        let callback_positions = positions.clone();
+        let mut device = create_output_device(
+            params,
+            move |_: &mut [f32], time_info: AudioTimeInfo| {
+                callback_positions
+                    .lock()
+                    .unwrap()
+                    .push(time_info.frame_position);
+            },
+            true,
+        )
+        .unwrap();
+        let mut buffer = vec![0.0; params.total_samples()];
+        for _ in 0..4 {
+            device.process(&mut buffer);
+        }
+        assert_eq!(*positions.lock().unwrap(), [0, 64, 128, 192]);
+    }
+
+    #[test]
+    fn test_zero_sample_rate_is_zero_duration() {
+        assert_eq!(samples_to_duration(4410, 0), Duration::ZERO);
+        assert_eq!(duration_to_samples(Duration::from_secs(1), 0), 0);
+        let params = OutputDeviceParameters {
+            sample_rate: 0,
+            ..Default::default()
+        };
+        assert_eq!(params.buffer_duration(), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_zero_duration_is_zero_samples() {
+        assert_eq!(duration_to_samples(Duration::ZERO, 44100), 0);
+        assert_eq!(duration_to_samples(Duration::ZERO, 0), 0);
+    }
+
+    #[test]
+    fn test_half_sample_is_rounded_up() {
+        // A sample lasts exactly one millisecond at 1000 Hz.
+        assert_eq!(duration_to_samples(Duration::from_micros(500), 1000), 1);
+        assert_eq!(duration_to_samples(Duration::from_nanos(499_999), 1000), 0);
+        assert_eq!(duration_to_samples(Duration::from_micros(1500), 1000), 2);
+        assert_eq!(duration_to_samples(Duration::from_micros(2500), 1000), 3);
+        assert_eq!(
+            duration_to_samples(Duration::from_nanos(2_499_999), 1000),
+            2
+        );
+    }
+
+    fn pan(pan: f32, left: f32, right: f32) -> (f32, f32) {
+        let mut frame = [left, right];
+        apply_pan(&mut frame, pan);
+        (frame[0], frame[1])
+    }
+
+    fn assert_near(actual: (f32, f32), expected: (f32, f32)) {
+        assert!(
+            (actual.0 - expected.0).abs() < 1e-6 && (actual.1 - expected.1).abs() < 1e-6,
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_pan_law() {
+        // Center leaves the channels as is, the edges move one channel into the other.
+        assert_near(pan(0.0, 0.5, 0.25), (0.5, 0.25));
+        assert_near(pan(-1.0, 0.5, 0.25), (0.75, 0.0));
+        assert_near(pan(1.0, 0.5, 0.25), (0.0, 0.75));
+
+        // The moved part of the channel keeps its power.
+        for &value in &[-0.75, -0.5, -0.25, 0.25, 0.5, 0.75] {
+            let (left, right) = if value < 0.0 {
+                pan(value, 0.0, 1.0)
+            } else {
+                pan(value, 1.0, 0.0)
+            };
+            assert!((left * left + right * right - 1.0).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_close_twice_then_drop() {
+        let mut device =
+            manual::run_output_device_manual(OutputDeviceParameters::default(), |data| {
+                data.fill(0.5)
+            })
+            .unwrap();
+        assert!(device.is_playing());
+
+        device.close();
+        device.close();
+        assert!(!device.is_playing());
+
+        // A closed device outputs silence.
+        let mut buffer = vec![0.5; 128];
+        assert_eq!(device.process(&mut buffer), CallbackResult::Stop);
+        assert!(buffer.iter().all(|&sample| sample == 0.0));
+
+        drop(device);
+    }
+}
@@ -0,0 +1,171 @@
+//! Manual output, where the host calls [`crate::OutputDevice::process`] from its own audio thread instead of
+//! a backend feeding the device.
+
+use crate::{
+    Backend, BaseAudioOutputDevice, CallbackResult, IntoCallbackResult, MaybeSend, OutputDevice,
+    OutputDeviceParameters,
+};
+use std::error::Error;
+
+/// A device that does not own any thread nor any system resource, the samples are rendered on demand.
+pub struct ManualOutputDevice<C> {
+    data_callback: C,
+    is_playing: bool,
+}
+
+// WebAssembly is single-threaded, the device never leaves the main thread.
+#[cfg(all(target_os = "unknown", target_arch = "wasm32"))]
+unsafe impl<C> Send for ManualOutputDevice<C> {}
+
+impl<C> ManualOutputDevice<C>
+where
+    C: FnMut(&mut [f32], Option<f64>) -> CallbackResult + MaybeSend + 'static,
+{
+    pub fn new(data_callback: C) -> Self {
+        Self {
+            data_callback,
+            is_playing: true,
+        }
+    }
+}
+
+impl<C> BaseAudioOutputDevice for ManualOutputDevice<C>
+where
+    C: FnMut(&mut [f32], Option<f64>) -> CallbackResult + MaybeSend + 'static,
+{
+    fn is_playing(&self) -> bool {
+        self.is_playing
+    }
+
+    fn backend(&self) -> Backend {
+        Backend::Manual
+    }
+
+    fn process(&mut self, output: &mut [f32]) -> Option<CallbackResult> {
+        if !self.is_playing {
+            output.fill(0.0);
+            return Some(CallbackResult::Stop);
+        }
+
+        let result = (self.data_callback)(output, None);
+        if result == CallbackResult::Stop {
+            self.is_playing = false;
+        }
+        Some(result)
+    }
+}
+
+/// Creates an output device that does not spawn any thread and does not open any system device. Instead,
+/// the host calls [`OutputDevice::process`] from its own audio thread (for example, the audio thread of a
+/// VST or CLAP plugin host), which calls the specified `data_callback` and applies all the processing of the
+/// crate (silence fill, channel mixing, monitor callback). `params.channel_sample_count`,
+/// `params.thread_priority` and `params.cpu_affinity` are ignored, the host decides how many samples are
+/// rendered at once.
+///
+/// ## Examples
+///
+/// ```rust,no_run
+/// # use tinyaudio::prelude::*;
+/// let params = OutputDeviceParameters::default();
+///
+/// let mut device = run_output_device_manual(params, |data| {
+///     for sample in data {
+///         *sample = 0.25;
+///     }
+/// })
+/// .unwrap();
+///
+/// // Called by the host for each block of samples.
+/// let mut block = vec![0.0; 256 * params.channels_count];
+/// device.process(&mut block);
+/// ```
+pub fn run_output_device_manual<C, R>(
+    params: OutputDeviceParameters,
+    mut data_callback: C,
+) -> Result<OutputDevice, Box<dyn Error>>
+where
+    C: FnMut(&mut [f32]) -> R + MaybeSend + 'static,
+    R: IntoCallbackResult,
+{
+    crate::create_output_device(params, move |data, _| data_callback(data), true)
+}
+
+/// Renders `buffer_count` buffers of `params.total_samples()` interleaved samples with the specified
+/// `data_callback` and returns them concatenated, without opening any device. All the processing of the
+/// crate (silence fill, channel mixing, fade-in) is applied, so the result is exactly what a device would
+/// play. Useful to test the output of a callback or to benchmark it in isolation. The buffers after the
+/// callback has asked to stop are silent.
+///
+/// ## Examples
+///
+/// ```rust
+/// # use tinyaudio::prelude::*;
+/// let mut params = OutputDeviceParameters::default();
+/// params.channels_count = 2;
+/// params.channel_sample_count = 256;
+///
+/// let samples = render(params, |data| data.fill(0.25), 4).unwrap();
+/// assert_eq!(samples.len(), 4 * params.total_samples());
+/// assert!(samples.iter().all(|&sample| sample == 0.25));
+/// ```
+pub fn render<C, R>(
+    params: OutputDeviceParameters,
+    data_callback: C,
+    buffer_count: usize,
+) -> Result<Vec<f32>, Box<dyn Error>>
+where
+    C: FnMut(&mut [f32]) -> R + MaybeSend + 'static,
+    R: IntoCallbackResult,
+{
+    let mut device = run_output_device_manual(params, data_callback)?;
+    let params = device.params();
+    let mut samples = vec![0.0; buffer_count * params.total_samples()];
+    for buffer in samples.chunks_exact_mut(params.total_samples()) {
+        device.process(buffer);
+    }
+    Ok(samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tail_is_silent_after_stop() {
+        let params = OutputDeviceParameters {
+            channels_count: 2,
+            channel_sample_count: 64,
+            ..Default::default()
+        };
+        let half = params.total_samples() / 2;
+        let samples = render(
+            params,
+            move |data| {
+                data[..half].fill(0.5);
+                CallbackResult::Stop
+            },
+            3,
+        )
+        .unwrap();
+        assert!(samples[..half].iter().all(|&sample| sample == 0.5));
+        assert!(samples[half..].iter().all(|&sample| sample == 0.0));
+    }
+
+    #[test]
+    fn test_stale_samples_are_not_replayed() {
+        // The host passes the same buffer again, it must not keep the samples of the previous call.
+        let mut calls = 0;
+        let mut device = run_output_device_manual(OutputDeviceParameters::default(), move |data| {
+            calls += 1;
+            if calls == 1 {
+                data.fill(0.5);
+            }
+        })
+        .unwrap();
+        let mut buffer = vec![0.0; 128];
+        device.process(&mut buffer);
+        assert!(buffer.iter().all(|&sample| sample == 0.5));
+        device.process(&mut buffer);
+        assert!(buffer.iter().all(|&sample| sample == 0.0));
+    }
+}
@@ -0,0 +1,289 @@
+//! Windows default output device information and notifications via `MMDevice API`.
+
+#![cfg(target_os = "windows")]
+
+use crate::{DeviceInfo, TinyAudioError};
+use std::{
+    error::Error,
+    ptr::null_mut,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Mutex,
+    },
+};
+use winapi::{
+    ctypes::c_void,
+    shared::{
+        guiddef::{IsEqualIID, REFIID},
+        minwindef::{DWORD, ULONG},
+        mmreg::WAVEFORMATEX,
+        winerror::{E_NOINTERFACE, HRESULT, S_OK},
+        wtypes::{PROPERTYKEY, VT_BLOB, VT_LPWSTR},
+    },
+    um::{
+        combaseapi::{CoCreateInstance, CoInitializeEx, PropVariantClear, CLSCTX_ALL},
+        coml2api::STGM_READ,
+        functiondiscoverykeys_devpkey::PKEY_Device_FriendlyName,
+        mmdeviceapi::{
+            eConsole, eRender, CLSID_MMDeviceEnumerator, EDataFlow, ERole, IMMDevice,
+            IMMDeviceEnumerator, IMMNotificationClient, IMMNotificationClientVtbl,
+            PKEY_AudioEngine_DeviceFormat,
+        },
+        objbase::COINIT_MULTITHREADED,
+        propidl::PROPVARIANT,
+        propsys::IPropertyStore,
+        unknwnbase::{IUnknown, IUnknownVtbl},
+        winnt::LPCWSTR,
+    },
+    Interface,
+};
+
+type DefaultDeviceChangedCallback = Box<dyn FnMut() + Send + 'static>;
+
+fn check(code: HRESULT, message: &str) -> Result<(), Box<dyn Error>> {
+    if code >= 0 {
+        Ok(())
+    } else {
+        Err(format!("{}. Error code {:#x}", message, code).into())
+    }
+}
+
+/// Creates the device enumerator, initializes COM on the calling thread if needed.
+unsafe fn create_enumerator() -> Result<*mut IMMDeviceEnumerator, Box<dyn Error>> {
+    // COM could be already initialized on this thread with another concurrency model, the enumerator works
+    // with both of them, so the result is ignored.
+    CoInitializeEx(null_mut(), COINIT_MULTITHREADED);
+
+    let mut enumerator: *mut IMMDeviceEnumerator = null_mut();
+    check(
+        CoCreateInstance(
+            &CLSID_MMDeviceEnumerator,
+            null_mut(),
+            CLSCTX_ALL,
+            &IMMDeviceEnumerator::uuidof(),
+            &mut enumerator as *mut *mut IMMDeviceEnumerator as *mut *mut c_void,
+        ),
+        "Failed to create `IMMDeviceEnumerator`",
+    )?;
+    Ok(enumerator)
+}
+
+/// Reads the property of the device, `read` gets the value only if it has the expected type.
+unsafe fn read_property<R>(
+    store: *mut IPropertyStore,
+    key: &PROPERTYKEY,
+    read: impl FnOnce(&PROPVARIANT) -> Option<R>,
+) -> Option<R> {
+    let mut value: PROPVARIANT = std::mem::zeroed();
+    if (*store).GetValue(key, &mut value) < 0 {
+        return None;
+    }
+    let result = read(&value);
+    PropVariantClear(&mut value);
+    result
+}
+
+unsafe fn wide_string_to_string(string: *const u16) -> String {
+    let length = (0..).take_while(|&i| *string.add(i) != 0).count();
+    String::from_utf16_lossy(std::slice::from_raw_parts(string, length))
+}
+
+/// Queries the name and the mix format of the default console output endpoint.
+pub fn default_output_device_info() -> Result<DeviceInfo, TinyAudioError> {
+    unsafe {
+        let enumerator =
+            create_enumerator().map_err(|err| TinyAudioError::BackendError(err.to_string()))?;
+
+        let mut device: *mut IMMDevice = null_mut();
+        let res = (*enumerator).GetDefaultAudioEndpoint(eRender, eConsole, &mut device);
+        (*enumerator).Release();
+        if res < 0 {
+            return Err(TinyAudioError::DeviceUnavailable(format!(
+                "There's no default output device. Error code {:#x}",
+                res
+            )));
+        }
+
+        let mut store: *mut IPropertyStore = null_mut();
+        let res = (*device).OpenPropertyStore(STGM_READ, &mut store);
+        (*device).Release();
+        if res < 0 {
+            return Err(TinyAudioError::BackendError(format!(
+                "Failed to `OpenPropertyStore`. Error code {:#x}",
+                res
+            )));
+        }
+
+        let name = read_property(store, &PKEY_Device_FriendlyName, |value| {
+            if value.vt == VT_LPWSTR as u16 {
+                Some(wide_string_to_string(*value.data.pwszVal()))
+            } else {
+                None
+            }
+        });
+        // The format the audio engine mixes the streams in, it has the native channel count and sample rate.
+        let format = read_property(store, &PKEY_AudioEngine_DeviceFormat, |value| {
+            let blob = value.data.blob();
+            if value.vt == VT_BLOB as u16
+                && blob.cbSize as usize >= std::mem::size_of::<WAVEFORMATEX>()
+            {
+                let format = std::ptr::read_unaligned(blob.pBlobData as *const WAVEFORMATEX);
+                Some((format.nChannels as usize, format.nSamplesPerSec as usize))
+            } else {
+                None
+            }
+        });
+        (*store).Release();
+
+        Ok(DeviceInfo::new_default(
+            "default".to_string(),
+            name.unwrap_or_else(|| "default".to_string()),
+            format.map(|(channels_count, _)| channels_count),
+            format.map(|(_, sample_rate)| sample_rate),
+        ))
+    }
+}
+
+/// Implementation of `IMMNotificationClient`, the vtable pointer must be the first field so a pointer to the
+/// struct is a valid pointer to the interface.
+#[repr(C)]
+struct NotificationClient {
+    vtbl: *const IMMNotificationClientVtbl,
+    ref_count: AtomicU32,
+    // Notifications are delivered on the threads of the system, the mutex serializes them.
+    callback: Mutex<DefaultDeviceChangedCallback>,
+}
+
+static NOTIFICATION_CLIENT_VTBL: IMMNotificationClientVtbl = IMMNotificationClientVtbl {
+    parent: IUnknownVtbl {
+        QueryInterface: query_interface,
+        AddRef: add_ref,
+        Release: release,
+    },
+    OnDeviceStateChanged: on_device_state_changed,
+    OnDeviceAdded: on_device_added,
+    OnDeviceRemoved: on_device_removed,
+    OnDefaultDeviceChanged: on_default_device_changed,
+    OnPropertyValueChanged: on_property_value_changed,
+};
+
+unsafe extern "system" fn query_interface(
+    this: *mut IUnknown,
+    riid: REFIID,
+    object: *mut *mut c_void,
+) -> HRESULT {
+    if IsEqualIID(&*riid, &IUnknown::uuidof())
+        || IsEqualIID(&*riid, &IMMNotificationClient::uuidof())
+    {
+        add_ref(this);
+        *object = this as *mut c_void;
+        S_OK
+    } else {
+        *object = null_mut();
+        E_NOINTERFACE
+    }
+}
+
+unsafe extern "system" fn add_ref(this: *mut IUnknown) -> ULONG {
+    let client = &*(this as *const NotificationClient);
+    client.ref_count.fetch_add(1, Ordering::SeqCst) + 1
+}
+
+unsafe extern "system" fn release(this: *mut IUnknown) -> ULONG {
+    let client = &*(this as *const NotificationClient);
+    let ref_count = client.ref_count.fetch_sub(1, Ordering::SeqCst) - 1;
+    if ref_count == 0 {
+        drop(Box::from_raw(this as *mut NotificationClient));
+    }
+    ref_count
+}
+
+unsafe extern "system" fn on_device_state_changed(
+    _this: *mut IMMNotificationClient,
+    _device_id: LPCWSTR,
+    _new_state: DWORD,
+) -> HRESULT {
+    S_OK
+}
+
+unsafe extern "system" fn on_device_added(
+    _this: *mut IMMNotificationClient,
+    _device_id: LPCWSTR,
+) -> HRESULT {
+    S_OK
+}
+
+unsafe extern "system" fn on_device_removed(
+    _this: *mut IMMNotificationClient,
+    _device_id: LPCWSTR,
+) -> HRESULT {
+    S_OK
+}
+
+unsafe extern "system" fn on_default_device_changed(
+    this: *mut IMMNotificationClient,
+    flow: EDataFlow,
+    role: ERole,
+    _default_device_id: LPCWSTR,
+) -> HRESULT {
+    // DirectSound plays to the default console device, the notifications for other roles are duplicates.
+    if flow == eRender && role == eConsole {
+        let client = &*(this as *const NotificationClient);
+        if let Ok(mut callback) = client.callback.lock() {
+            (callback)();
+        }
+    }
+    S_OK
+}
+
+unsafe extern "system" fn on_property_value_changed(
+    _this: *mut IMMNotificationClient,
+    _device_id: LPCWSTR,
+    _key: PROPERTYKEY,
+) -> HRESULT {
+    S_OK
+}
+
+/// Listens to the changes of the default output device, the listener is unregistered on drop.
+pub struct DefaultDeviceListener {
+    enumerator: *mut IMMDeviceEnumerator,
+    client: *mut NotificationClient,
+}
+
+unsafe impl Send for DefaultDeviceListener {}
+
+impl DefaultDeviceListener {
+    pub fn new(callback: DefaultDeviceChangedCallback) -> Result<Self, Box<dyn Error>> {
+        unsafe {
+            let enumerator = create_enumerator()?;
+
+            let client = Box::into_raw(Box::new(NotificationClient {
+                vtbl: &NOTIFICATION_CLIENT_VTBL,
+                ref_count: AtomicU32::new(1),
+                callback: Mutex::new(callback),
+            }));
+
+            // Releases both objects on failure.
+            let listener = Self { enumerator, client };
+
+            check(
+                (*enumerator)
+                    .RegisterEndpointNotificationCallback(client as *mut IMMNotificationClient),
+                "Failed to `RegisterEndpointNotificationCallback`",
+            )?;
+
+            Ok(listener)
+        }
+    }
+}
+
+impl Drop for DefaultDeviceListener {
+    fn drop(&mut self) {
+        unsafe {
+            (*self.enumerator)
+                .UnregisterEndpointNotificationCallback(self.client as *mut IMMNotificationClient);
+            release(self.client as *mut IUnknown);
+            (*self.enumerator).Release();
+        }
+    }
+}
@@ -0,0 +1,85 @@
+//! Playback of the same samples on several output devices at once.
+
+use crate::{
+    push::RingBuffer, run_output_device, IntoCallbackResult, MaybeSend, OutputDevice,
+    OutputDeviceParameters, TinyAudioError,
+};
+use std::{error::Error, sync::Arc};
+
+/// Creates several output devices, that play the same samples produced by the single `data_callback`. For
+/// example, it could be used to play the same stream on the speakers and on a capture card. The first
+/// parameters define the primary device, that calls the data callback. The samples are handed over to the
+/// other devices via lock-free ring buffers (that hold four buffers each), so every device pulls the samples
+/// with its own clock: the drift between the clocks of the devices is tolerated by playing silence when a
+/// ring buffer runs dry, and by dropping the samples when it is full.
+///
+/// All the parameters must have the same `sample_rate` and `channels_count`, otherwise the function fails
+/// with [`TinyAudioError::InvalidParameters`]. The other parameters (for example, `channel_sample_count` or
+/// the backend-specific ones) may differ. The devices are returned in the same order as the parameters,
+/// the other devices play silence once the primary device is closed. The backends always open the default output device, so the
+/// devices differ only by their parameters (for example, [`crate::LinuxParameters::backend`]).
+///
+/// ## Examples
+///
+/// ```rust,no_run
+/// # use tinyaudio::prelude::*;
+/// let params = OutputDeviceParameters::default();
+///
+/// let _devices = run_output_device_multi(&[params, params], |_| {
+///     // Output silence to both devices.
+/// })
+/// .unwrap();
+///
+/// std::thread::sleep(std::time::Duration::from_secs(1));
+/// ```
+pub fn run_output_device_multi<C, R>(
+    params: &[OutputDeviceParameters],
+    mut data_callback: C,
+) -> Result<Vec<OutputDevice>, Box<dyn Error>>
+where
+    C: FnMut(&mut [f32]) -> R + MaybeSend + 'static,
+    R: IntoCallbackResult,
+{
+    let (primary_params, secondary_params) = params.split_first().ok_or_else(|| {
+        TinyAudioError::InvalidParameters("At least one device must be specified".to_string())
+    })?;
+
+    for device_params in secondary_params {
+        if device_params.sample_rate != primary_params.sample_rate
+            || device_params.channels_count != primary_params.channels_count
+        {
+            return Err(Box::new(TinyAudioError::InvalidParameters(
+                "All the devices must have the same `sample_rate` and `channels_count`".to_string(),
+            )));
+        }
+    }
+
+    let mut ring_buffers = Vec::new();
+    let mut secondary_devices = Vec::new();
+    let primary_params = primary_params.resolve_channel_sample_count();
+    for device_params in secondary_params.iter().copied() {
+        let device_params = device_params.resolve_channel_sample_count();
+        let ring_buffer = Arc::new(RingBuffer::new(
+            4 * device_params
+                .total_samples()
+                .max(primary_params.total_samples()),
+        ));
+        secondary_devices.push(run_output_device(device_params, {
+            let ring_buffer = ring_buffer.clone();
+            move |data| ring_buffer.read_frames(data, device_params.channels_count)
+        })?);
+        ring_buffers.push(ring_buffer);
+    }
+
+    let primary_device = run_output_device(primary_params, move |data| {
+        let result = data_callback(data).into_callback_result();
+        for ring_buffer in ring_buffers.iter() {
+            ring_buffer.write_frames(data, primary_params.channels_count);
+        }
+        result
+    })?;
+
+    let mut devices = vec![primary_device];
+    devices.extend(secondary_devices);
+    Ok(devices)
+}
@@ -0,0 +1,106 @@
+//! Identification of the backends and access to their native handles.
+
+use std::fmt::{self, Display, Formatter};
+
+/// The backend that plays the samples, see [`crate::OutputDevice::backend`]. All the variants exist on every
+/// platform, so the value could be matched (or logged) without `cfg` attributes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Backend {
+    /// ALSA (Linux).
+    Alsa,
+
+    /// JACK (Linux, `jack` feature).
+    Jack,
+
+    /// OSS (FreeBSD, `oss` feature).
+    Oss,
+
+    /// sndio (OpenBSD, `sndio` feature).
+    Sndio,
+
+    /// DirectSound (Windows).
+    DirectSound,
+
+    /// CoreAudio (macOS, iOS).
+    CoreAudio,
+
+    /// AAudio (Android).
+    AAudio,
+
+    /// WebAudio (WebAssembly).
+    WebAudio,
+
+    /// No backend, the samples are rendered on demand (see [`crate::run_output_device_manual`]).
+    Manual,
+}
+
+impl Display for Backend {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Backend::Alsa => "ALSA",
+            Backend::Jack => "JACK",
+            Backend::Oss => "OSS",
+            Backend::Sndio => "sndio",
+            Backend::DirectSound => "DirectSound",
+            Backend::CoreAudio => "CoreAudio",
+            Backend::AAudio => "AAudio",
+            Backend::WebAudio => "WebAudio",
+            Backend::Manual => "manual",
+        })
+    }
+}
+
+/// A native handle of the backend that plays the samples, see [`crate::OutputDevice::native_handle`]. The
+/// variants depend on the target platform.
+#[non_exhaustive]
+pub enum NativeHandle<'a> {
+    /// ALSA playback device (Linux).
+    #[cfg(target_os = "linux")]
+    Alsa(*mut alsa_sys::snd_pcm_t),
+
+    /// JACK client (Linux, `jack` feature).
+    #[cfg(all(target_os = "linux", feature = "jack"))]
+    Jack(&'a jack::Client),
+
+    /// DirectSound device (Windows).
+    #[cfg(target_os = "windows")]
+    DirectSound(*mut winapi::um::dsound::IDirectSound),
+
+    /// CoreAudio output queue (macOS, iOS).
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    CoreAudio(coreaudio_sys::AudioQueueRef),
+
+    /// WebAudio context (WebAssembly).
+    #[cfg(all(target_os = "unknown", target_arch = "wasm32"))]
+    Web(&'a web_sys::AudioContext),
+
+    /// The device is closed, or its backend does not expose its handle (AAudio, OSS and sndio).
+    Unavailable,
+
+    // Not every platform has a borrowed handle, but the lifetime must be used anyway.
+    #[doc(hidden)]
+    _Lifetime(std::marker::PhantomData<&'a ()>),
+}
+
+// The borrowed objects are not required to implement `Debug`, only the raw pointers are printed.
+impl fmt::Debug for NativeHandle<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(target_os = "linux")]
+            NativeHandle::Alsa(pcm) => f.debug_tuple("Alsa").field(pcm).finish(),
+            #[cfg(all(target_os = "linux", feature = "jack"))]
+            NativeHandle::Jack(_) => f.write_str("Jack(..)"),
+            #[cfg(target_os = "windows")]
+            NativeHandle::DirectSound(direct_sound) => {
+                f.debug_tuple("DirectSound").field(direct_sound).finish()
+            }
+            #[cfg(any(target_os = "macos", target_os = "ios"))]
+            NativeHandle::CoreAudio(queue) => f.debug_tuple("CoreAudio").field(queue).finish(),
+            #[cfg(all(target_os = "unknown", target_arch = "wasm32"))]
+            NativeHandle::Web(_) => f.write_str("Web(..)"),
+            NativeHandle::Unavailable => f.write_str("Unavailable"),
+            NativeHandle::_Lifetime(_) => f.write_str("_Lifetime"),
+        }
+    }
+}
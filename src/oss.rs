@@ -0,0 +1,192 @@
+//! FreeBSD output device via `OSS` (Open Sound System).
+
+#![cfg(all(target_os = "freebsd", feature = "oss"))]
+
+use crate::{
+    convert::Dither, AudioOutputDevice, Backend, BaseAudioOutputDevice, CallbackResult,
+    OutputDeviceParameters, TinyAudioError,
+};
+use std::{
+    error::Error,
+    fs::{File, OpenOptions},
+    io::Write,
+    os::{
+        raw::{c_int, c_ulong},
+        unix::io::AsRawFd,
+    },
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+};
+
+// `ioctl` requests from `sys/soundcard.h`, they are not exposed by the `libc` crate.
+const SNDCTL_DSP_SYNC: c_ulong = 0x2000_5001;
+const SNDCTL_DSP_SPEED: c_ulong = 0xC004_5002;
+const SNDCTL_DSP_SETFMT: c_ulong = 0xC004_5005;
+const SNDCTL_DSP_CHANNELS: c_ulong = 0xC004_5006;
+const SNDCTL_DSP_SETFRAGMENT: c_ulong = 0xC004_500A;
+
+// 16-bit signed samples in native byte order (`AFMT_S16_LE` or `AFMT_S16_BE`).
+#[cfg(target_endian = "little")]
+const AFMT_S16_NE: c_int = 0x0000_0010;
+#[cfg(target_endian = "big")]
+const AFMT_S16_NE: c_int = 0x0000_0020;
+
+pub struct OssOutputDevice {
+    thread_handle: Option<JoinHandle<()>>,
+    is_running: Arc<AtomicBool>,
+}
+
+/// Sends the `request` to the device, the driver may replace the `value` with the closest supported one.
+fn ioctl(file: &File, request: c_ulong, value: &mut c_int) -> Result<(), Box<dyn Error>> {
+    if unsafe { libc::ioctl(file.as_raw_fd(), request as _, value as *mut c_int) } < 0 {
+        Err(format!("OSS: {}", std::io::Error::last_os_error()).into())
+    } else {
+        Ok(())
+    }
+}
+
+impl BaseAudioOutputDevice for OssOutputDevice {
+    fn is_playing(&self) -> bool {
+        self.is_running.load(Ordering::SeqCst)
+    }
+
+    fn backend(&self) -> Backend {
+        Backend::Oss
+    }
+}
+
+impl AudioOutputDevice for OssOutputDevice {
+    fn new<C>(params: OutputDeviceParameters, data_callback: C) -> Result<Self, Box<dyn Error>>
+    where
+        C: FnMut(&mut [f32], Option<f64>) -> CallbackResult + Send + 'static,
+        Self: Sized,
+    {
+        let device = OpenOptions::new()
+            .write(true)
+            .open("/dev/dsp")
+            .map_err(|err| TinyAudioError::DeviceUnavailable(format!("OSS: {}", err)))?;
+
+        // Two fragments of (at least) the size of the data buffer, so the latency is the same as with
+        // the other backends. The driver is free to ignore this, so the failure is not fatal.
+        let buffer_size_bytes = params.total_samples() * 2;
+        let mut fragment =
+            (2 << 16) | buffer_size_bytes.next_power_of_two().trailing_zeros() as c_int;
+        let _ = ioctl(&device, SNDCTL_DSP_SETFRAGMENT, &mut fragment);
+
+        // The order of these requests is important, some drivers are unable to change the format after the
+        // sample rate was set.
+        let mut format = AFMT_S16_NE;
+        ioctl(&device, SNDCTL_DSP_SETFMT, &mut format)?;
+        if format != AFMT_S16_NE {
+            return Err("OSS: the device does not support 16-bit samples".into());
+        }
+
+        let mut channels_count = params.channels_count as c_int;
+        ioctl(&device, SNDCTL_DSP_CHANNELS, &mut channels_count)?;
+        if channels_count as usize != params.channels_count {
+            return Err(format!(
+                "OSS: the device does not support {} channels",
+                params.channels_count
+            )
+            .into());
+        }
+
+        let mut sample_rate = params.sample_rate as c_int;
+        ioctl(&device, SNDCTL_DSP_SPEED, &mut sample_rate)?;
+        // The driver picks the closest rate it supports, but the callback renders at the requested one.
+        if sample_rate as usize != params.sample_rate {
+            return Err(TinyAudioError::InvalidParameters(format!(
+                "`sample_rate` is {} Hz, but the OSS device supports only {} Hz",
+                params.sample_rate, sample_rate
+            ))
+            .into());
+        }
+
+        let is_running = Arc::new(AtomicBool::new(true));
+
+        let thread_handle = DataSender {
+            device,
+            callback: data_callback,
+            data_buffer: vec![0.0f32; params.total_samples()],
+            output_buffer: vec![0i16; params.total_samples()],
+            dither: Dither::new(params.dither, params.limiter),
+            is_running: is_running.clone(),
+        }
+        .run_in_thread(params.thread_name("OssDataSender"))?;
+
+        Ok(Self {
+            thread_handle: Some(thread_handle),
+            is_running,
+        })
+    }
+}
+
+impl Drop for OssOutputDevice {
+    fn drop(&mut self) {
+        self.is_running.store(false, Ordering::SeqCst);
+
+        // The thread could have panicked in the data callback, the device must be released anyway.
+        if let Some(thread_handle) = self.thread_handle.take() {
+            let _ = thread_handle.join();
+        }
+    }
+}
+
+struct DataSender<C> {
+    // Closed when the feed thread exits.
+    device: File,
+    callback: C,
+    data_buffer: Vec<f32>,
+    output_buffer: Vec<i16>,
+    dither: Dither,
+    is_running: Arc<AtomicBool>,
+}
+
+impl<C> Drop for DataSender<C> {
+    fn drop(&mut self) {
+        // The feed thread has exited (normally or because of a panic in the data callback), let the
+        // device know that it no longer plays anything.
+        self.is_running.store(false, Ordering::SeqCst);
+    }
+}
+
+impl<C> DataSender<C>
+where
+    C: FnMut(&mut [f32], Option<f64>) -> CallbackResult + Send + 'static,
+{
+    pub fn run_in_thread(mut self, thread_name: String) -> Result<JoinHandle<()>, Box<dyn Error>> {
+        Ok(std::thread::Builder::new()
+            .name(thread_name)
+            .spawn(move || self.run_send_loop())?)
+    }
+
+    pub fn run_send_loop(&mut self) {
+        while self.is_running.load(Ordering::SeqCst) {
+            let result = (self.callback)(&mut self.data_buffer, None);
+
+            self.dither
+                .convert(&self.data_buffer, &mut self.output_buffer);
+
+            let bytes = unsafe {
+                std::slice::from_raw_parts(
+                    self.output_buffer.as_ptr() as *const u8,
+                    self.output_buffer.len() * std::mem::size_of::<i16>(),
+                )
+            };
+            // Blocks until the device has enough free space, this is what paces the loop.
+            if let Err(err) = self.device.write_all(bytes) {
+                eprintln!("OSS: unable to write the samples - {}", err);
+                break;
+            }
+
+            if result == CallbackResult::Stop {
+                // Wait until the last portion of samples is played and exit.
+                let _ = ioctl(&self.device, SNDCTL_DSP_SYNC, &mut 0);
+                break;
+            }
+        }
+    }
+}
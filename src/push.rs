@@ -0,0 +1,161 @@
+//! Push-style output, where the samples are written to the device instead of being pulled by the data
+//! callback.
+
+use crate::{run_output_device, OutputDevice, OutputDeviceParameters};
+use std::{
+    error::Error,
+    fmt,
+    sync::{
+        atomic::{AtomicU32, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+/// Single-producer single-consumer lock-free ring buffer of samples. Samples are stored as bits of `f32` in
+/// atomics, so no locks or unsafe code are needed.
+pub(crate) struct RingBuffer {
+    samples: Box<[AtomicU32]>,
+    // Both positions are increasing monotonically (with wrapping) and the capacity is a power of two, so
+    // the amount of samples in the buffer is always `write_position - read_position`.
+    read_position: AtomicUsize,
+    write_position: AtomicUsize,
+}
+
+impl RingBuffer {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            samples: (0..capacity.next_power_of_two())
+                .map(|_| AtomicU32::new(0))
+                .collect(),
+            read_position: AtomicUsize::new(0),
+            write_position: AtomicUsize::new(0),
+        }
+    }
+
+    fn index(&self, position: usize) -> usize {
+        position & (self.samples.len() - 1)
+    }
+
+    pub(crate) fn write(&self, data: &[f32]) -> usize {
+        self.write_samples(data, 1)
+    }
+
+    pub(crate) fn write_frames(&self, data: &[f32], channels_count: usize) -> usize {
+        self.write_samples(data, channels_count)
+    }
+
+    // Writes as many samples as fit, in whole groups of `granularity` samples.
+    fn write_samples(&self, data: &[f32], granularity: usize) -> usize {
+        let write_position = self.write_position.load(Ordering::Relaxed);
+        let read_position = self.read_position.load(Ordering::Acquire);
+        let free = self.samples.len() - write_position.wrapping_sub(read_position);
+        // The capacity is not necessarily a multiple of the channel count, a partially written frame would
+        // swap the channels of every frame after it.
+        let count = data.len().min(free) / granularity * granularity;
+        for (i, sample) in data[..count].iter().enumerate() {
+            self.samples[self.index(write_position.wrapping_add(i))]
+                .store(sample.to_bits(), Ordering::Relaxed);
+        }
+        self.write_position
+            .store(write_position.wrapping_add(count), Ordering::Release);
+        count
+    }
+
+    pub(crate) fn read_frames(&self, data: &mut [f32], channels_count: usize) {
+        let read_position = self.read_position.load(Ordering::Relaxed);
+        let write_position = self.write_position.load(Ordering::Acquire);
+        let available = write_position.wrapping_sub(read_position);
+        // Read whole frames only, otherwise the channels will be swapped after an underflow.
+        let count = data.len().min(available) / channels_count * channels_count;
+        for (i, sample) in data[..count].iter_mut().enumerate() {
+            *sample = f32::from_bits(
+                self.samples[self.index(read_position.wrapping_add(i))].load(Ordering::Relaxed),
+            );
+        }
+        self.read_position
+            .store(read_position.wrapping_add(count), Ordering::Release);
+    }
+}
+
+/// A sink that sends the samples to the output device created by [`run_output_device_push`].
+pub struct AudioSink {
+    ring_buffer: Arc<RingBuffer>,
+}
+
+impl fmt::Debug for AudioSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AudioSink")
+            .field("capacity", &self.capacity())
+            .finish()
+    }
+}
+
+impl AudioSink {
+    /// Pushes the interleaved samples to the device. The samples are queued in an internal lock-free ring
+    /// buffer, so this method never blocks. Returns the amount of samples that were actually queued, it is
+    /// less than the length of `data` if the buffer is full. Try to push the rest of samples later.
+    pub fn write(&mut self, data: &[f32]) -> usize {
+        self.ring_buffer.write(data)
+    }
+
+    /// Returns the maximum amount of samples that could be queued at once.
+    pub fn capacity(&self) -> usize {
+        self.ring_buffer.samples.len()
+    }
+}
+
+/// Creates a new output device, that plays the samples pushed to the returned [`AudioSink`]. It is an
+/// alternative to the data callback of [`run_output_device`], that is useful when the samples are produced
+/// in irregular bursts (for example, when decoding network packets). The sink can queue at least four
+/// device buffers, the device plays silence when there's not enough samples in the queue.
+///
+/// ## Examples
+///
+/// ```rust,no_run
+/// # use tinyaudio::prelude::*;
+/// let params = OutputDeviceParameters::default();
+///
+/// let (_device, mut sink) = run_output_device_push(params).unwrap();
+///
+/// // Push one second of silence.
+/// let samples = vec![0.0; params.sample_rate * params.channels_count];
+/// let mut written = 0;
+/// while written < samples.len() {
+///     written += sink.write(&samples[written..]);
+///     std::thread::sleep(std::time::Duration::from_millis(10));
+/// }
+/// ```
+pub fn run_output_device_push(
+    params: OutputDeviceParameters,
+) -> Result<(OutputDevice, AudioSink), Box<dyn Error>> {
+    let params = params.resolve_channel_sample_count();
+    let ring_buffer = Arc::new(RingBuffer::new(4 * params.total_samples()));
+    let device = run_output_device(params, {
+        let ring_buffer = ring_buffer.clone();
+        move |data| ring_buffer.read_frames(data, params.channels_count)
+    })?;
+    Ok((device, AudioSink { ring_buffer }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RingBuffer;
+
+    #[test]
+    fn test_write_frames_never_splits_a_frame() {
+        // Capacity of eight samples does not hold two 5.1 frames.
+        let ring_buffer = RingBuffer::new(8);
+        let frame = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        assert_eq!(ring_buffer.write_frames(&frame, 6), 6);
+        assert_eq!(ring_buffer.write_frames(&frame, 6), 0);
+
+        let mut data = [0.0; 6];
+        ring_buffer.read_frames(&mut data, 6);
+        assert_eq!(data, frame);
+
+        // The next frame starts at the frame boundary again.
+        assert_eq!(ring_buffer.write_frames(&[7.0; 6], 6), 6);
+        ring_buffer.read_frames(&mut data, 6);
+        assert_eq!(data, [7.0; 6]);
+    }
+}
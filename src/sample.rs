@@ -0,0 +1,97 @@
+//! Data callbacks that produce samples of other types than `f32`.
+
+use crate::{
+    run_output_device, IntoCallbackResult, MaybeSend, OutputDevice, OutputDeviceParameters,
+};
+use std::error::Error;
+
+/// A type of the samples produced by the data callback of [`run_output_device_typed`]. Implemented for
+/// `f32`, `f64`, `i16` and `u8`, could be implemented for other types too.
+pub trait Sample: Copy + MaybeSend + 'static {
+    /// The value that means silence, the buffer is filled with it before each call of the data callback.
+    const SILENCE: Self;
+
+    /// Converts the sample to `f32` in `[-1.0, 1.0]` range.
+    fn to_f32(self) -> f32;
+}
+
+impl Sample for f32 {
+    const SILENCE: Self = 0.0;
+
+    fn to_f32(self) -> f32 {
+        self
+    }
+}
+
+impl Sample for f64 {
+    const SILENCE: Self = 0.0;
+
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+}
+
+impl Sample for i16 {
+    const SILENCE: Self = 0;
+
+    fn to_f32(self) -> f32 {
+        self as f32 / i16::MAX as f32
+    }
+}
+
+impl Sample for u8 {
+    // Unsigned 8-bit samples are centered around 128.
+    const SILENCE: Self = 128;
+
+    fn to_f32(self) -> f32 {
+        (self as f32 - 128.0) / 128.0
+    }
+}
+
+/// The same as [`run_output_device`], but the data callback produces samples of the given [`Sample`] type.
+/// Useful when the samples are already decoded to integers (for example, 16-bit PCM from a WAV file), so
+/// they don't need to be converted by the callback.
+///
+/// This is a convenience, not an optimization: the samples are not passed to the device as is, but are
+/// converted to `f32` (see [`Sample::to_f32`]) and then to the format of the device by the backend, even if
+/// the formats match. The round trip is lossless, so the backends that send 16-bit integers to the device get
+/// exactly the same `i16` samples back, unless [`OutputDeviceParameters::dither`] or
+/// [`OutputDeviceParameters::limiter`] is enabled.
+///
+/// ## Examples
+///
+/// ```rust,no_run
+/// # use tinyaudio::prelude::*;
+/// let params = OutputDeviceParameters::default();
+///
+/// let _device = run_output_device_typed(params, |data: &mut [i16]| {
+///     for sample in data {
+///         // Output silence.
+///         *sample = 0;
+///     }
+/// })
+/// .unwrap();
+///
+/// std::thread::sleep(std::time::Duration::from_secs(1));
+/// ```
+pub fn run_output_device_typed<S, C, R>(
+    params: OutputDeviceParameters,
+    mut data_callback: C,
+) -> Result<OutputDevice, Box<dyn Error>>
+where
+    S: Sample,
+    C: FnMut(&mut [S]) -> R + MaybeSend + 'static,
+    R: IntoCallbackResult,
+{
+    let mut samples = Vec::new();
+    run_output_device(params, move |data| {
+        // The size of the buffer changes only if the device was reconfigured, so it allocates only once.
+        samples.clear();
+        samples.resize(data.len(), S::SILENCE);
+        let result = data_callback(&mut samples);
+        for (out_sample, in_sample) in data.iter_mut().zip(samples.iter()) {
+            *out_sample = in_sample.to_f32();
+        }
+        result
+    })
+}
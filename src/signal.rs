@@ -0,0 +1,61 @@
+//! Simple test signals (`signal` feature), each generator returns a closure that could be passed to
+//! [`crate::run_output_device`] as is.
+//!
+//! ## Examples
+//!
+//! ```rust,no_run
+//! # use tinyaudio::{prelude::*, signal};
+//! let params = OutputDeviceParameters::default();
+//!
+//! let _device = run_output_device(params, signal::sine(440.0, params)).unwrap();
+//!
+//! std::thread::sleep(std::time::Duration::from_secs(5));
+//! ```
+
+#![cfg(feature = "signal")]
+
+use crate::OutputDeviceParameters;
+
+/// Peak amplitude of the generated signals, `-6 dBFS`, so the test signals are not played at full scale.
+const AMPLITUDE: f32 = 0.5;
+
+/// Returns a data callback that plays a sine wave of the given `frequency` (in Hz) on all the channels of
+/// the device created with `params`. The phase is kept between the buffers, so the wave is continuous.
+pub fn sine(
+    frequency: f32,
+    params: OutputDeviceParameters,
+) -> impl FnMut(&mut [f32]) + Send + 'static {
+    // The phase is accumulated in `f64`, so the pitch doesn't drift on long runs.
+    let step = frequency as f64 / params.sample_rate as f64;
+    let mut phase = 0.0f64;
+    move |data| {
+        for frame in data.chunks_mut(params.channels_count) {
+            let value = AMPLITUDE * (phase * std::f64::consts::TAU).sin() as f32;
+            frame.fill(value);
+            phase = (phase + step).fract();
+        }
+    }
+}
+
+/// Returns a data callback that plays uniformly distributed white noise, every channel gets its own
+/// noise. The generator is deterministic, so the noise is the same on each run.
+pub fn white_noise() -> impl FnMut(&mut [f32]) + Send + 'static {
+    // State of the xorshift generator, never zero.
+    let mut state = 0x9e37_79b9u32;
+    move |data| {
+        for sample in data {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            // The upper 24 bits fit into the mantissa of `f32` exactly.
+            let uniform = (state >> 8) as f32 / (1 << 24) as f32;
+            *sample = AMPLITUDE * (2.0 * uniform - 1.0);
+        }
+    }
+}
+
+/// Returns a data callback that plays silence. The buffer is filled with silence before each call, so the
+/// callback does nothing, it is useful to keep the device running.
+pub fn silence() -> impl FnMut(&mut [f32]) + Send + 'static {
+    |_| {}
+}
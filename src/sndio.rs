@@ -0,0 +1,249 @@
+//! OpenBSD output device via `sndio`.
+
+#![cfg(all(target_os = "openbsd", feature = "sndio"))]
+
+use crate::{
+    convert::Dither, AudioOutputDevice, Backend, BaseAudioOutputDevice, CallbackResult,
+    OutputDeviceParameters, TinyAudioError,
+};
+use std::{
+    error::Error,
+    os::raw::{c_char, c_int, c_uint, c_void},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+};
+
+// Bindings to the subset of `sndio.h` that is needed to play the samples.
+#[allow(non_camel_case_types)]
+#[repr(C)]
+struct sio_hdl {
+    _private: [u8; 0],
+}
+
+#[allow(non_camel_case_types, dead_code)]
+#[repr(C)]
+struct sio_par {
+    bits: c_uint,
+    bps: c_uint,
+    sig: c_uint,
+    le: c_uint,
+    msb: c_uint,
+    rchan: c_uint,
+    pchan: c_uint,
+    rate: c_uint,
+    bufsz: c_uint,
+    xrun: c_uint,
+    round: c_uint,
+    appbufsz: c_uint,
+    __pad: [c_int; 3],
+    __magic: c_uint,
+}
+
+const SIO_PLAY: c_uint = 1;
+const SIO_DEVANY: &[u8] = b"default\0";
+#[cfg(target_endian = "little")]
+const SIO_LE_NATIVE: c_uint = 1;
+#[cfg(target_endian = "big")]
+const SIO_LE_NATIVE: c_uint = 0;
+
+#[link(name = "sndio")]
+extern "C" {
+    fn sio_open(name: *const c_char, mode: c_uint, nbio_flag: c_int) -> *mut sio_hdl;
+    fn sio_close(hdl: *mut sio_hdl);
+    fn sio_initpar(par: *mut sio_par);
+    fn sio_setpar(hdl: *mut sio_hdl, par: *mut sio_par) -> c_int;
+    fn sio_getpar(hdl: *mut sio_hdl, par: *mut sio_par) -> c_int;
+    fn sio_start(hdl: *mut sio_hdl) -> c_int;
+    fn sio_stop(hdl: *mut sio_hdl) -> c_int;
+    fn sio_write(hdl: *mut sio_hdl, addr: *const c_void, nbytes: usize) -> usize;
+}
+
+/// An opened, but not yet configured `sndio` device.
+pub struct SndioHandle(*mut sio_hdl);
+
+impl Drop for SndioHandle {
+    fn drop(&mut self) {
+        unsafe {
+            sio_close(self.0);
+        }
+    }
+}
+
+/// Opens the default `sndio` device. The error means that the `sndiod` server is not running (or the
+/// device is busy), so it could be used to fall back to some other backend.
+pub fn open_handle() -> Result<SndioHandle, TinyAudioError> {
+    let hdl = unsafe { sio_open(SIO_DEVANY.as_ptr() as *const c_char, SIO_PLAY, 0) };
+    if hdl.is_null() {
+        Err(TinyAudioError::DeviceUnavailable(
+            "sndio: unable to open the default device".to_string(),
+        ))
+    } else {
+        Ok(SndioHandle(hdl))
+    }
+}
+
+pub struct SndioOutputDevice {
+    // Must be closed after the feed thread has exited.
+    _handle: SndioHandle,
+    thread_handle: Option<JoinHandle<()>>,
+    is_running: Arc<AtomicBool>,
+}
+
+unsafe impl Send for SndioOutputDevice {}
+
+impl BaseAudioOutputDevice for SndioOutputDevice {
+    fn is_playing(&self) -> bool {
+        self.is_running.load(Ordering::SeqCst)
+    }
+
+    fn backend(&self) -> Backend {
+        Backend::Sndio
+    }
+}
+
+impl SndioOutputDevice {
+    pub fn start<C>(
+        handle: SndioHandle,
+        params: OutputDeviceParameters,
+        data_callback: C,
+    ) -> Result<Self, Box<dyn Error>>
+    where
+        C: FnMut(&mut [f32], Option<f64>) -> CallbackResult + Send + 'static,
+    {
+        unsafe {
+            let mut par = std::mem::zeroed::<sio_par>();
+            sio_initpar(&mut par);
+            par.bits = 16;
+            par.sig = 1;
+            par.le = SIO_LE_NATIVE;
+            par.pchan = params.channels_count as c_uint;
+            par.rate = params.sample_rate as c_uint;
+            // Ensure double buffering is possible.
+            par.appbufsz = 2 * params.channel_sample_count as c_uint;
+            if sio_setpar(handle.0, &mut par) == 0 || sio_getpar(handle.0, &mut par) == 0 {
+                return Err("sndio: unable to configure the device".into());
+            }
+
+            // The server may pick the closest supported configuration, but the callback renders the
+            // samples in the requested format.
+            if par.bits != 16 || par.sig != 1 || par.le != SIO_LE_NATIVE {
+                return Err("sndio: the device does not support 16-bit samples".into());
+            }
+            if par.pchan as usize != params.channels_count {
+                return Err(format!(
+                    "sndio: the device does not support {} channels",
+                    params.channels_count
+                )
+                .into());
+            }
+            if par.rate as usize != params.sample_rate {
+                return Err(Box::new(TinyAudioError::InvalidParameters(format!(
+                    "`sample_rate` is {} Hz, but the sndio device supports only {} Hz",
+                    params.sample_rate, par.rate
+                ))));
+            }
+
+            if sio_start(handle.0) == 0 {
+                return Err("sndio: unable to start the device".into());
+            }
+        }
+
+        let is_running = Arc::new(AtomicBool::new(true));
+
+        let thread_handle = DataSender {
+            hdl: handle.0,
+            callback: data_callback,
+            data_buffer: vec![0.0f32; params.total_samples()],
+            output_buffer: vec![0i16; params.total_samples()],
+            dither: Dither::new(params.dither, params.limiter),
+            is_running: is_running.clone(),
+        }
+        .run_in_thread(params.thread_name("SndioDataSender"))?;
+
+        Ok(Self {
+            _handle: handle,
+            thread_handle: Some(thread_handle),
+            is_running,
+        })
+    }
+}
+
+impl AudioOutputDevice for SndioOutputDevice {
+    fn new<C>(params: OutputDeviceParameters, data_callback: C) -> Result<Self, Box<dyn Error>>
+    where
+        C: FnMut(&mut [f32], Option<f64>) -> CallbackResult + Send + 'static,
+        Self: Sized,
+    {
+        Self::start(open_handle()?, params, data_callback)
+    }
+}
+
+impl Drop for SndioOutputDevice {
+    fn drop(&mut self) {
+        self.is_running.store(false, Ordering::SeqCst);
+
+        // The thread could have panicked in the data callback, the device must be released anyway.
+        if let Some(thread_handle) = self.thread_handle.take() {
+            let _ = thread_handle.join();
+        }
+    }
+}
+
+struct DataSender<C> {
+    hdl: *mut sio_hdl,
+    callback: C,
+    data_buffer: Vec<f32>,
+    output_buffer: Vec<i16>,
+    dither: Dither,
+    is_running: Arc<AtomicBool>,
+}
+
+unsafe impl<C> Send for DataSender<C> {}
+
+impl<C> Drop for DataSender<C> {
+    fn drop(&mut self) {
+        // The feed thread has exited (normally or because of a panic in the data callback), let the
+        // device know that it no longer plays anything.
+        self.is_running.store(false, Ordering::SeqCst);
+    }
+}
+
+impl<C> DataSender<C>
+where
+    C: FnMut(&mut [f32], Option<f64>) -> CallbackResult + Send + 'static,
+{
+    pub fn run_in_thread(mut self, thread_name: String) -> Result<JoinHandle<()>, Box<dyn Error>> {
+        Ok(std::thread::Builder::new()
+            .name(thread_name)
+            .spawn(move || self.run_send_loop())?)
+    }
+
+    pub fn run_send_loop(&mut self) {
+        while self.is_running.load(Ordering::SeqCst) {
+            let result = (self.callback)(&mut self.data_buffer, None);
+
+            self.dither
+                .convert(&self.data_buffer, &mut self.output_buffer);
+
+            // Blocks until the device has enough free space, this is what paces the loop.
+            let size = self.output_buffer.len() * std::mem::size_of::<i16>();
+            let written =
+                unsafe { sio_write(self.hdl, self.output_buffer.as_ptr() as *const c_void, size) };
+            if written != size {
+                eprintln!("sndio: unable to write the samples, the device was lost");
+                break;
+            }
+
+            if result == CallbackResult::Stop {
+                // Waits until the last portion of samples is played.
+                unsafe {
+                    sio_stop(self.hdl);
+                }
+                break;
+            }
+        }
+    }
+}
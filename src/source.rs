@@ -0,0 +1,56 @@
+//! Playback of the samples pulled from an iterator, without a hand-written data callback.
+
+use crate::{run_output_device, CallbackResult, MaybeSend, OutputDevice, OutputDeviceParameters};
+use std::error::Error;
+
+/// Creates a new output device, that plays the interleaved samples yielded by the `source`. Each buffer of
+/// the device is filled with exactly as many samples as it needs, the device stops (see
+/// [`OutputDevice::is_playing`]) once the source is exhausted, the rest of the last buffer is silent. Useful
+/// for playing a decoded PCM stream or a pre-rendered sound, the source could be any iterator (including
+/// `Box<dyn Iterator<Item = f32> + Send>`).
+///
+/// ## Examples
+///
+/// The following example plays a 440 Hz sine wave for two seconds.
+///
+/// ```rust,no_run
+/// # use tinyaudio::prelude::*;
+/// let params = OutputDeviceParameters::default();
+///
+/// let frame_count = 2 * params.sample_rate;
+/// let source = (0..frame_count).flat_map(move |frame| {
+///     let value =
+///         (frame as f32 * 440.0 * 2.0 * std::f32::consts::PI / params.sample_rate as f32).sin();
+///     std::iter::repeat(value).take(params.channels_count)
+/// });
+///
+/// let device = run_output_device_from_source(params, source).unwrap();
+/// while device.is_playing() {
+///     std::thread::sleep(std::time::Duration::from_millis(10));
+/// }
+/// ```
+pub fn run_output_device_from_source<S>(
+    params: OutputDeviceParameters,
+    source: S,
+) -> Result<OutputDevice, Box<dyn Error>>
+where
+    S: IntoIterator<Item = f32>,
+    S::IntoIter: MaybeSend + 'static,
+{
+    // Peeking lets the device stop right after the last sample, instead of playing one more silent buffer.
+    let mut source = source.into_iter().peekable();
+    run_output_device(params, move |data| {
+        for sample in data.iter_mut() {
+            match source.next() {
+                Some(value) => *sample = value,
+                None => break,
+            }
+        }
+
+        if source.peek().is_none() {
+            CallbackResult::Stop
+        } else {
+            CallbackResult::Continue
+        }
+    })
+}
@@ -0,0 +1,105 @@
+//! Scheduling priority and CPU affinity of the feed threads.
+
+#![cfg(any(target_os = "linux", target_os = "windows"))]
+
+use crate::ThreadPriority;
+
+/// Realtime priority of the feed thread on Linux. It is deliberately moderate: below the threaded interrupt
+/// handlers of the kernel (50) and the realtime threads of the sound servers, and within the default limit of
+/// RTKit (20), so a busy feed thread can't starve the system or the audio stack it depends on.
+#[cfg(target_os = "linux")]
+const REALTIME_PRIORITY: i32 = 20;
+
+/// Tries to set the priority of the calling thread. Failure is not fatal, the thread just keeps running at
+/// the default priority, so only a warning is printed.
+#[cfg(target_os = "linux")]
+pub fn set_current_thread_priority(priority: ThreadPriority) {
+    let policy = match priority {
+        ThreadPriority::Fifo => libc::SCHED_FIFO,
+        ThreadPriority::RoundRobin => libc::SCHED_RR,
+    };
+
+    unsafe {
+        let param = libc::sched_param {
+            sched_priority: REALTIME_PRIORITY
+                .max(libc::sched_get_priority_min(policy))
+                .min(libc::sched_get_priority_max(policy)),
+        };
+        let err = libc::pthread_setschedparam(libc::pthread_self(), policy, &param);
+        if err != 0 {
+            eprintln!(
+                "tinyaudio: unable to set {:?} priority of the feed thread (error code {}), \
+                the default priority will be used.",
+                priority, err
+            );
+        }
+    }
+}
+
+/// Tries to pin the calling thread to the CPU `core`. Failure is not fatal, the thread just keeps running on
+/// any core, so only a warning is printed.
+#[cfg(target_os = "linux")]
+pub fn set_current_thread_affinity(core: usize) {
+    unsafe {
+        let mut cpu_set = std::mem::zeroed::<libc::cpu_set_t>();
+        libc::CPU_ZERO(&mut cpu_set);
+        let err = if core < libc::CPU_SETSIZE as usize {
+            libc::CPU_SET(core, &mut cpu_set);
+            libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &cpu_set)
+        } else {
+            -1
+        };
+        if err != 0 {
+            eprintln!(
+                "tinyaudio: unable to pin the feed thread to the CPU core {} ({}), \
+                the thread will run on any core.",
+                core,
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+}
+
+/// Tries to set the priority of the calling thread. Failure is not fatal, the thread just keeps running at
+/// the default priority, so only a warning is printed.
+#[cfg(target_os = "windows")]
+pub fn set_current_thread_priority(priority: ThreadPriority) {
+    use winapi::um::{
+        errhandlingapi::GetLastError,
+        processthreadsapi::{GetCurrentThread, SetThreadPriority},
+        winbase::THREAD_PRIORITY_TIME_CRITICAL,
+    };
+
+    unsafe {
+        if SetThreadPriority(GetCurrentThread(), THREAD_PRIORITY_TIME_CRITICAL as i32) == 0 {
+            eprintln!(
+                "tinyaudio: unable to set {:?} priority of the feed thread (error code {}), \
+                the default priority will be used.",
+                priority,
+                GetLastError()
+            );
+        }
+    }
+}
+
+/// Tries to pin the calling thread to the CPU `core`. Failure is not fatal, the thread just keeps running on
+/// any core, so only a warning is printed.
+#[cfg(target_os = "windows")]
+pub fn set_current_thread_affinity(core: usize) {
+    use winapi::um::{
+        errhandlingapi::GetLastError, processthreadsapi::GetCurrentThread,
+        winbase::SetThreadAffinityMask,
+    };
+
+    unsafe {
+        let mask = 1usize.checked_shl(core as u32).unwrap_or(0);
+        if mask == 0 || SetThreadAffinityMask(GetCurrentThread(), mask) == 0 {
+            eprintln!(
+                "tinyaudio: unable to pin the feed thread to the CPU core {} (error code {}), \
+                the thread will run on any core.",
+                core,
+                GetLastError()
+            );
+        }
+    }
+}
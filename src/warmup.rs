@@ -0,0 +1,151 @@
+//! Prerendering of the first buffers, see [`crate::OutputDeviceParameters::warmup_buffers`].
+
+use crate::CallbackResult;
+use std::collections::VecDeque;
+
+/// A queue of the samples rendered ahead of the device. The data callback renders a few blocks before the
+/// stream starts, then each portion of samples requested by the device is taken from the front of the queue
+/// while the same amount of samples is rendered to its back. So the queue delays the samples by a constant
+/// amount of frames.
+pub struct WarmupQueue {
+    samples: VecDeque<f32>,
+    scratch: Vec<f32>,
+    channels_count: usize,
+    block_frame_count: usize,
+    // Amount of blocks that are not prerendered yet.
+    pending_block_count: usize,
+    // The callback has asked to stop, the queue contains the last portion of samples.
+    is_last_block: bool,
+}
+
+impl WarmupQueue {
+    pub fn new(channels_count: usize, block_frame_count: usize, block_count: usize) -> Self {
+        Self {
+            samples: VecDeque::with_capacity(
+                (block_count + 1) * block_frame_count * channels_count,
+            ),
+            scratch: Vec::new(),
+            channels_count,
+            block_frame_count,
+            pending_block_count: block_count,
+            is_last_block: false,
+        }
+    }
+
+    /// Renders the blocks that are not prerendered yet, `callback` gets the block and the amount of frames
+    /// queued before it. Does nothing if the blocks were rendered already.
+    pub fn prerender<C>(&mut self, mut callback: C)
+    where
+        C: FnMut(&mut [f32], usize) -> CallbackResult,
+    {
+        while self.pending_block_count > 0 && !self.is_last_block {
+            self.pending_block_count -= 1;
+            self.render(self.block_frame_count * self.channels_count, &mut callback);
+        }
+        self.pending_block_count = 0;
+    }
+
+    fn render<C>(&mut self, sample_count: usize, callback: &mut C)
+    where
+        C: FnMut(&mut [f32], usize) -> CallbackResult,
+    {
+        self.scratch.clear();
+        self.scratch.resize(sample_count, 0.0);
+        let queued_frames = self.samples.len() / self.channels_count;
+        if callback(&mut self.scratch, queued_frames) == CallbackResult::Stop {
+            self.is_last_block = true;
+        }
+        self.samples.extend(self.scratch.iter());
+    }
+
+    /// Renders as many samples as the interleaved `data` holds to the back of the queue and fills `data`
+    /// from its front. Once the callback has asked to stop, the queue is drained and the rest of `data` is
+    /// silent. Returns [`CallbackResult::Stop`] when the last queued samples are in `data`.
+    pub fn fill<C>(&mut self, data: &mut [f32], mut callback: C) -> CallbackResult
+    where
+        C: FnMut(&mut [f32], usize) -> CallbackResult,
+    {
+        self.prerender(&mut callback);
+        if !self.is_last_block {
+            self.render(data.len(), &mut callback);
+        }
+
+        for sample in data.iter_mut() {
+            *sample = self.samples.pop_front().unwrap_or(0.0);
+        }
+
+        if self.is_last_block && self.samples.is_empty() {
+            CallbackResult::Stop
+        } else {
+            CallbackResult::Continue
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{render, OutputDeviceParameters};
+
+    #[test]
+    fn test_samples_keep_their_order() {
+        // Mono blocks of 4 frames numbered from 1, the callback stops after the fifth block.
+        let mut queue = WarmupQueue::new(1, 4, 2);
+        let mut sample_number = 0.0;
+        let mut queued_frames = Vec::new();
+        let mut callback = |block: &mut [f32], queued: usize| {
+            queued_frames.push(queued);
+            for sample in block.iter_mut() {
+                sample_number += 1.0;
+                *sample = sample_number;
+            }
+            if sample_number == 20.0 {
+                CallbackResult::Stop
+            } else {
+                CallbackResult::Continue
+            }
+        };
+
+        queue.prerender(&mut callback);
+        let mut output = Vec::new();
+        let mut data = [0.0; 4];
+        loop {
+            let result = queue.fill(&mut data, &mut callback);
+            output.extend_from_slice(&data);
+            if result == CallbackResult::Stop {
+                break;
+            }
+        }
+
+        // Each block is rendered after the ones that are queued already.
+        assert_eq!(queued_frames, [0, 4, 8, 8, 8]);
+        assert!(output
+            .iter()
+            .copied()
+            .eq((1..=20).map(|number| number as f32)));
+    }
+
+    #[test]
+    fn test_first_samples_are_prerendered() {
+        let params = OutputDeviceParameters {
+            channels_count: 2,
+            channel_sample_count: 16,
+            warmup_buffers: 2,
+            ..Default::default()
+        };
+        let mut buffer_number = 0.0;
+        let samples = render(
+            params,
+            move |data| {
+                buffer_number += 1.0;
+                data.fill(buffer_number);
+            },
+            3,
+        )
+        .unwrap();
+        // The device starts with the samples of the first callback instead of silence or garbage.
+        for (index, buffer) in samples.chunks_exact(params.total_samples()).enumerate() {
+            assert!(buffer.iter().all(|&sample| sample == (index + 1) as f32));
+        }
+    }
+}
@@ -3,13 +3,26 @@
 #![cfg(all(target_os = "unknown", target_arch = "wasm32"))]
 #![allow(deprecated)]
 
-use crate::{AudioOutputDevice, BaseAudioOutputDevice, OutputDeviceParameters};
+use crate::{
+    device::COMMON_SAMPLE_RATES, AudioOutputDevice, Backend, BaseAudioOutputDevice, CallbackResult,
+    DeviceInfo, MaybeSend, NativeHandle, OutputDeviceParameters, TinyAudioError, WebLatencyHint,
+    WebOutput,
+};
+use js_sys::{Array, Function, Promise, Reflect};
 use std::{
+    cell::RefCell,
     error::Error,
-    sync::{Arc, Mutex, RwLock},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock,
+    },
 };
 use wasm_bindgen::{closure::Closure, JsCast, JsValue};
-use web_sys::{AudioBuffer, AudioContext, AudioContextOptions};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{
+    AudioBuffer, AudioBufferSourceNode, AudioContext, AudioContextOptions, AudioContextState,
+    AudioNode, EventTarget, MediaStream, MediaStreamAudioDestinationNode,
+};
 
 type OnEndedClosure = Arc<RwLock<Option<Closure<dyn FnMut()>>>>;
 
@@ -17,47 +30,312 @@ fn convert_err(err_object: JsValue) -> Box<dyn Error> {
     format!("WebAudio error occurred: {:?}", err_object).into()
 }
 
+pub fn supported_sample_rates() -> Vec<usize> {
+    // Every implementation must support at least this range of sample rates, according to the spec.
+    COMMON_SAMPLE_RATES
+        .iter()
+        .copied()
+        .filter(|rate| (8000..=96000).contains(rate))
+        .collect()
+}
+
+fn get_property(object: &JsValue, name: &str) -> Option<JsValue> {
+    Reflect::get(object, &JsValue::from_str(name))
+        .ok()
+        .filter(|value| !value.is_undefined() && !value.is_null())
+}
+
+/// Enumerates the audio outputs via `navigator.mediaDevices.enumerateDevices`. The names of the devices are
+/// empty until the page is allowed to access the media devices, the ids are used instead then.
+pub async fn output_devices() -> Result<Vec<DeviceInfo>, TinyAudioError> {
+    // The global object is used instead of `window`, so the devices could be enumerated in a worker too.
+    let media_devices = get_property(&js_sys::global(), "navigator")
+        .and_then(|navigator| get_property(&navigator, "mediaDevices"))
+        .ok_or_else(|| {
+            TinyAudioError::DeviceUnavailable(
+                "`navigator.mediaDevices` is not supported by this browser".to_string(),
+            )
+        })?;
+    let enumerate_devices = get_property(&media_devices, "enumerateDevices")
+        .and_then(|function| function.dyn_into::<Function>().ok())
+        .ok_or_else(|| {
+            TinyAudioError::DeviceUnavailable(
+                "`enumerateDevices` is not supported by this browser".to_string(),
+            )
+        })?;
+
+    let enumeration_err = |err: JsValue| {
+        TinyAudioError::BackendError(format!("Failed to enumerate the devices: {:?}", err))
+    };
+    let promise: Promise = enumerate_devices
+        .call0(&media_devices)
+        .map_err(enumeration_err)?
+        .unchecked_into();
+    let devices: Array = JsFuture::from(promise)
+        .await
+        .map_err(enumeration_err)?
+        .unchecked_into();
+
+    Ok(devices
+        .iter()
+        .filter(|device| {
+            get_property(device, "kind")
+                .and_then(|kind| kind.as_string())
+                .as_deref()
+                == Some("audiooutput")
+        })
+        .map(|device| {
+            let id = get_property(&device, "deviceId")
+                .and_then(|id| id.as_string())
+                .unwrap_or_default();
+            let name = get_property(&device, "label")
+                .and_then(|label| label.as_string())
+                .filter(|label| !label.is_empty())
+                .unwrap_or_else(|| id.clone());
+            // Chromium reports the default output as a separate device with `default` id, the empty id means
+            // the default output for `setSinkId`.
+            let is_default = id.is_empty() || id == "default";
+            DeviceInfo::new(id, name, is_default, None, None)
+        })
+        .collect())
+}
+
+/// Routes the context to the output with the given id (see [`output_devices`]) via `AudioContext.setSinkId`.
+/// The switch is asynchronous, its failures are reported to the console.
+pub fn set_sink_id(audio_context: &AudioContext, id: &str) -> Result<(), TinyAudioError> {
+    let set_sink_id = get_property(audio_context, "setSinkId")
+        .and_then(|function| function.dyn_into::<Function>().ok())
+        .ok_or_else(|| {
+            TinyAudioError::DeviceUnavailable(
+                "`AudioContext.setSinkId` is not supported by this browser".to_string(),
+            )
+        })?;
+
+    let promise: Promise = set_sink_id
+        .call1(audio_context, &JsValue::from_str(id))
+        .map_err(|err| TinyAudioError::BackendError(format!("Failed to `setSinkId`: {:?}", err)))?
+        .unchecked_into();
+    wasm_bindgen_futures::spawn_local(async move {
+        if let Err(err) = JsFuture::from(promise).await {
+            web_sys::console::warn_1(
+                &format!("WebAudio: unable to switch the output device: {:?}", err).into(),
+            );
+        }
+    });
+    Ok(())
+}
+
 fn create_audio_context(
     params: &OutputDeviceParameters,
 ) -> Result<Arc<AudioContext>, Box<dyn Error>> {
+    let latency_hint = JsValue::from_str(match params.web.latency_hint {
+        WebLatencyHint::Interactive => "interactive",
+        WebLatencyHint::Balanced => "balanced",
+        WebLatencyHint::Playback => "playback",
+    });
     let mut options = AudioContextOptions::new();
+    options.latency_hint(&latency_hint);
 
-    options.sample_rate(params.sample_rate as f32);
+    // The context runs at its preferred sample rate otherwise, the samples are resampled then.
+    if !params.web.native_sample_rate {
+        options.sample_rate(params.sample_rate as f32);
+    }
 
-    let audio_context = AudioContext::new_with_context_options(&options).map_err(convert_err)?;
+    let audio_context = match AudioContext::new_with_context_options(&options) {
+        Ok(audio_context) => audio_context,
+        // Browsers throw `NotSupportedError` if the sample rate is out of their range, the context is opened
+        // at its preferred sample rate then and the samples are resampled.
+        Err(err)
+            if !params.web.native_sample_rate
+                && get_property(&err, "name").and_then(|name| name.as_string())
+                    == Some("NotSupportedError".to_string()) =>
+        {
+            let mut options = AudioContextOptions::new();
+            options.latency_hint(&latency_hint);
+            AudioContext::new_with_context_options(&options).map_err(|default_err| {
+                format!(
+                    "Failed to create an audio context at {} Hz ({:?}) and at the default sample rate \
+                    ({:?})",
+                    params.sample_rate, err, default_err
+                )
+            })?
+        }
+        Err(err) => return Err(convert_err(err)),
+    };
 
     Ok(Arc::new(audio_context))
 }
 
+thread_local! {
+    // The context shared by the devices created with `WebParameters::shared_context`, along with the amount
+    // of such devices. The `onended` closures hold clones of the context, so the strong count of the `Arc`
+    // cannot tell whether the context is still used.
+    static SHARED_CONTEXT: RefCell<Option<(Arc<AudioContext>, usize)>> = RefCell::new(None);
+}
+
+/// Returns the shared context, it is created with the given parameters if there's none (or it was closed by
+/// the browser). Each call must be paired with [`release_shared_context`].
+fn acquire_shared_context(
+    params: &OutputDeviceParameters,
+) -> Result<Arc<AudioContext>, Box<dyn Error>> {
+    SHARED_CONTEXT.with(|shared| {
+        let mut shared = shared.borrow_mut();
+        if let Some((audio_context, user_count)) = shared.as_mut() {
+            if audio_context.state() != AudioContextState::Closed {
+                *user_count += 1;
+                return Ok(audio_context.clone());
+            }
+        }
+        let audio_context = create_audio_context(params)?;
+        *shared = Some((audio_context.clone(), 1));
+        Ok(audio_context)
+    })
+}
+
+/// Releases the shared context, returns `true` if the context is no longer used by any device and should
+/// be closed.
+fn release_shared_context(audio_context: &Arc<AudioContext>) -> bool {
+    SHARED_CONTEXT.with(|shared| {
+        let mut shared = shared.borrow_mut();
+        let is_last_user = match shared.as_mut() {
+            Some((shared_context, user_count)) if Arc::ptr_eq(shared_context, audio_context) => {
+                *user_count -= 1;
+                *user_count == 0
+            }
+            // The context was replaced by a new one, because the browser has closed it.
+            _ => return true,
+        };
+        if is_last_user {
+            *shared = None;
+        }
+        is_last_user
+    })
+}
+
 fn create_buffer(
     audio_context: &AudioContext,
-    params: &OutputDeviceParameters,
+    channels_count: usize,
+    frame_count: usize,
 ) -> Result<AudioBuffer, Box<dyn Error>> {
     Ok(audio_context
         .create_buffer(
-            params.channels_count as u32,
-            params.channel_sample_count as u32,
-            params.sample_rate as f32,
+            channels_count as u32,
+            frame_count as u32,
+            audio_context.sample_rate(),
         )
         .map_err(convert_err)?)
 }
 
+/// Converts the samples rendered at the requested sample rate to the sample rate of the context by linear
+/// interpolation. The blocks of the data callback are rendered on demand, so the size of the buffers of the
+/// context is independent of `channel_sample_count`.
+struct Resampler {
+    channels_count: usize,
+    // Amount of input frames per output frame.
+    step: f64,
+    block: Vec<f32>,
+    // The last frame of the previous block, it is interpolated with the first frame of the current one.
+    previous_frame: Vec<f32>,
+    // Position (in frames) in the current block, `-1.0..0.0` is between the previous frame and the block.
+    position: f64,
+    // The callback has asked to stop, the block contains the last portion of samples.
+    is_last_block: bool,
+    is_drained: bool,
+}
+
+impl Resampler {
+    fn new(params: &OutputDeviceParameters, device_sample_rate: usize) -> Self {
+        Self {
+            channels_count: params.channels_count,
+            step: params.sample_rate as f64 / device_sample_rate as f64,
+            block: vec![0.0; params.total_samples()],
+            previous_frame: vec![0.0; params.channels_count],
+            // Force the first frame to render a new block.
+            position: params.channel_sample_count as f64,
+            is_last_block: false,
+            is_drained: false,
+        }
+    }
+
+    /// Fills the interleaved `output` with the resampled blocks rendered by `render`. Returns
+    /// [`CallbackResult::Stop`] once the last block was consumed, the rest of the output is silent then.
+    fn fill<R>(&mut self, output: &mut [f32], mut render: R) -> CallbackResult
+    where
+        R: FnMut(&mut [f32]) -> CallbackResult,
+    {
+        let channels_count = self.channels_count;
+        let block_frame_count = self.block.len() / channels_count;
+
+        for frame in output.chunks_exact_mut(channels_count) {
+            // Both frames around the position must be available.
+            while !self.is_drained && self.position >= (block_frame_count - 1) as f64 {
+                if self.is_last_block {
+                    self.is_drained = true;
+                    break;
+                }
+
+                self.previous_frame
+                    .copy_from_slice(&self.block[(block_frame_count - 1) * channels_count..]);
+                if render(&mut self.block) == CallbackResult::Stop {
+                    self.is_last_block = true;
+                }
+                self.position -= block_frame_count as f64;
+            }
+
+            if self.is_drained {
+                frame.fill(0.0);
+                continue;
+            }
+
+            let index = self.position.floor();
+            let fraction = (self.position - index) as f32;
+            let index = index as isize;
+            for (channel_index, sample) in frame.iter_mut().enumerate() {
+                let left = if index < 0 {
+                    self.previous_frame[channel_index]
+                } else {
+                    self.block[index as usize * channels_count + channel_index]
+                };
+                let right = self.block[(index + 1) as usize * channels_count + channel_index];
+                *sample = left + (right - left) * fraction;
+            }
+
+            self.position += self.step;
+        }
+
+        if self.is_drained {
+            CallbackResult::Stop
+        } else {
+            CallbackResult::Continue
+        }
+    }
+}
+
+/// Splits the interleaved samples into a planar buffer laid out like so: `[ch0 samples...][ch1 samples...]`.
+/// The planar buffer must have the same length as the interleaved one.
+fn deinterleave(
+    channels_count: usize,
+    interleaved_data_buffer: &[f32],
+    planar_samples: &mut [f32],
+) {
+    crate::deinterleave_planar(interleaved_data_buffer, channels_count, planar_samples);
+}
+
 #[cfg(not(target_feature = "atomics"))]
 fn write_samples(
     buffer: &AudioBuffer,
     channels_count: usize,
     interleaved_data_buffer: &[f32],
-    temp_samples: &mut Vec<f32>,
-) {
-    for channel_index in 0..channels_count {
-        temp_samples.clear();
-        for samples in interleaved_data_buffer.chunks(channels_count) {
-            temp_samples.push(samples[channel_index]);
-        }
-        buffer
-            .copy_to_channel(&temp_samples, channel_index as i32)
-            .unwrap();
+    planar_samples: &mut [f32],
+) -> Result<(), JsValue> {
+    deinterleave(channels_count, interleaved_data_buffer, planar_samples);
+    for (channel_index, channel_samples) in planar_samples
+        .chunks_exact(interleaved_data_buffer.len() / channels_count)
+        .enumerate()
+    {
+        buffer.copy_to_channel(channel_samples, channel_index as i32)?;
     }
+    Ok(())
 }
 
 #[cfg(target_feature = "atomics")]
@@ -86,25 +364,23 @@ mod atomics {
         buffer: &AudioBuffer,
         channels_count: usize,
         interleaved_data_buffer: &[f32],
-        temp_samples: &mut Vec<f32>,
+        planar_samples: &mut [f32],
         temporary_channel_array_view: &js_sys::Float32Array,
-    ) {
-        for channel_index in 0..channels_count {
-            // Copy channel samples from the interleaved buffer into the temporary one.
-            temp_samples.clear();
-            for samples in interleaved_data_buffer.chunks(channels_count) {
-                temp_samples.push(samples[channel_index]);
-            }
-
+    ) -> Result<(), JsValue> {
+        super::deinterleave(channels_count, interleaved_data_buffer, planar_samples);
+        for (channel_index, channel_samples) in planar_samples
+            .chunks_exact(interleaved_data_buffer.len() / channels_count)
+            .enumerate()
+        {
             // Do another clone to temporary JS buffer.
-            temporary_channel_array_view.copy_from(temp_samples);
+            temporary_channel_array_view.copy_from(channel_samples);
 
             // Copy samples from this temporary buffer to the channel buffer.
             buffer
                 .unchecked_ref::<ArrayAudioBuffer>()
-                .copy_to_channel(&temporary_channel_array_view, channel_index as i32)
-                .unwrap();
+                .copy_to_channel(&temporary_channel_array_view, channel_index as i32)?;
         }
+        Ok(())
     }
 
     pub fn make_temp_js_buffer(channel_sample_count: usize) -> js_sys::Float32Array {
@@ -116,72 +392,286 @@ mod atomics {
 
 fn create_buffer_source(
     audio_context: &AudioContext,
+    destinations: &[AudioNode],
     buffer: &AudioBuffer,
     start_time: f64,
     onended_closure: &OnEndedClosure,
-) {
-    let source = audio_context.create_buffer_source().unwrap();
+) -> Result<(), Box<dyn Error>> {
+    let source = audio_context.create_buffer_source().map_err(convert_err)?;
     source.set_buffer(Some(&buffer));
-    source
-        .connect_with_audio_node(&audio_context.destination())
-        .unwrap();
-    source.set_onended(Some(
-        onended_closure
-            .read()
-            .unwrap()
-            .as_ref()
-            .unwrap()
-            .as_ref()
-            .unchecked_ref(),
-    ));
-    source.start_with_when(start_time).unwrap();
+    for destination in destinations {
+        source
+            .connect_with_audio_node(destination)
+            .map_err(convert_err)?;
+    }
+    match onended_closure.read() {
+        Ok(onended_closure) => match onended_closure.as_ref() {
+            Some(onended_closure) => {
+                source.set_onended(Some(onended_closure.as_ref().unchecked_ref()))
+            }
+            None => return Err("The feed loop was released".into()),
+        },
+        Err(_) => return Err("The feed loop was poisoned".into()),
+    }
+    source.start_with_when(start_time).map_err(convert_err)?;
+    Ok(())
+}
+
+/// Keeps the context busy with a looping silent source and restarts the playback when the page becomes
+/// visible again. See `WebParameters::keep_alive`.
+struct KeepAlive {
+    source: AudioBufferSourceNode,
+    // The document and the listener of its `visibilitychange` event, there's no document in a worker.
+    visibility_listener: Option<(EventTarget, Closure<dyn FnMut()>)>,
+}
+
+impl KeepAlive {
+    fn new(
+        audio_context: &Arc<AudioContext>,
+        seeds: Vec<Function>,
+        time: Arc<RwLock<f64>>,
+        is_stopping: Arc<AtomicBool>,
+        buffer_duration_secs: f64,
+    ) -> Result<Self, Box<dyn Error>> {
+        // A single frame of silence played in a loop.
+        let buffer = audio_context
+            .create_buffer(1, 1, audio_context.sample_rate())
+            .map_err(convert_err)?;
+        let source = audio_context.create_buffer_source().map_err(convert_err)?;
+        source.set_buffer(Some(&buffer));
+        source.set_loop(true);
+        source
+            .connect_with_audio_node(&audio_context.destination())
+            .map_err(convert_err)?;
+        source.start().map_err(convert_err)?;
+
+        let visibility_listener = match get_property(&js_sys::global(), "document") {
+            Some(document) => {
+                let audio_context = audio_context.clone();
+                let document_clone = document.clone();
+                let listener = Closure::wrap(Box::new(move || {
+                    let is_visible = get_property(&document_clone, "visibilityState")
+                        .and_then(|state| state.as_string())
+                        .as_deref()
+                        == Some("visible");
+                    if !is_visible || is_stopping.load(Ordering::SeqCst) {
+                        return;
+                    }
+
+                    // Mobile Safari suspends (or interrupts) the context in the background.
+                    if audio_context.state() != AudioContextState::Running {
+                        let _ = audio_context.resume();
+                    }
+
+                    // The scheduled buffers have ended without scheduling the next ones, the feed loops
+                    // are restarted.
+                    if audio_context.current_time() > *time.read().unwrap() + buffer_duration_secs {
+                        for seed in seeds.iter() {
+                            let _ = seed.call0(&JsValue::NULL);
+                        }
+                    }
+                }) as Box<dyn FnMut()>);
+
+                let target: EventTarget = document.unchecked_into();
+                target
+                    .add_event_listener_with_callback(
+                        "visibilitychange",
+                        listener.as_ref().unchecked_ref(),
+                    )
+                    .map_err(convert_err)?;
+                Some((target, listener))
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            source,
+            visibility_listener,
+        })
+    }
+}
+
+impl Drop for KeepAlive {
+    fn drop(&mut self) {
+        let _ = self.source.stop();
+        if let Some((target, listener)) = self.visibility_listener.as_ref() {
+            let _ = target.remove_event_listener_with_callback(
+                "visibilitychange",
+                listener.as_ref().unchecked_ref(),
+            );
+        }
+    }
 }
 
 pub struct WebAudioDevice {
     audio_context: Arc<AudioContext>,
+    // The context is shared with other devices, see `WebParameters::shared_context`.
+    is_shared: bool,
+    // Set once the feed loop has stopped, the context itself may still run for the other devices.
+    has_ended: Arc<AtomicBool>,
+    // The node that turns the samples into a stream, see `WebOutput::MediaStream`.
+    media_stream_destination: Option<MediaStreamAudioDestinationNode>,
+    // Shared with the feed loop, set when the callback has asked to stop or when the device is dropped.
+    is_stopping: Arc<AtomicBool>,
+    // Dropped after the context is closed.
+    _keep_alive: Option<KeepAlive>,
 }
 
-impl BaseAudioOutputDevice for WebAudioDevice {}
+impl BaseAudioOutputDevice for WebAudioDevice {
+    fn is_playing(&self) -> bool {
+        !self.has_ended.load(Ordering::SeqCst)
+            && self.audio_context.state() == AudioContextState::Running
+    }
+
+    fn backend(&self) -> Backend {
+        Backend::WebAudio
+    }
+
+    fn native_handle(&self) -> NativeHandle<'_> {
+        NativeHandle::Web(&self.audio_context)
+    }
+
+    fn device_sample_rate(&self) -> Option<usize> {
+        Some(self.audio_context.sample_rate() as usize)
+    }
+
+    fn media_stream(&self) -> Option<MediaStream> {
+        self.media_stream_destination
+            .as_ref()
+            .map(|destination| destination.stream())
+    }
+}
 
 unsafe impl Send for WebAudioDevice {}
 
 impl AudioOutputDevice for WebAudioDevice {
     fn new<C>(params: OutputDeviceParameters, data_callback: C) -> Result<Self, Box<dyn Error>>
     where
-        C: FnMut(&mut [f32]) + Send + 'static,
+        C: FnMut(&mut [f32], Option<f64>) -> CallbackResult + MaybeSend + 'static,
         Self: Sized,
     {
-        let window = web_sys::window().ok_or_else(|| "Failed to fetch main window.")?;
-        let audio_context = create_audio_context(&params)?;
-        let callback = Arc::new(Mutex::new(data_callback));
+        let is_shared = params.web.shared_context;
+        let audio_context = if is_shared {
+            acquire_shared_context(&params)?
+        } else {
+            create_audio_context(&params)?
+        };
+        // Constructed right away, so the shared context is released if the device fails to start.
+        let mut device = Self {
+            audio_context: audio_context.clone(),
+            is_shared,
+            has_ended: Arc::new(AtomicBool::new(false)),
+            is_stopping: Arc::new(AtomicBool::new(false)),
+            media_stream_destination: None,
+            _keep_alive: None,
+        };
+
+        // Each device has its own media stream, even if the context is shared.
+        let mut destinations: Vec<AudioNode> = Vec::new();
+        if params.web.output != WebOutput::MediaStream {
+            destinations.push(audio_context.destination().into());
+        }
+        if params.web.output != WebOutput::Speakers {
+            let media_stream_destination = audio_context
+                .create_media_stream_destination()
+                .map_err(convert_err)?;
+            destinations.push(media_stream_destination.clone().into());
+            device.media_stream_destination = Some(media_stream_destination);
+        }
+
+        // A buffer of the context holds several buffers of the data callback, they are rendered one after
+        // another.
+        let blocks_per_buffer = params.web.blocks_per_buffer.max(1);
+        let block_sample_count = params.total_samples();
+        let block_duration_secs = params.channel_sample_count as f64 / params.sample_rate as f64;
+
+        // The context could run at another sample rate if it was opened at its preferred one, the buffers
+        // of the context have the same duration as the buffers of the data callback then.
+        let device_sample_rate = audio_context.sample_rate() as usize;
+        let (frame_count, mut resampler) = if device_sample_rate == params.sample_rate {
+            (params.channel_sample_count * blocks_per_buffer, None)
+        } else {
+            (
+                (params.channel_sample_count * blocks_per_buffer * device_sample_rate
+                    + params.sample_rate
+                    - 1)
+                    / params.sample_rate,
+                Some(Resampler::new(&params, device_sample_rate)),
+            )
+        };
+        let sample_count = frame_count * params.channels_count;
+
+        let mut data_callback = data_callback;
+        let callback =
+            Arc::new(Mutex::new(
+                move |output: &mut [f32], time: Option<f64>| match resampler.as_mut() {
+                    Some(resampler) => resampler.fill(output, |block| data_callback(block, time)),
+                    None => {
+                        let mut result = CallbackResult::Continue;
+                        for (index, block) in
+                            output.chunks_exact_mut(block_sample_count).enumerate()
+                        {
+                            // The rest of the buffer is silent once the callback has asked to stop.
+                            if result == CallbackResult::Stop {
+                                block.fill(0.0);
+                                continue;
+                            }
+                            let block_time =
+                                time.map(|time| time + index as f64 * block_duration_secs);
+                            result = data_callback(block, block_time);
+                        }
+                        result
+                    }
+                },
+            ));
 
         let time = Arc::new(RwLock::new(0.0f64));
+        // The callback has asked to stop, the feed loop must not schedule new buffers anymore.
+        let is_stopping = device.is_stopping.clone();
 
-        let buffer_duration_secs = params.channel_sample_count as f64 / params.sample_rate as f64;
-        let time_step_ms = (buffer_duration_secs * 1_000.0) as i32;
-        let mut offset_ms = 0;
+        let buffer_duration_secs = frame_count as f64 / device_sample_rate as f64;
+
+        // The functions that start the feed loops, the keep-alive restarts them if needed.
+        let mut seeds = Vec::new();
 
         for _ in 0..2 {
-            let buffer = create_buffer(&audio_context, &params)?;
+            let buffer = create_buffer(&audio_context, params.channels_count, frame_count)?;
 
             let onended_closure: OnEndedClosure = Arc::new(RwLock::new(None));
 
             let audio_context_clone = audio_context.clone();
+            let destinations = destinations.clone();
             let onended_closure_clone = onended_closure.clone();
             let time = time.clone();
             let callback = callback.clone();
+            let is_stopping = is_stopping.clone();
+            let has_ended = device.has_ended.clone();
+            let mut is_last_buffer_scheduled = false;
 
-            let mut interleaved_data_buffer =
-                vec![0.0f32; params.channel_sample_count * params.channels_count];
-            let mut temp_samples = vec![0.0f32; params.channel_sample_count];
+            let mut interleaved_data_buffer = vec![0.0f32; sample_count];
+            let mut planar_samples = vec![0.0f32; sample_count];
 
             #[cfg(target_feature = "atomics")]
-            let temp_js_samples = atomics::make_temp_js_buffer(params.channel_sample_count);
+            let temp_js_samples = atomics::make_temp_js_buffer(frame_count);
 
             onended_closure
                 .write()
                 .unwrap()
                 .replace(Closure::wrap(Box::new(move || {
+                    if is_last_buffer_scheduled {
+                        // The last portion of samples was played, the device reports that it is no longer
+                        // playing. The shared context keeps running for the other devices.
+                        has_ended.store(true, Ordering::SeqCst);
+                        if !is_shared {
+                            let _ = audio_context_clone.suspend();
+                        }
+                        return;
+                    }
+
+                    if is_stopping.load(Ordering::SeqCst) {
+                        return;
+                    }
+
                     let current_time = audio_context_clone.current_time();
                     let raw_time = *time.read().unwrap();
                     let start_time = if raw_time >= current_time {
@@ -190,64 +680,107 @@ impl AudioOutputDevice for WebAudioDevice {
                         current_time
                     };
 
-                    (callback.lock().unwrap())(&mut interleaved_data_buffer);
-
-                    #[cfg(not(target_feature = "atomics"))]
+                    if (callback.lock().unwrap())(&mut interleaved_data_buffer, Some(current_time))
+                        == CallbackResult::Stop
                     {
-                        write_samples(
-                            &buffer,
-                            params.channels_count,
-                            &interleaved_data_buffer,
-                            &mut temp_samples,
-                        );
+                        is_stopping.store(true, Ordering::SeqCst);
+                        is_last_buffer_scheduled = true;
                     }
 
+                    #[cfg(not(target_feature = "atomics"))]
+                    let written = write_samples(
+                        &buffer,
+                        params.channels_count,
+                        &interleaved_data_buffer,
+                        &mut planar_samples,
+                    );
+
                     #[cfg(target_feature = "atomics")]
-                    {
-                        atomics::write_samples(
+                    let written = atomics::write_samples(
+                        &buffer,
+                        params.channels_count,
+                        &interleaved_data_buffer,
+                        &mut planar_samples,
+                        &temp_js_samples,
+                    );
+
+                    let scheduled = written.map_err(convert_err).and_then(|_| {
+                        create_buffer_source(
+                            &audio_context_clone,
+                            &destinations,
                             &buffer,
-                            params.channels_count,
-                            &interleaved_data_buffer,
-                            &mut temp_samples,
-                            &temp_js_samples,
+                            start_time,
+                            &onended_closure_clone,
                         )
+                    });
+                    if let Err(err) = scheduled {
+                        // The feed loop cannot go on, the device stops instead of tearing down the whole
+                        // module with a panic.
+                        web_sys::console::error_1(
+                            &format!("WebAudio: unable to schedule the samples: {}", err).into(),
+                        );
+                        is_stopping.store(true, Ordering::SeqCst);
+                        has_ended.store(true, Ordering::SeqCst);
+                        if !is_shared {
+                            let _ = audio_context_clone.suspend();
+                        }
+                        return;
                     }
 
-                    create_buffer_source(
-                        &audio_context_clone,
-                        &buffer,
-                        start_time,
-                        &onended_closure_clone,
-                    );
-
                     *time.write().unwrap() = start_time + buffer_duration_secs;
                 })));
 
-            // Run closures one after another to run the feed loop.
-            window
-                .set_timeout_with_callback_and_timeout_and_arguments_0(
-                    onended_closure
-                        .read()
-                        .unwrap()
-                        .as_ref()
-                        .unwrap()
-                        .as_ref()
-                        .unchecked_ref(),
-                    offset_ms,
-                )
-                .map_err(convert_err)?;
-
-            offset_ms += time_step_ms;
+            // Seed the feed loop right away. The second loop schedules its first buffer right after the
+            // first one (the shared time has advanced), so the loops alternate without any timers. Timers
+            // (and `window`) are not used, so the device could be created in a Web Worker too.
+            // The closure reads its own lock to schedule the next buffer, so the lock must be released
+            // before the call.
+            let seed = onended_closure
+                .read()
+                .unwrap()
+                .as_ref()
+                .unwrap()
+                .as_ref()
+                .unchecked_ref::<Function>()
+                .clone();
+            seed.call0(&JsValue::NULL).map_err(convert_err)?;
+            seeds.push(seed);
         }
 
+        device._keep_alive = if params.web.keep_alive {
+            Some(KeepAlive::new(
+                &audio_context,
+                seeds,
+                time,
+                is_stopping.clone(),
+                buffer_duration_secs,
+            )?)
+        } else {
+            None
+        };
+
         let _ = audio_context.resume().map_err(convert_err)?;
 
-        Ok(Self { audio_context })
+        Ok(device)
     }
 }
 
 impl Drop for WebAudioDevice {
     fn drop(&mut self) {
-        let _ = self.audio_context.close().unwrap();
+        // The feed loop could have a pending timeout, it must not schedule new buffers anymore.
+        self.is_stopping.store(true, Ordering::SeqCst);
+
+        // The shared context is closed by its last device.
+        let is_unused = !self.is_shared || release_shared_context(&self.audio_context);
+
+        // The context could be closed already (for example, by the browser). Destructors must never panic,
+        // on WebAssembly it tears down the whole module.
+        if is_unused && self.audio_context.state() != AudioContextState::Closed {
+            if let Err(err) = self.audio_context.close() {
+                web_sys::console::warn_1(
+                    &format!("WebAudio: unable to close the audio context: {:?}", err).into(),
+                );
+            }
+        }
     }
 }
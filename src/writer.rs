@@ -0,0 +1,255 @@
+//! Raw output, where the samples are written to any [`std::io::Write`] (a pipe, a socket, a file) instead of
+//! a sound device.
+
+#![cfg(not(all(target_os = "unknown", target_arch = "wasm32")))]
+
+use crate::{
+    convert::Dither, manual::run_output_device_manual, CallbackResult, IntoCallbackResult,
+    OutputDevice, OutputDeviceParameters,
+};
+use std::{
+    error::Error,
+    fmt,
+    io::Write,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::Instant,
+};
+
+/// Format of the samples written by [`run_output_device_to_writer`]. The samples are interleaved, without
+/// any header.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RawSampleFormat {
+    /// 32-bit floating-point samples, little-endian (`f32le` in `ffmpeg` terms).
+    F32Le,
+    /// 32-bit floating-point samples, big-endian (`f32be` in `ffmpeg` terms).
+    F32Be,
+    /// 16-bit signed integer samples, little-endian (`s16le` in `ffmpeg` terms). The samples are converted
+    /// with [`OutputDeviceParameters::dither`] and [`OutputDeviceParameters::limiter`].
+    I16Le,
+    /// 16-bit signed integer samples, big-endian (`s16be` in `ffmpeg` terms). The samples are converted
+    /// with [`OutputDeviceParameters::dither`] and [`OutputDeviceParameters::limiter`].
+    I16Be,
+    /// 8-bit unsigned integer samples centered at 128 (`u8` in `ffmpeg` terms), as used by retro hardware
+    /// and 8-bit WAV files. The samples are rounded, [`OutputDeviceParameters::dither`] is not applied, but
+    /// [`OutputDeviceParameters::limiter`] is. The ALSA and DirectSound devices output this format too, see
+    /// [`OutputDeviceParameters::sample_format`].
+    U8,
+}
+
+impl RawSampleFormat {
+    /// Returns the size of a single sample in bytes.
+    pub fn bytes_per_sample(self) -> usize {
+        match self {
+            RawSampleFormat::F32Le | RawSampleFormat::F32Be => 4,
+            RawSampleFormat::I16Le | RawSampleFormat::I16Be => 2,
+            RawSampleFormat::U8 => 1,
+        }
+    }
+}
+
+/// An opaque "handle" to the device created by [`run_output_device_to_writer`]. The samples are written
+/// while the handle is alive, drop it (or call [`WriterDevice::close`]) to stop.
+#[must_use = "the device stops when the handle is dropped"]
+pub struct WriterDevice {
+    thread_handle: Option<JoinHandle<()>>,
+    is_running: Arc<AtomicBool>,
+}
+
+impl WriterDevice {
+    /// Stops writing the samples and waits until the writer is flushed and dropped. Does nothing if the
+    /// device is already closed.
+    pub fn close(&mut self) {
+        self.is_running.store(false, Ordering::SeqCst);
+
+        // The thread could have panicked in the data callback, the writer must be released anyway.
+        if let Some(thread_handle) = self.thread_handle.take() {
+            let _ = thread_handle.join();
+        }
+    }
+
+    /// Returns `true` if the samples are still written. It returns `false` after the data callback has
+    /// returned [`CallbackResult::Stop`], after the writer has failed or after the device was closed.
+    pub fn is_playing(&self) -> bool {
+        self.is_running.load(Ordering::SeqCst)
+    }
+}
+
+impl fmt::Debug for WriterDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WriterDevice")
+            .field("is_playing", &self.is_playing())
+            .finish()
+    }
+}
+
+impl Drop for WriterDevice {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+struct DataSender<W> {
+    device: OutputDevice,
+    writer: W,
+    format: RawSampleFormat,
+    offline: bool,
+    params: OutputDeviceParameters,
+    data_buffer: Vec<f32>,
+    int_buffer: Vec<i16>,
+    byte_buffer: Vec<u8>,
+    dither: Dither,
+    is_running: Arc<AtomicBool>,
+}
+
+impl<W> Drop for DataSender<W> {
+    fn drop(&mut self) {
+        // The thread has exited (normally or because of a panic in the data callback), let the device know
+        // that it no longer writes anything.
+        self.is_running.store(false, Ordering::SeqCst);
+    }
+}
+
+impl<W> DataSender<W>
+where
+    W: Write + Send + 'static,
+{
+    fn run_in_thread(mut self, thread_name: String) -> Result<JoinHandle<()>, Box<dyn Error>> {
+        Ok(std::thread::Builder::new()
+            .name(thread_name)
+            .spawn(move || self.run_send_loop())?)
+    }
+
+    fn encode(&mut self) {
+        self.byte_buffer.clear();
+        match self.format {
+            RawSampleFormat::F32Le => {
+                for sample in self.data_buffer.iter() {
+                    self.byte_buffer.extend_from_slice(&sample.to_le_bytes());
+                }
+            }
+            RawSampleFormat::F32Be => {
+                for sample in self.data_buffer.iter() {
+                    self.byte_buffer.extend_from_slice(&sample.to_be_bytes());
+                }
+            }
+            RawSampleFormat::I16Le | RawSampleFormat::I16Be => {
+                self.dither.convert(&self.data_buffer, &mut self.int_buffer);
+                let is_little_endian = self.format == RawSampleFormat::I16Le;
+                for sample in self.int_buffer.iter() {
+                    self.byte_buffer.extend_from_slice(&if is_little_endian {
+                        sample.to_le_bytes()
+                    } else {
+                        sample.to_be_bytes()
+                    });
+                }
+            }
+            RawSampleFormat::U8 => {
+                self.byte_buffer.resize(self.data_buffer.len(), 0);
+                self.dither
+                    .convert_u8(&self.data_buffer, &mut self.byte_buffer);
+            }
+        }
+    }
+
+    fn run_send_loop(&mut self) {
+        let mut deadline = Instant::now();
+        while self.is_running.load(Ordering::SeqCst) {
+            let result = self.device.process(&mut self.data_buffer);
+
+            self.encode();
+            if let Err(err) = self.writer.write_all(&self.byte_buffer) {
+                eprintln!("tinyaudio: unable to write the samples - {}", err);
+                break;
+            }
+
+            if result == CallbackResult::Stop {
+                break;
+            }
+
+            // Nothing blocks the loop in real-time mode, so it sleeps for the duration of each buffer.
+            if !self.offline {
+                deadline += self.params.buffer_duration();
+                if let Some(wait) = deadline.checked_duration_since(Instant::now()) {
+                    std::thread::sleep(wait);
+                }
+            }
+        }
+
+        if let Err(err) = self.writer.flush() {
+            eprintln!("tinyaudio: unable to flush the writer - {}", err);
+        }
+    }
+}
+
+/// Creates a device that writes the samples produced by the specified `data_callback` to the `writer` in the
+/// given `format`, instead of playing them. It could be used to pipe the samples to another process (for
+/// example, `ffplay -f f32le -ar 44100 -ac 2 -`), to send them over a socket or to record them to a file.
+/// All the processing of the crate (silence fill, channel mixing, fade-in) is applied, the samples are
+/// written in buffers of `params.channel_sample_count` frames from a separate thread.
+///
+/// If `offline` is `false`, the buffers are written at the real-time cadence of `params.sample_rate`,
+/// otherwise they are written as fast as the callback and the writer allow (the callback should return
+/// [`CallbackResult::Stop`] at some point then). The writer is flushed and dropped when the callback has
+/// asked to stop, when the writer has failed or when the device is closed. `params.thread_priority`,
+/// `params.cpu_affinity` and the platform-specific parameters are ignored. Not available on WebAssembly.
+///
+/// ## Examples
+///
+/// ```rust,no_run
+/// # use tinyaudio::prelude::*;
+/// let child = std::process::Command::new("ffplay")
+///     .args(["-nodisp", "-f", "f32le", "-ar", "44100", "-ac", "2", "-"])
+///     .stdin(std::process::Stdio::piped())
+///     .spawn()
+///     .unwrap();
+///
+/// let _device = run_output_device_to_writer(
+///     OutputDeviceParameters::default(),
+///     child.stdin.unwrap(),
+///     RawSampleFormat::F32Le,
+///     false,
+///     |data| data.fill(0.0),
+/// )
+/// .unwrap();
+///
+/// std::thread::sleep(std::time::Duration::from_secs(5));
+/// ```
+pub fn run_output_device_to_writer<W, C, R>(
+    params: OutputDeviceParameters,
+    writer: W,
+    format: RawSampleFormat,
+    offline: bool,
+    data_callback: C,
+) -> Result<WriterDevice, Box<dyn Error>>
+where
+    W: Write + Send + 'static,
+    C: FnMut(&mut [f32]) -> R + Send + 'static,
+    R: IntoCallbackResult,
+{
+    let device = run_output_device_manual(params, data_callback)?;
+    let params = device.params();
+    let is_running = Arc::new(AtomicBool::new(true));
+
+    let thread_handle = DataSender {
+        device,
+        writer,
+        format,
+        offline,
+        params,
+        data_buffer: vec![0.0; params.total_samples()],
+        int_buffer: vec![0; params.total_samples()],
+        byte_buffer: Vec::with_capacity(params.total_samples() * format.bytes_per_sample()),
+        dither: Dither::new(params.dither, params.limiter),
+        is_running: is_running.clone(),
+    }
+    .run_in_thread(params.thread_name("WriterDataSender"))?;
+
+    Ok(WriterDevice {
+        thread_handle: Some(thread_handle),
+        is_running,
+    })
+}